@@ -0,0 +1,181 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::num::{NonZeroU32, NonZeroU8};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use miette::{miette, Context, IntoDiagnostic};
+use rodio::Source;
+use vorbis_rs::{VorbisBitrateManagementStrategy, VorbisEncoderBuilder};
+
+use crate::transcode;
+
+/// Frames encoded at once
+const BLOCK_FRAMES: usize = 4096;
+/// How far ahead of real time the stream is sent, to absorb network hiccups
+const LEAD: Duration = Duration::from_secs(2);
+
+#[derive(Args)]
+pub struct Opt {
+    /// Files to broadcast, in order
+    #[clap(name = "FILES", required = true)]
+    files: Vec<String>,
+    /// Icecast server, as `host:port`
+    #[clap(long, name = "SERVER")]
+    server: String,
+    /// Mount point of the stream
+    #[clap(long, name = "MOUNT", default_value = "/stream")]
+    mount: String,
+    /// Source user
+    #[clap(long, name = "USER", default_value = "source")]
+    user: String,
+    /// Source password
+    #[clap(long, name = "PASSWORD")]
+    password: String,
+    /// Name of the stream shown by the server
+    #[clap(long, name = "NAME", default_value = "lilac")]
+    name: String,
+    /// Vorbis quality, from -0.1 to 1.0
+    #[clap(short, long, name = "QUALITY", default_value = "0.5")]
+    quality: f32,
+    /// Start over from the first file after the last one
+    #[clap(long = "loop")]
+    repeat: bool,
+}
+
+/// Streams files to an Icecast server as Ogg Vorbis, in real time
+///
+/// Every file is sent as a new chained Ogg stream carrying its own comments,
+/// which is how Icecast picks up metadata updates for Ogg streams.
+pub fn main(opt: Opt) -> crate::Result {
+    let mut stream = connect(&opt)?;
+
+    loop {
+        for file in &opt.files {
+            let (lilac, _) = transcode::decode(file.as_ref())?;
+            status!(
+                "{}",
+                tr!(
+                    "Now broadcasting {} by {} on {}",
+                    lilac.title(),
+                    lilac.artist(),
+                    lilac.album(),
+                )
+            );
+
+            let channels = lilac.channels as usize;
+            let mut encoder = VorbisEncoderBuilder::new(
+                NonZeroU32::new(lilac.sample_rate).ok_or_else(|| miette!("Invalid sample rate"))?,
+                u8::try_from(lilac.channels)
+                    .ok()
+                    .and_then(NonZeroU8::new)
+                    .ok_or_else(|| miette!("Unsupported channel count"))?,
+                &mut stream,
+            )
+            .into_diagnostic()?;
+            for (tag, value) in [
+                ("TITLE", &lilac.title),
+                ("ARTIST", &lilac.artist),
+                ("ALBUM", &lilac.album),
+            ] {
+                if let Some(value) = value {
+                    encoder.comment_tag(tag, value);
+                }
+            }
+            let mut encoder = encoder
+                .bitrate_management_strategy(VorbisBitrateManagementStrategy::QualityVbr {
+                    target_quality: opt.quality,
+                })
+                .build()
+                .into_diagnostic()?;
+
+            let rate = lilac.sample_rate as f64;
+            let mut samples = lilac.source();
+            let mut block = vec![Vec::with_capacity(BLOCK_FRAMES); channels];
+            let start = Instant::now();
+            let mut sent = 0;
+            loop {
+                block.iter_mut().for_each(Vec::clear);
+                for (i, s) in samples.by_ref().take(BLOCK_FRAMES * channels).enumerate() {
+                    block[i % channels].push(s);
+                }
+                if block[0].is_empty() {
+                    break;
+                }
+
+                encoder
+                    .encode_audio_block(&block)
+                    .into_diagnostic()
+                    .wrap_err(tr!("Lost the connection to the server"))?;
+                sent += block[0].len();
+
+                let ahead =
+                    Duration::from_secs_f64(sent as f64 / rate).saturating_sub(start.elapsed());
+                thread::sleep(ahead.saturating_sub(LEAD));
+            }
+            encoder.finish().into_diagnostic()?;
+        }
+
+        if !opt.repeat {
+            break;
+        }
+    }
+
+    crate::OK
+}
+
+/// Opens a source connection to the server
+fn connect(opt: &Opt) -> miette::Result<TcpStream> {
+    let mut stream = TcpStream::connect(&opt.server)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to connect to `{}`", opt.server))?;
+    let credentials = base64(format!("{}:{}", opt.user, opt.password).as_bytes());
+    write!(
+        stream,
+        "PUT {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Authorization: Basic {}\r\n\
+         Content-Type: application/ogg\r\n\
+         Ice-Name: {}\r\n\
+         Ice-Public: 0\r\n\
+         Expect: 100-continue\r\n\
+         \r\n",
+        opt.mount, opt.server, credentials, opt.name,
+    )
+    .into_diagnostic()?;
+
+    let mut status = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut status)
+        .into_diagnostic()?;
+    match status.split_whitespace().nth(1) {
+        Some("100" | "200") => Ok(stream),
+        _ => Err(miette!(
+            "The server refused the stream: {}",
+            status.trim_end()
+        )),
+    }
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+use std::sync::{Condvar, Mutex};
+
+/// Limits the total estimated memory used by concurrently decoded files
+pub struct MemoryBudget {
+    limit: u64,
+    available: Mutex<u64>,
+    freed: Condvar,
+}
+
+pub struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    amount: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            available: Mutex::new(limit),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Blocks until the given amount of memory is available
+    ///
+    /// Amounts over the limit are clamped to it so that large files
+    /// still get decoded, just never alongside anything else.
+    pub fn reserve(&self, amount: u64) -> Reservation<'_> {
+        let amount = amount.min(self.limit);
+        let mut available = self
+            .freed
+            .wait_while(self.available.lock().unwrap(), |a| *a < amount)
+            .unwrap();
+        *available -= amount;
+
+        Reservation {
+            budget: self,
+            amount,
+        }
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.available.lock().unwrap() += self.amount;
+        self.budget.freed.notify_all();
+    }
+}
+
+/// Estimates how much memory decoding a file will take based on its size and format
+pub fn estimate(path: &Path) -> u64 {
+    let size = path.metadata().map(|m| m.len()).unwrap_or(0);
+    // Rough decoded to encoded size ratios, accounting for samples being stored as i32
+    let ratio = match path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .as_deref()
+    {
+        Some("lilac") => 2,
+        Some("wav") => 2,
+        Some("flac") => 4,
+        _ => 24,
+    };
+    size.saturating_mul(ratio)
+}
+
+/// Parses sizes like `512M` or `4G`, in bytes when unitless
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{}`", s))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().trim_end_matches("IB") {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        "T" | "TB" => 1 << 40,
+        _ => return Err(format!("invalid size unit `{}`", unit)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size `{}` is too large", s))
+}
+
+/// Formats a size in bytes with the largest fitting binary unit
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if size < 1 << 10 {
+        return format!("{} B", size);
+    }
+
+    let mut size = size as f64;
+    let mut unit = "B";
+    for u in UNITS {
+        if size < 1024.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = u;
+    }
+    format!("{:.1} {}iB", size, unit)
+}
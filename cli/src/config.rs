@@ -0,0 +1,319 @@
+use std::collections::{BTreeMap, HashMap};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::{env, fs, io};
+
+use lilac::Interpolation;
+use miette::{miette, Context, IntoDiagnostic};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::budget;
+use crate::effect::Effect;
+use crate::hooks::Hooks;
+use crate::keys::Keys;
+use crate::theme::ThemeName;
+use crate::transcode::Target;
+
+/// Environment variable overriding the configuration file location
+const CONFIG_ENV: &str = "LILAC_CONFIG";
+/// Environment variable selecting a profile, when `--profile` isn't given
+const PROFILE_ENV: &str = "LILAC_PROFILE";
+/// Number of recently played songs remembered
+const RECENT_LEN: usize = 50;
+
+/// Configuration file, read from `~/.config/lilac/config.toml` by default
+///
+/// ```toml
+/// theme = "high-contrast"
+///
+/// [player]
+/// volume = 80
+/// device = "USB Audio"
+/// convert = true
+/// shuffle = "smart"
+/// crossfeed = true
+/// resampler = "sinc"
+///
+/// [transcode]
+/// pattern = "%A/%a/%n %T.%E"
+///
+/// [keys]
+/// play-pause = "p"
+///
+/// [hooks]
+/// on-track-start = "notify-send \"$LILAC_TITLE\" \"$LILAC_ARTIST\""
+///
+/// [providers]
+/// discogs-token = "..."
+///
+/// [network]
+/// proxy = "socks5://localhost:9050"
+///
+/// [preset.phone]
+/// to = "wav"
+/// channels = 2
+/// bit-depth = 16
+/// dither = true
+/// effects = ["norm -1"]
+///
+/// [profile.laptop.player]
+/// device = "Speakers"
+///
+/// [profile.laptop.transcode]
+/// jobs = 2
+/// memory-limit = "2G"
+/// ```
+///
+/// Profiles override any part of the rest of the file when selected
+/// with `--profile` or the `LILAC_PROFILE` environment variable,
+/// so that the same file can be shared between machines.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    #[serde(rename = "preset")]
+    pub presets: HashMap<String, Preset>,
+    pub theme: ThemeName,
+    pub player: Player,
+    pub transcode: Transcode,
+    pub keys: Keys,
+    pub hooks: Hooks,
+    pub providers: Providers,
+    pub network: Network,
+}
+
+/// Defaults of the interactive player, overridden by its flags
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Player {
+    /// Initial volume, from 0 to 100, when none was remembered from the last run
+    pub volume: Option<u16>,
+    pub device: Option<String>,
+    /// Convert songs in other formats to LILAC files next to them when they are enqueued,
+    /// so that later plays use those instead
+    pub convert: bool,
+    pub shuffle: ShuffleStyle,
+    /// Start with crossfeed enabled, for headphones
+    pub crossfeed: bool,
+    pub resampler: Resampler,
+}
+
+/// How the interactive player converts songs to the sample rate of the output device
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Resampler {
+    /// Leaves it to rodio and the system mixer
+    Off,
+    Linear,
+    #[default]
+    Cubic,
+    Sinc,
+}
+
+impl Resampler {
+    pub fn interpolation(self) -> Option<Interpolation> {
+        match self {
+            Resampler::Off => None,
+            Resampler::Linear => Some(Interpolation::Linear),
+            Resampler::Cubic => Some(Interpolation::Cubic),
+            Resampler::Sinc => Some(Interpolation::Sinc),
+        }
+    }
+}
+
+/// How songs are shuffled by the interactive player
+#[derive(Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ShuffleStyle {
+    #[default]
+    Random,
+    /// Avoids playing the same artist twice in a row
+    /// and plays recently played songs later on
+    Smart,
+}
+
+/// Settings of the online metadata providers
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Providers {
+    /// Personal access token, which the Discogs search requires
+    pub discogs_token: Option<String>,
+}
+
+/// Network access of the online features
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+#[cfg_attr(not(feature = "online"), allow(dead_code))]
+pub struct Network {
+    /// Proxy for every request, such as `socks5://localhost:9050`
+    pub proxy: Option<String>,
+    /// Only use cached responses, like `--offline`
+    pub offline: bool,
+}
+
+/// Defaults of the transcode subcommand, overridden by its flags and presets
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Transcode {
+    pub pattern: Option<String>,
+    pub jobs: Option<NonZeroUsize>,
+    /// Accepts sizes like 512M or 4G, as `--memory-limit` does
+    #[serde(deserialize_with = "size")]
+    pub memory_limit: Option<u64>,
+}
+
+/// Named set of transcode options
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Preset {
+    pub to: Option<Target>,
+    pub pattern: Option<String>,
+    pub keep: Option<bool>,
+    pub channels: Option<u16>,
+    pub bit_depth: Option<u32>,
+    pub dither: Option<bool>,
+    pub effects: Vec<Effect>,
+}
+
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Selects the profile applied to the configuration, over the environment variable
+pub fn set_profile(name: String) {
+    PROFILE.set(name).ok();
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        match env::var_os(CONFIG_ENV) {
+            Some(p) => Some(PathBuf::from(p)),
+            None => dirs::config_dir().map(|d| d.join("lilac").join("config.toml")),
+        }
+    }
+
+    /// Loads the configuration file, falling back to the defaults if it doesn't exist,
+    /// and applies the selected profile
+    pub fn load() -> miette::Result<Self> {
+        let path = Self::path();
+        let config = match &path {
+            None => String::new(),
+            Some(path) => match fs::read_to_string(path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                r => r
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to read `{}`", path.display()))?,
+            },
+        };
+        let invalid = || {
+            let path = path.as_deref().unwrap_or(Path::new(""));
+            format!("Invalid configuration file `{}`", path.display())
+        };
+
+        let mut config: toml::Table = toml::from_str(&config)
+            .into_diagnostic()
+            .wrap_err_with(invalid)?;
+        let profiles = match config.remove("profile") {
+            Some(toml::Value::Table(profiles)) => profiles,
+            Some(_) => return Err(miette!("`profile` must be a table of profiles")),
+            None => toml::Table::new(),
+        };
+        let profile = PROFILE
+            .get()
+            .cloned()
+            .or_else(|| env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty()));
+        if let Some(name) = profile {
+            match profiles.get(&name) {
+                Some(toml::Value::Table(profile)) => merge(&mut config, profile.clone()),
+                Some(_) => return Err(miette!("Profile `{}` must be a table", name)),
+                None => {
+                    let mut names: Vec<_> = profiles.keys().map(String::as_str).collect();
+                    names.sort_unstable();
+                    return Err(miette!(
+                        "Unknown profile `{}`, available profiles are: {}",
+                        name,
+                        names.join(", ")
+                    ));
+                }
+            }
+        }
+
+        let config: Self = config.try_into().into_diagnostic().wrap_err_with(invalid)?;
+        for (name, preset) in &config.presets {
+            if preset.channels == Some(0) {
+                return Err(miette!("Preset `{}` has zero channels", name));
+            }
+            if preset.bit_depth.is_some_and(|b| !(1..=32).contains(&b)) {
+                return Err(miette!("Preset `{}` has an invalid bit depth", name));
+            }
+        }
+        if config.player.volume.is_some_and(|v| v > 100) {
+            return Err(miette!("The player volume must be between 0 and 100"));
+        }
+        Ok(config)
+    }
+}
+
+/// Overrides the values of a table with those of another, merging nested tables
+fn merge(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(table)), toml::Value::Table(value)) => merge(table, value),
+            (Some(slot), value) => *slot = value,
+            (None, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    let size = String::deserialize(deserializer)?;
+    budget::parse_size(&size)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Values remembered between runs, kept apart from the hand-written configuration
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct State {
+    pub volume: Option<u16>,
+    /// Most recently played songs first
+    pub recent: Vec<PathBuf>,
+    /// Where long songs were left, in seconds
+    pub positions: BTreeMap<PathBuf, f64>,
+}
+
+impl State {
+    fn path() -> Option<PathBuf> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|d| d.join("lilac").join("state.toml"))
+    }
+
+    /// Loads the remembered values, which are simply forgotten if they can't be read
+    pub fn load() -> Self {
+        Self::path()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|s| toml::from_str(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Puts a song at the top of the recently played ones
+    pub fn played(&mut self, path: &Path) {
+        self.recent.retain(|p| p != path);
+        self.recent.insert(0, path.to_owned());
+        self.recent.truncate(RECENT_LEN);
+    }
+
+    pub fn save(&self) -> crate::Result {
+        let Some(path) = Self::path() else {
+            return crate::OK;
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).into_diagnostic()?;
+        }
+        fs::write(&path, toml::to_string(self).into_diagnostic()?)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write `{}`", path.display()))
+    }
+}
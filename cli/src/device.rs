@@ -0,0 +1,78 @@
+use miette::{miette, Context, IntoDiagnostic};
+use rodio::cpal::traits::{DeviceTrait, HostTrait};
+use rodio::{cpal, OutputStream, OutputStreamHandle};
+
+/// Opens the output device with the given name, or the default one
+pub fn open(name: Option<&str>) -> miette::Result<(OutputStream, OutputStreamHandle)> {
+    let Some(name) = name else {
+        return OutputStream::try_default()
+            .into_diagnostic()
+            .context(tr!("No audio output device"));
+    };
+
+    let device = cpal::default_host()
+        .output_devices()
+        .into_diagnostic()?
+        .find(|d| d.name().is_ok_and(|n| n == name))
+        .ok_or_else(|| {
+            miette!(
+                "{}",
+                tr!(
+                    "No audio output device named `{}`, run `lilac devices` to list them",
+                    name
+                )
+            )
+        })?;
+    OutputStream::try_from_device(&device).into_diagnostic()
+}
+
+/// Name of the device opened by [`open`] for the given name
+pub fn resolve(name: Option<&str>) -> Option<String> {
+    match name {
+        Some(name) => Some(name.to_owned()),
+        None => cpal::default_host()
+            .default_output_device()
+            .and_then(|d| d.name().ok()),
+    }
+}
+
+/// Sample rate at which the device opened by [`open`] for the given name plays
+pub fn sample_rate(name: Option<&str>) -> Option<u32> {
+    let host = cpal::default_host();
+    let device = match name {
+        Some(name) => host
+            .output_devices()
+            .ok()?
+            .find(|d| d.name().is_ok_and(|n| n == name))?,
+        None => host.default_output_device()?,
+    };
+    // Like rodio, which opens devices with their default configuration
+    let config = device.default_output_config().ok()?;
+    Some(config.sample_rate().0)
+}
+
+/// Whether an output device with the given name is still available
+///
+/// Errors on the output stream are swallowed by rodio,
+/// so an unplugged device is only noticed by enumerating them again.
+pub fn connected(name: &str) -> bool {
+    cpal::default_host()
+        .output_devices()
+        .is_ok_and(|mut devices| devices.any(|d| d.name().is_ok_and(|n| n == name)))
+}
+
+/// Prints the names of the output devices, marking the default one
+pub fn list() -> crate::Result {
+    let host = cpal::default_host();
+    let default = host.default_output_device().and_then(|d| d.name().ok());
+    for device in host.output_devices().into_diagnostic()? {
+        let name = device.name().into_diagnostic()?;
+        if default.as_ref() == Some(&name) {
+            println!("{}", tr!("{} (default)", name));
+        } else {
+            println!("{}", name);
+        }
+    }
+
+    crate::OK
+}
@@ -0,0 +1,129 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use lilac::Lilac;
+use serde::{Deserialize, Deserializer};
+
+/// SoX-style effect applied during transcoding
+#[derive(Clone, Debug)]
+pub enum Effect {
+    /// `trim START [LENGTH]`
+    Trim(Duration, Option<Duration>),
+    /// `fade IN [STOP [OUT]]`
+    Fade {
+        fade_in: Duration,
+        stop: Option<Duration>,
+        fade_out: Duration,
+    },
+    /// `gain DB`
+    Gain(f64),
+    /// `norm [DB]`
+    Norm(f64),
+    /// `channels N`
+    Channels(u16),
+    /// `crossfeed`
+    Crossfeed,
+}
+
+impl Effect {
+    pub fn apply(&self, mut lilac: Lilac) -> Lilac {
+        match *self {
+            Effect::Trim(start, length) => {
+                let end = length.map_or(Duration::MAX, |l| start.saturating_add(l));
+                lilac.trim(start..end);
+            }
+            Effect::Fade {
+                fade_in,
+                stop,
+                fade_out,
+            } => {
+                if let Some(stop) = stop {
+                    lilac.trim(Duration::ZERO..stop);
+                }
+                lilac.fade_in(fade_in);
+                if stop.is_some() {
+                    lilac.fade_out(fade_out);
+                }
+            }
+            Effect::Gain(db) => lilac.amplify(db),
+            Effect::Norm(db) => lilac.normalize(db),
+            Effect::Channels(c) => lilac = lilac.remix(c),
+            Effect::Crossfeed => lilac.crossfeed(),
+        }
+        lilac
+    }
+}
+
+impl FromStr for Effect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut args = s.split_whitespace();
+        let name = args.next().ok_or_else(|| "empty effect".to_owned())?;
+        let args: Vec<&str> = args.collect();
+
+        let time = |i: usize| args.get(i).map(|a| crate::parse_duration(a)).transpose();
+        let db = |i: usize| {
+            args.get(i)
+                .map(|a| {
+                    a.parse::<f64>()
+                        .map_err(|_| format!("invalid gain `{}`", a))
+                })
+                .transpose()
+        };
+        let arity = |range: std::ops::RangeInclusive<usize>| {
+            if range.contains(&args.len()) {
+                Ok(())
+            } else {
+                Err(format!("wrong number of arguments for `{}`", name))
+            }
+        };
+
+        match name {
+            "trim" => {
+                arity(1..=2)?;
+                Ok(Effect::Trim(time(0)?.unwrap_or_default(), time(1)?))
+            }
+            "fade" => {
+                arity(1..=3)?;
+                let fade_in = time(0)?.unwrap_or_default();
+                // Like SoX, a stop position of 0 means the end of the audio
+                let stop = time(1)?.map(|s| if s.is_zero() { Duration::MAX } else { s });
+                let fade_out = time(2)?.unwrap_or(fade_in);
+                Ok(Effect::Fade {
+                    fade_in,
+                    stop,
+                    fade_out,
+                })
+            }
+            "gain" => {
+                arity(1..=1)?;
+                Ok(Effect::Gain(db(0)?.unwrap_or_default()))
+            }
+            "norm" => {
+                arity(0..=1)?;
+                Ok(Effect::Norm(db(0)?.unwrap_or_default()))
+            }
+            "channels" => {
+                arity(1..=1)?;
+                match args[0].parse() {
+                    Ok(c) if c > 0 => Ok(Effect::Channels(c)),
+                    _ => Err(format!("invalid channel count `{}`", args[0])),
+                }
+            }
+            "crossfeed" => {
+                arity(0..=0)?;
+                Ok(Effect::Crossfeed)
+            }
+            _ => Err(format!("unknown effect `{}`", name)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Effect {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
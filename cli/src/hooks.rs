@@ -0,0 +1,67 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use lilac::Lilac;
+use serde::Deserialize;
+
+/// Shell commands run by the player, configured under `[hooks]`
+///
+/// The metadata of the track is passed in `LILAC_*` environment variables.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Hooks {
+    /// When a track starts playing
+    pub on_track_start: Option<String>,
+    /// When a track finishes playing
+    pub on_track_end: Option<String>,
+    /// When the last track of the queue finishes playing
+    pub on_queue_end: Option<String>,
+}
+
+/// Runs a hook in the background, ignoring its output so that it can't garble the player
+pub fn run(command: Option<&str>, lilac: &Lilac, path: &Path) {
+    let Some(command) = command else {
+        return;
+    };
+
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .env("LILAC_FILE", path)
+        .env("LILAC_DURATION", lilac.duration().as_secs_f64().to_string());
+    for (name, value) in [
+        ("LILAC_TITLE", lilac.title.as_deref()),
+        ("LILAC_ARTIST", lilac.artist.as_deref()),
+        ("LILAC_ALBUM", lilac.album.as_deref()),
+        ("LILAC_GENRE", lilac.genre().as_deref()),
+    ] {
+        if let Some(value) = value {
+            cmd.env(name, value);
+        }
+    }
+    for (name, value) in [
+        ("LILAC_YEAR", lilac.year.map(|y| y.to_string())),
+        ("LILAC_TRACK", lilac.track.map(|t| t.to_string())),
+        ("LILAC_DISC", lilac.disc.map(|d| d.to_string())),
+    ] {
+        if let Some(value) = value {
+            cmd.env(name, value);
+        }
+    }
+
+    // Waited on from another thread to avoid leaving zombies around
+    if let Ok(mut child) = cmd.spawn() {
+        thread::spawn(move || child.wait());
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use std::{fs, process, thread};
+
+use miette::{miette, Context, IntoDiagnostic};
+use serde::de::DeserializeOwned;
+
+use crate::config;
+
+/// How long responses are reused before being requested again, unless offline
+const CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Least time between two requests to the same host,
+/// as the MusicBrainz and Discogs APIs both ask
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+/// Sent along with every request, as the MusicBrainz API requires
+const USER_AGENT: &str = concat!(
+    "lilac/",
+    env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/luludotdev/lilac )"
+);
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// Client shared by every online feature
+struct Client {
+    agent: ureq::Agent,
+    /// Time of the last request to each host
+    last: Mutex<HashMap<String, Instant>>,
+}
+
+/// Forbids network access, answering requests from the cache only
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::SeqCst);
+}
+
+/// Sets up the client from the configuration, which only counts before the first request
+pub fn configure(network: &config::Network) -> crate::Result {
+    if network.offline {
+        set_offline(true);
+    }
+    let mut builder = ureq::AgentBuilder::new().user_agent(USER_AGENT);
+    if let Some(proxy) = &network.proxy {
+        let proxy = ureq::Proxy::new(proxy)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid proxy `{}`", proxy))?;
+        builder = builder.proxy(proxy);
+    }
+    CLIENT.set(Client::new(builder)).ok();
+    crate::OK
+}
+
+/// Requests JSON, from the cache when it holds a recent enough response
+///
+/// Requests to the same host are spaced out, waiting as needed.
+pub fn get<T: DeserializeOwned>(
+    url: &str,
+    query: &[(&str, &str)],
+    headers: &[(&str, &str)],
+) -> miette::Result<T> {
+    let offline = OFFLINE.load(Ordering::SeqCst);
+    let cache = cache_path(url, query);
+    if let Some(body) = cache.as_deref().and_then(|p| cached(p, offline)) {
+        // A corrupted entry is simply requested again
+        if let Ok(value) = serde_json::from_str(&body) {
+            return Ok(value);
+        }
+    }
+    if offline {
+        return Err(miette!(
+            "`{}` isn't cached and network access is disabled",
+            url
+        ));
+    }
+
+    let client =
+        CLIENT.get_or_init(|| Client::new(ureq::AgentBuilder::new().user_agent(USER_AGENT)));
+    client.wait(url);
+    let mut request = client.agent.get(url);
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    for (name, value) in query {
+        request = request.query(name, value);
+    }
+    let body = request
+        .call()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Request to `{}` failed", url))?
+        .into_string()
+        .into_diagnostic()?;
+    let value = serde_json::from_str(&body)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid response from `{}`", url))?;
+
+    if let Some(path) = cache {
+        store(&path, &body);
+    }
+    Ok(value)
+}
+
+impl Client {
+    fn new(builder: ureq::AgentBuilder) -> Self {
+        Self {
+            agent: builder.build(),
+            last: Mutex::default(),
+        }
+    }
+
+    /// Sleeps until the host of the URL can be sent another request
+    fn wait(&self, url: &str) {
+        let host = url.split_once("://").map_or(url, |(_, rest)| rest);
+        let host = host.split('/').next().unwrap_or_default();
+
+        let mut last = self.last.lock().unwrap();
+        if let Some(&at) = last.get(host) {
+            let next = at + MIN_INTERVAL;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+        }
+        last.insert(host.to_owned(), Instant::now());
+    }
+}
+
+/// Location of the cached response to a request, in the cache directory
+fn cache_path(url: &str, query: &[(&str, &str)]) -> Option<PathBuf> {
+    let mut hasher = DefaultHasher::new();
+    (url, query).hash(&mut hasher);
+    let name = format!("{:016x}.json", hasher.finish());
+    dirs::cache_dir().map(|d| d.join("lilac").join("http").join(name))
+}
+
+/// Body of a cached response, expired ones still being used offline
+fn cached(path: &Path, offline: bool) -> Option<String> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    let fresh = modified.elapsed().is_ok_and(|age| age < CACHE_TTL);
+    if !fresh && !offline {
+        return None;
+    }
+    fs::read_to_string(path).ok()
+}
+
+/// Caches a response, which only means requesting it again next time if it fails
+fn store(path: &Path, body: &str) {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).ok();
+    }
+    // Unique to the process, as another one could be writing the same response
+    let part = path.with_extension(format!("{}.part", process::id()));
+    if fs::write(&part, body).is_ok() {
+        fs::rename(&part, path).ok();
+    }
+}
@@ -0,0 +1,237 @@
+//! Gettext-style translation of user-facing messages
+//!
+//! Messages are looked up by their English text in the catalog of the language
+//! selected by the usual locale variables, falling back to English.
+
+use std::env;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+type Catalog = &'static [(&'static str, &'static str)];
+
+static FR: Catalog = &[
+    ("Loading...", "Chargement..."),
+    ("No audio output device", "Aucun périphérique de sortie audio"),
+    (
+        "No audio output device named `{}`, run `lilac devices` to list them",
+        "Aucun périphérique de sortie audio nommé `{}`, lancez `lilac devices` pour les lister",
+    ),
+    ("{} (default)", "{} (par défaut)"),
+    ("PLAY", "LIRE"),
+    ("PAUSE", "PAUSE"),
+    ("mono", "mono"),
+    ("stereo", "stéréo"),
+    ("polyphonic", "polyphonique"),
+    ("{} bits {} at {} Hz", "{} bits {} à {} Hz"),
+    ("Now playing {} by {} on {}", "Lecture de {} par {} sur {}"),
+    ("Now broadcasting {} by {} on {}", "Diffusion de {} par {} sur {}"),
+    ("Now streaming {} by {} on {}", "Envoi de {} par {} sur {}"),
+    ("Lost the connection to the server", "Connexion au serveur perdue"),
+    ("Playing", "Lecture"),
+    ("Paused", "En pause"),
+    ("Volume {}", "Volume {}"),
+    ("Shuffle on", "Lecture aléatoire activée"),
+    ("Shuffle off", "Lecture aléatoire désactivée"),
+    ("Shuffle albums", "Albums en ordre aléatoire"),
+    ("Repeat off", "Répétition désactivée"),
+    ("Repeat all", "Répéter tout"),
+    ("Repeat one", "Répéter le morceau"),
+    ("SHUF", "ALÉA"),
+    ("ALBM", "ALBM"),
+    ("REP", "RÉP"),
+    ("REP1", "RÉP1"),
+    ("Added {} songs", "{} morceaux ajoutés"),
+    ("Crossfeed on", "Crossfeed activé"),
+    ("Crossfeed off", "Crossfeed désactivé"),
+    ("Output at {} Hz", "Sortie à {} Hz"),
+    ("Matched {} by {} on {} with {}", "{} de {} sur {} trouvé avec {}"),
+    ("{} of {} files look lossy or upsampled", "{} fichiers sur {} semblent issus d'un format avec perte ou suréchantillonnés"),
+    ("Added job {} to the queue", "Tâche {} ajoutée à la file"),
+    ("The queue is empty", "La file est vide"),
+    ("Running job {}", "Exécution de la tâche {}"),
+    ("Job {} failed", "Échec de la tâche {}"),
+    ("{} jobs done, {} failed", "{} tâches terminées, {} échouées"),
+    ("XFD", "XFD"),
+    ("{} files, {} added, {} modified, {} removed", "{} fichiers, {} ajoutés, {} modifiés, {} supprimés"),
+    ("Selection starts at {}", "Sélection à partir de {}"),
+    ("Selected {} to {}", "Sélection de {} à {}"),
+    ("Selection cleared", "Sélection effacée"),
+    ("Select a segment with `a` or add bookmarks first", "Sélectionnez un passage avec `a` ou ajoutez d'abord des signets"),
+    ("Playlists must end in .m3u or .m3u8", "Les listes de lecture doivent finir par .m3u ou .m3u8"),
+    ("Bookmarked {}", "Signet {} ajouté"),
+    ("No bookmarks", "Aucun signet"),
+    ("Press Enter to resume from {}", "Appuyez sur Entrée pour reprendre à {}"),
+    ("Unknown command `{}`", "Commande `{}` inconnue"),
+    ("Wrote `{}`", "`{}` écrit"),
+    ("Tagged `{}`", "`{}` étiqueté"),
+    ("`{}` skipped", "`{}` ignoré"),
+    (
+        "Watching {} directories, press Ctrl+C to stop",
+        "Surveillance de {} dossiers, appuyez sur Ctrl+C pour arrêter",
+    ),
+    (
+        "Failed to transcode `{}`, will retry",
+        "Échec du transcodage de `{}`, nouvel essai plus tard",
+    ),
+    ("Giving up on `{}`", "Abandon de `{}`"),
+    ("`{}` <- {} files", "`{}` <- {} fichiers"),
+    (
+        "Interrupted, rolling back files in progress... (press Ctrl+C again to abort)",
+        "Interrompu, annulation des fichiers en cours... (appuyez à nouveau sur Ctrl+C pour abandonner)",
+    ),
+    (
+        "Interrupted before transcoding {} files, which were left untouched:",
+        "Interrompu avant de transcoder {} fichiers, qui n'ont pas été modifiés :",
+    ),
+    (
+        "Run the same command again to resume",
+        "Relancez la même commande pour reprendre",
+    ),
+    ("waiting", "en attente"),
+    ("decoding", "décodage"),
+    ("writing", "écriture"),
+    ("verifying", "vérification"),
+    ("{} succeeded, {} failed", "{} réussis, {} échoués"),
+    ("Transcoded {} files in {} s", "{} fichiers transcodés en {} s"),
+    ("{} -> {}, {}x real time", "{} -> {}, {}x le temps réel"),
+    ("{} files, {} s, {}x real time", "{} fichiers, {} s, {}x le temps réel"),
+];
+
+static DE: Catalog = &[
+    ("Loading...", "Wird geladen..."),
+    ("No audio output device", "Kein Audioausgabegerät"),
+    (
+        "No audio output device named `{}`, run `lilac devices` to list them",
+        "Kein Audioausgabegerät namens `{}`, `lilac devices` listet alle auf",
+    ),
+    ("{} (default)", "{} (Standard)"),
+    ("PLAY", "SPIELT"),
+    ("PAUSE", "PAUSE"),
+    ("mono", "Mono"),
+    ("stereo", "Stereo"),
+    ("polyphonic", "mehrkanalig"),
+    ("{} bits {} at {} Hz", "{} Bit {} mit {} Hz"),
+    ("Now playing {} by {} on {}", "Es läuft {} von {} auf {}"),
+    ("Now broadcasting {} by {} on {}", "Gesendet wird {} von {} auf {}"),
+    ("Now streaming {} by {} on {}", "Gestreamt wird {} von {} auf {}"),
+    ("Lost the connection to the server", "Verbindung zum Server verloren"),
+    ("Playing", "Wiedergabe"),
+    ("Paused", "Pausiert"),
+    ("Volume {}", "Lautstärke {}"),
+    ("Shuffle on", "Zufallswiedergabe an"),
+    ("Shuffle off", "Zufallswiedergabe aus"),
+    ("Shuffle albums", "Alben in zufälliger Reihenfolge"),
+    ("Repeat off", "Wiederholung aus"),
+    ("Repeat all", "Alle wiederholen"),
+    ("Repeat one", "Titel wiederholen"),
+    ("SHUF", "ZUF"),
+    ("ALBM", "ALBM"),
+    ("REP", "WDH"),
+    ("REP1", "WDH1"),
+    ("Added {} songs", "{} Titel hinzugefügt"),
+    ("Crossfeed on", "Crossfeed an"),
+    ("Crossfeed off", "Crossfeed aus"),
+    ("Output at {} Hz", "Ausgabe mit {} Hz"),
+    ("Matched {} by {} on {} with {}", "{} von {} auf {} mit {} gefunden"),
+    ("{} of {} files look lossy or upsampled", "{} von {} Dateien wirken verlustbehaftet oder hochgerechnet"),
+    ("Added job {} to the queue", "Auftrag {} zur Warteschlange hinzugefügt"),
+    ("The queue is empty", "Die Warteschlange ist leer"),
+    ("Running job {}", "Auftrag {} läuft"),
+    ("Job {} failed", "Auftrag {} fehlgeschlagen"),
+    ("{} jobs done, {} failed", "{} Aufträge erledigt, {} fehlgeschlagen"),
+    ("XFD", "XFD"),
+    ("{} files, {} added, {} modified, {} removed", "{} Dateien, {} hinzugefügt, {} geändert, {} entfernt"),
+    ("Selection starts at {}", "Auswahl beginnt bei {}"),
+    ("Selected {} to {}", "{} bis {} ausgewählt"),
+    ("Selection cleared", "Auswahl aufgehoben"),
+    ("Select a segment with `a` or add bookmarks first", "Zuerst einen Abschnitt mit `a` auswählen oder Lesezeichen setzen"),
+    ("Playlists must end in .m3u or .m3u8", "Wiedergabelisten müssen auf .m3u oder .m3u8 enden"),
+    ("Bookmarked {}", "Lesezeichen {} gesetzt"),
+    ("No bookmarks", "Keine Lesezeichen"),
+    ("Press Enter to resume from {}", "Eingabe drücken, um bei {} fortzusetzen"),
+    ("Unknown command `{}`", "Unbekannter Befehl `{}`"),
+    ("Wrote `{}`", "`{}` geschrieben"),
+    ("Tagged `{}`", "`{}` getaggt"),
+    ("`{}` skipped", "`{}` übersprungen"),
+    (
+        "Watching {} directories, press Ctrl+C to stop",
+        "{} Verzeichnisse werden überwacht, Strg+C zum Beenden",
+    ),
+    (
+        "Failed to transcode `{}`, will retry",
+        "Transkodieren von `{}` fehlgeschlagen, neuer Versuch folgt",
+    ),
+    ("Giving up on `{}`", "`{}` wird aufgegeben"),
+    ("`{}` <- {} files", "`{}` <- {} Dateien"),
+    (
+        "Interrupted, rolling back files in progress... (press Ctrl+C again to abort)",
+        "Unterbrochen, laufende Dateien werden zurückgesetzt... (erneut Strg+C drücken zum Abbrechen)",
+    ),
+    (
+        "Interrupted before transcoding {} files, which were left untouched:",
+        "Unterbrochen vor dem Transkodieren von {} Dateien, die unverändert blieben:",
+    ),
+    (
+        "Run the same command again to resume",
+        "Denselben Befehl erneut ausführen, um fortzufahren",
+    ),
+    ("waiting", "wartet"),
+    ("decoding", "dekodiert"),
+    ("writing", "schreibt"),
+    ("verifying", "prüft"),
+    ("{} succeeded, {} failed", "{} erfolgreich, {} fehlgeschlagen"),
+    ("Transcoded {} files in {} s", "{} Dateien in {} s transkodiert"),
+    ("{} -> {}, {}x real time", "{} -> {}, {}x Echtzeit"),
+    ("{} files, {} s, {}x real time", "{} Dateien, {} s, {}x Echtzeit"),
+];
+
+/// Catalog of the language selected by `LC_ALL`, `LC_MESSAGES` or `LANG`,
+/// or `None` for English
+fn catalog() -> Option<Catalog> {
+    static CATALOG: OnceLock<Option<Catalog>> = OnceLock::new();
+    *CATALOG.get_or_init(|| {
+        let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(|v| env::var(v).ok())
+            .find(|v| !v.is_empty())?;
+        let lang = locale.split(['_', '.', '@']).next()?;
+        match lang {
+            "fr" => Some(FR),
+            "de" => Some(DE),
+            _ => None,
+        }
+    })
+}
+
+/// Translates a message, returning it unchanged when there is no translation
+pub fn translate(msgid: &'static str) -> &'static str {
+    catalog()
+        .and_then(|c| c.iter().find(|(id, _)| *id == msgid))
+        .map_or(msgid, |&(_, msg)| msg)
+}
+
+/// Replaces the `{}` placeholders of a translated message with the arguments, in order
+pub fn fill(msg: &str, args: &[&dyn Display]) -> String {
+    let mut parts = msg.split("{}");
+    let mut filled = parts.next().unwrap_or_default().to_owned();
+    for (i, part) in parts.enumerate() {
+        if let Some(arg) = args.get(i) {
+            filled.push_str(&arg.to_string());
+        }
+        filled.push_str(part);
+    }
+    filled
+}
+
+/// Translates a message, filling its `{}` placeholders with the given arguments
+macro_rules! tr {
+    ($msgid:literal) => {
+        $crate::i18n::translate($msgid)
+    };
+    ($msgid:literal, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::fill(
+            $crate::i18n::translate($msgid),
+            &[$(&$arg as &dyn std::fmt::Display),+],
+        )
+    };
+}
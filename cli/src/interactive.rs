@@ -1,83 +1,217 @@
+use std::collections::{HashMap, HashSet};
 use std::io::{self, Write};
+use std::ops::Range;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::{Duration, Instant};
-use std::{process, thread};
+use std::{fs, iter, mem, process, thread};
 
-use crossterm::event::{self, Event as TerminalEvent, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::event::{
+    self, Event as TerminalEvent, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+};
 use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
 use crossterm::ExecutableCommand;
-use lilac::Lilac;
-use miette::{Context, IntoDiagnostic};
+use lilac::{Bookmark, CrossfeedSource, Interpolation, Lilac, ResampledSource};
+use miette::{miette, IntoDiagnostic};
 use ratatui::backend::CrosstermBackend;
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
-use ratatui::style::{self, Color, Style};
-use ratatui::text::Line;
+use ratatui::text::{Line, Span};
 use ratatui::widgets::Wrap;
 use ratatui::{widgets, Frame, Terminal};
 use rayon::prelude::*;
 use rodio::{Sink, Source};
 
-const TICK_RATE: Duration = Duration::from_millis(100);
+use crate::config::{self, Config, ShuffleStyle};
+use crate::library::{Browser, Library};
+use crate::theme::Theme;
+use crate::{device, hooks, media, paths, playlist, sleep, transcode};
 
-static BOLD: Style = Style::new().add_modifier(style::Modifier::BOLD);
-static WHITE: Style = Style::new().fg(Color::White);
+const TICK_RATE: Duration = Duration::from_millis(100);
+const SEEK_STEP: Duration = Duration::from_secs(5);
+/// Songs at least this long get their position remembered, e.g. podcasts or audiobooks
+const LONG_SONG: Duration = Duration::from_secs(10 * 60);
+/// Positions this close to either end aren't worth resuming from
+const RESUME_MARGIN: Duration = Duration::from_secs(30);
+/// Length of the waveform shown by the visualizer
+const WAVEFORM_WINDOW: Duration = Duration::from_secs(8);
+const WAVEFORM_BUCKETS: usize = 256;
+const SPECTRUM_BANDS: usize = 48;
+/// Resolution of the visualizer levels
+const VISUALIZER_SCALE: u64 = 100;
+/// Playback speed change per key press, and the slowest and fastest speeds
+const SPEED_STEP: f32 = 0.25;
+const SPEED_RANGE: (f32, f32) = (0.5, 2.0);
+/// Ticks between two checks that the output device is still there
+const DEVICE_CHECK_TICKS: u32 = 20;
 
 struct Queue {
-    songs: Vec<(Lilac, PathBuf)>,
+    songs: Vec<(Arc<Lilac>, PathBuf)>,
+    /// Whether songs in other formats are converted to LILAC files next to them
+    convert: bool,
+    /// Play order, as indices into `songs`
+    order: Vec<usize>,
+    /// Position in `order`
     cursor: usize,
+    shuffle: Shuffle,
+    repeat: Repeat,
+    style: ShuffleStyle,
+    /// Recently played songs, most recent first, which smart shuffle plays later on
+    history: Vec<PathBuf>,
+}
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Shuffle {
+    Off,
+    Songs,
+    /// Albums in random order, with their songs in sequence
+    Albums,
+}
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    Off,
+    All,
+    One,
 }
 struct QueueEl<'a> {
     idx: usize,
-    lilac: &'a Lilac,
+    /// Metadata only, see [`Decoded`] for the samples
+    lilac: &'a Arc<Lilac>,
+    path: &'a Path,
 }
 
 impl Queue {
-    fn new<'a, P>(files: &'a [P]) -> Result<Self, lilac::Error>
+    fn new<'a, P>(files: &'a [P], convert: bool) -> Result<Self, lilac::Error>
     where
         P: AsRef<Path> + Sync,
         &'a [P]: IntoParallelIterator<Item = &'a P>,
     {
+        let songs = Self::load(files, convert);
         Ok(Self {
-            songs: files
-                .par_iter()
-                .filter_map(|f| match Lilac::read_file(f) {
-                    Ok(l) => Some((l, f.as_ref().to_owned())),
+            order: (0..songs.len()).collect(),
+            songs,
+            convert,
+            cursor: 0,
+            shuffle: Shuffle::Off,
+            repeat: Repeat::Off,
+            style: ShuffleStyle::Random,
+            history: Vec::new(),
+        })
+    }
+    /// Reads the metadata of songs, preferring the LILAC file an earlier conversion left
+    ///
+    /// Samples are only decoded when played,
+    /// and other formats are converted in the background when enabled.
+    fn load<'a, P>(files: &'a [P], convert: bool) -> Vec<(Arc<Lilac>, PathBuf)>
+    where
+        P: AsRef<Path> + Sync,
+        &'a [P]: IntoParallelIterator<Item = &'a P>,
+    {
+        let songs: Vec<_> = files
+            .par_iter()
+            .filter_map(|f| {
+                let mut path = f.as_ref().to_owned();
+                if !is_lilac(&path) && path.with_extension("lilac").is_file() {
+                    path.set_extension("lilac");
+                }
+                match transcode::read_header(&path) {
+                    Ok((l, _)) => Some((Arc::new(l), path)),
                     Err(e) => {
-                        io::stderr().lock().write_fmt(format_args!("{}", e)).ok();
+                        eprintln!("{:#}", e);
                         None
                     }
-                })
-                .collect(),
-            cursor: 0,
-        })
+                }
+            })
+            .collect();
+
+        let others: Vec<_> = songs
+            .iter()
+            .map(|(_, p)| p)
+            .filter(|p| !is_lilac(p))
+            .cloned()
+            .collect();
+        if convert && !others.is_empty() {
+            thread::spawn(move || convert_all(others));
+        }
+        songs
     }
     fn is_empty(&self) -> bool {
         self.songs.is_empty()
     }
 
     fn current(&self) -> QueueEl {
-        let (l, _) = &self.songs[self.cursor];
+        let idx = self.order[self.cursor];
+        let (l, p) = &self.songs[idx];
         QueueEl {
-            idx: self.cursor,
+            idx,
             lilac: l,
+            path: p,
+        }
+    }
+    /// Path of the song played after the current one if nothing is pressed
+    fn upcoming(&self) -> Option<&Path> {
+        let position = match self.repeat {
+            Repeat::One => self.cursor,
+            _ if self.cursor + 1 < self.order.len() => self.cursor + 1,
+            Repeat::All => 0,
+            Repeat::Off => return None,
+        };
+        Some(&self.songs[self.order[position]].1)
+    }
+    /// Runs of consecutive songs from the same album, with a header describing it
+    fn albums(&self) -> Vec<(String, Range<usize>)> {
+        let mut albums: Vec<(String, Range<usize>)> = Vec::new();
+        for (i, (l, _)) in self.songs.iter().enumerate() {
+            let artist = l.album_artist();
+            let header = match l.year {
+                Some(year) => format!("{} - {}, {}", artist, l.album(), year),
+                None => format!("{} - {}", artist, l.album()),
+            };
+            match albums.last_mut() {
+                Some((h, range)) if *h == header => range.end = i + 1,
+                _ => albums.push((header, i..i + 1)),
+            }
         }
+        albums
     }
-    fn files(&self) -> Vec<&str> {
+    /// Title, artist and album of every song, as matched by searches
+    fn labels(&self) -> Vec<String> {
         self.songs
             .iter()
-            .map(|(_, p)| p.file_stem().unwrap().to_str().unwrap())
+            .map(|(l, _)| format!("{} - {} - {}", l.title(), l.artist(), l.album()))
+            .collect()
+    }
+    fn files(&self) -> Vec<String> {
+        self.songs
+            .iter()
+            .map(|(_, p)| {
+                p.file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            })
             .collect()
     }
 
     fn next(&mut self) -> bool {
         if self.cursor == self.songs.len() - 1 {
-            return false;
+            if self.repeat == Repeat::Off {
+                return false;
+            }
+            self.wrap();
+            return true;
         }
 
         self.cursor += 1;
         true
     }
+    /// Moves on once the current song is over, following the repeat mode
+    fn advance(&mut self) -> bool {
+        self.repeat == Repeat::One || self.next()
+    }
+    fn rewind(&mut self) {
+        self.cursor = 0;
+    }
     fn prev(&mut self) -> bool {
         if self.cursor == 0 {
             return false;
@@ -86,74 +220,328 @@ impl Queue {
         self.cursor -= 1;
         true
     }
-}
-
-struct Stopwatch {
-    time: Duration,
-    started: Instant,
-    running: bool,
-}
 
-impl Stopwatch {
-    fn new() -> Self {
-        Self {
-            time: Duration::new(0, 0),
-            started: Instant::now(),
-            running: false,
+    /// Starts over from the beginning, with a new order when shuffling
+    fn wrap(&mut self) {
+        match self.shuffle {
+            Shuffle::Songs if self.order.len() > 1 => {
+                // Don't play the last song twice in a row
+                let last = self.order.pop().unwrap();
+                let mut order = mem::take(&mut self.order);
+                self.shuffle_songs(&mut order);
+                let i = fastrand::usize(1..=order.len());
+                order.insert(i, last);
+                self.order = order;
+            }
+            Shuffle::Albums => {
+                let last = *self.order.last().unwrap();
+                let mut albums = self.album_ranges();
+                fastrand::shuffle(&mut albums);
+                if albums.len() > 1 && albums[0].contains(&last) {
+                    albums.swap(0, 1);
+                }
+                self.order = albums.into_iter().flatten().collect();
+            }
+            _ => {}
         }
+        self.cursor = 0;
     }
 
-    fn start(&mut self) {
-        if self.running {
-            return;
+    /// Goes from no shuffle to shuffling songs, then albums
+    fn cycle_shuffle(&mut self) {
+        let current = self.order[self.cursor];
+        self.shuffle = match self.shuffle {
+            Shuffle::Off => Shuffle::Songs,
+            Shuffle::Songs => Shuffle::Albums,
+            Shuffle::Albums => Shuffle::Off,
+        };
+        match self.shuffle {
+            Shuffle::Off => {
+                self.order = (0..self.songs.len()).collect();
+                self.cursor = current;
+            }
+            Shuffle::Songs => {
+                // The current song stays first and the rest is played in random order
+                let mut order: Vec<_> = (0..self.songs.len()).filter(|&i| i != current).collect();
+                self.shuffle_songs(&mut order);
+                order.insert(0, current);
+                self.order = order;
+                self.cursor = 0;
+            }
+            Shuffle::Albums => {
+                // The current album stays first, without skipping back to its start
+                let mut albums = self.album_ranges();
+                let first = albums.iter().position(|r| r.contains(&current)).unwrap();
+                let first = albums.remove(first);
+                fastrand::shuffle(&mut albums);
+                self.cursor = current - first.start;
+                self.order = iter::once(first).chain(albums).flatten().collect();
+            }
         }
-        self.running = true;
-        self.started = Instant::now();
     }
-    fn stop(&mut self) {
-        if !self.running {
+    /// Puts songs in random order, following the shuffle style
+    fn shuffle_songs(&self, order: &mut [usize]) {
+        if self.style == ShuffleStyle::Random {
+            fastrand::shuffle(order);
             return;
         }
-        self.running = false;
-        self.time += self.started.elapsed();
+
+        // Weighted random order, where the more recently a song was played the less it weighs:
+        // sorting by u^(1/w) with u uniform draws songs in proportion to their weight
+        let recency: HashMap<&Path, usize> = self
+            .history
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (p.as_path(), i))
+            .collect();
+        let keys: HashMap<usize, f64> = order
+            .iter()
+            .map(|&i| {
+                let weight = match recency.get(self.songs[i].1.as_path()) {
+                    Some(&n) => (n + 1) as f64 / (self.history.len() + 1) as f64,
+                    None => 1.0,
+                };
+                (i, fastrand::f64().powf(1.0 / weight))
+            })
+            .collect();
+        order.sort_by(|a, b| keys[b].total_cmp(&keys[a]));
+
+        // Then the next song by another artist is pulled forward whenever one repeats
+        let artist = |i: usize| self.songs[i].0.artist.as_deref();
+        for i in 1..order.len() {
+            if artist(order[i]) != artist(order[i - 1]) {
+                continue;
+            }
+            if let Some(j) =
+                (i + 1..order.len()).find(|&j| artist(order[j]) != artist(order[i - 1]))
+            {
+                order[i..=j].rotate_right(1);
+            }
+        }
+    }
+    /// Songs of each album, as runs of consecutive songs
+    fn album_ranges(&self) -> Vec<Range<usize>> {
+        self.albums().into_iter().map(|(_, r)| r).collect()
+    }
+    /// Appends songs at the end of the play order, returning how many were loaded
+    fn add<'a, P>(&mut self, files: &'a [P]) -> usize
+    where
+        P: AsRef<Path> + Sync,
+        &'a [P]: IntoParallelIterator<Item = &'a P>,
+    {
+        let songs = Self::load(files, self.convert);
+        let added = songs.len();
+        self.order
+            .extend(self.songs.len()..self.songs.len() + added);
+        self.songs.extend(songs);
+        added
+    }
+    /// Makes the song at the given index the current one
+    fn jump(&mut self, idx: usize) {
+        self.cursor = self.order.iter().position(|&i| i == idx).unwrap();
     }
+    /// Removes the song at the given index, unless it is the last one left
+    ///
+    /// If it was the current one, the following song becomes current.
+    fn remove(&mut self, idx: usize) -> bool {
+        if self.songs.len() == 1 {
+            return false;
+        }
+
+        let position = self.order.iter().position(|&i| i == idx).unwrap();
+        self.songs.remove(idx);
+        self.order.remove(position);
+        for i in &mut self.order {
+            if *i > idx {
+                *i -= 1;
+            }
+        }
 
-    fn reset(&mut self) {
-        self.time = Duration::new(0, 0);
-        self.started = Instant::now();
+        if position < self.cursor {
+            self.cursor -= 1;
+        }
+        self.cursor = self.cursor.min(self.order.len() - 1);
+        true
+    }
+    /// Swaps two songs in the list without changing the play order
+    fn swap(&mut self, a: usize, b: usize) {
+        self.songs.swap(a, b);
+        for i in &mut self.order {
+            if *i == a {
+                *i = b;
+            } else if *i == b {
+                *i = a;
+            }
+        }
     }
 
-    fn time(&self) -> Duration {
-        if self.running {
-            self.time + self.started.elapsed()
+    fn cycle_repeat(&mut self) {
+        self.repeat = match self.repeat {
+            Repeat::Off => Repeat::All,
+            Repeat::All => Repeat::One,
+            Repeat::One => Repeat::Off,
+        };
+    }
+}
+
+fn is_lilac(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == "lilac")
+}
+
+/// Reads a LILAC file, or decodes a song in any other supported format
+fn decode(path: &Path) -> miette::Result<Lilac> {
+    if is_lilac(path) {
+        Ok(Lilac::read_file(path)?)
+    } else {
+        transcode::decode(path).map(|(l, _)| l)
+    }
+}
+
+/// Converts songs to LILAC files next to them, one at a time
+///
+/// Failures are ignored, the original simply keeps being played.
+fn convert_all(paths: Vec<PathBuf>) {
+    for path in paths {
+        let dest = path.with_extension("lilac");
+        if dest.exists() {
+            continue;
+        }
+        let Ok((lilac, _)) = transcode::decode(&path) else {
+            continue;
+        };
+
+        // Written next to the file first so that a failure never leaves it truncated
+        let part = transcode::part_path(&dest);
+        if lilac.write_file(&part).is_ok() {
+            fs::rename(&part, &dest).ok();
         } else {
-            self.time
+            fs::remove_file(&part).ok();
+        }
+    }
+}
+
+/// Fully decoded songs, only kept for the current and upcoming ones
+///
+/// The queue itself only holds metadata, so that large queues load quickly
+/// and the upcoming song can be decoded in the background while the current one plays.
+#[derive(Default)]
+struct Decoded {
+    songs: Arc<Mutex<HashMap<PathBuf, Arc<OnceLock<Option<Arc<Lilac>>>>>>>,
+}
+
+impl Decoded {
+    fn cell(&self, path: &Path) -> Arc<OnceLock<Option<Arc<Lilac>>>> {
+        let mut songs = self.songs.lock().unwrap();
+        Arc::clone(songs.entry(path.to_owned()).or_default())
+    }
+
+    /// Returns the decoded song, waiting for it if it is being prefetched
+    fn get(&self, path: &Path) -> miette::Result<Arc<Lilac>> {
+        match self
+            .cell(path)
+            .get_or_init(|| decode(path).ok().map(Arc::new))
+        {
+            Some(lilac) => Ok(Arc::clone(lilac)),
+            // Decode again to get the error, which can't be kept around
+            None => decode(path).map(Arc::new),
+        }
+    }
+
+    /// Drops every song but the current one and starts decoding the upcoming one
+    fn prefetch(&self, current: &Path, upcoming: Option<&Path>) {
+        self.songs
+            .lock()
+            .unwrap()
+            .retain(|p, _| p == current || Some(p.as_path()) == upcoming);
+
+        if let Some(path) = upcoming {
+            let cell = self.cell(path);
+            let path = path.to_owned();
+            thread::spawn(move || {
+                cell.get_or_init(|| decode(&path).ok().map(Arc::new));
+            });
         }
     }
 }
 
-pub fn main(files: Vec<String>) -> crate::Result {
-    println!("Loading...");
-    let mut queue = Queue::new(&files)?;
+pub fn main(
+    files: Vec<String>,
+    device: Option<String>,
+    plain: bool,
+    library: Option<PathBuf>,
+    volume: Option<u16>,
+) -> crate::Result {
+    let config = Config::load()?;
+    let theme = Theme::new(config.theme);
+    let hooks = config.hooks;
+    let keys = config.keys.remap();
+    let mut memory = config::State::load();
+    let device = device.or(config.player.device);
+    let volume = volume
+        .or(memory.volume)
+        .or(config.player.volume)
+        .unwrap_or(100)
+        .min(100);
+    let hook = |command: &Option<String>, queue: &Queue| {
+        let QueueEl { lilac, path, .. } = queue.current();
+        hooks::run(command.as_deref(), lilac, path);
+    };
+
+    status!("{}", tr!("Loading..."));
+    let files = playlist::expand(files.into_iter().map(PathBuf::from))?;
+    let mut queue = Queue::new(&files, config.player.convert)?;
+    queue.style = config.player.shuffle;
+    queue.history.clone_from(&memory.recent);
+    let browser = match library {
+        Some(dir) => {
+            let library = Library::scan(&dir)?;
+            // Without explicit files, the whole library is queued
+            if queue.is_empty() {
+                queue.add(&library.paths());
+            }
+            (!library.is_empty()).then(|| Browser::new(library))
+        }
+        None => None,
+    };
     if queue.is_empty() {
         return crate::OK;
     }
-    let (_stream, device) = rodio::OutputStream::try_default()
-        .into_diagnostic()
-        .context("No audio output device")?;
+    let mut device_name = device::resolve(device.as_deref());
+    let output_rate = device::sample_rate(device.as_deref());
+    let (mut _stream, mut device) = device::open(device.as_deref())?;
+    let mut ticks = 0;
 
     crossterm::terminal::enable_raw_mode().into_diagnostic()?;
 
-    let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout())).into_diagnostic()?;
-    terminal
-        .backend_mut()
-        .execute(EnterAlternateScreen)
-        .into_diagnostic()?;
-    terminal.hide_cursor().into_diagnostic()?;
+    let mut terminal = if plain {
+        None
+    } else {
+        let mut terminal = Terminal::new(CrosstermBackend::new(io::stdout())).into_diagnostic()?;
+        terminal
+            .backend_mut()
+            .execute(EnterAlternateScreen)
+            .into_diagnostic()?;
+        terminal.hide_cursor().into_diagnostic()?;
 
-    terminal.clear().into_diagnostic()?;
+        terminal.clear().into_diagnostic()?;
+        Some(terminal)
+    };
+    let mut announcer = Announcer::default();
+    // Index of the track for which the start hook was last run
+    let mut started = None;
 
     let (tx, rx) = mpsc::channel();
+    let sleep_tx = tx.clone();
+    sleep::watch(move |sleeping| {
+        let event = if sleeping { Event::Sleep } else { Event::Wake };
+        sleep_tx.send(event).ok();
+    });
+    let media_tx = tx.clone();
+    let mut media = media::Controls::new(move |command| {
+        media_tx.send(Event::Media(command)).ok();
+    });
+    // Track and play state last published to the media controls
+    let mut published = None;
     thread::spawn(move || {
         if let Err(e) = poll(tx) {
             eprintln!("{:#}", e);
@@ -161,9 +549,17 @@ pub fn main(files: Vec<String>) -> crate::Result {
         }
     });
 
-    let mut stopwatch = Stopwatch::new();
-
-    let source = queue.current().lilac.clone().source();
+    let decoded = Decoded::default();
+    // Shared with every source so that toggling it applies to the current one
+    let crossfeed = Arc::new(AtomicBool::new(config.player.crossfeed));
+    let resampler = config.player.resampler;
+    let source = song_source(
+        decoded.get(queue.current().path)?,
+        &crossfeed,
+        output_rate,
+        resampler,
+    );
+    decoded.prefetch(queue.current().path, queue.upcoming());
     let mut sink = Sink::try_new(&device).into_diagnostic()?;
 
     let mut state = State {
@@ -173,26 +569,94 @@ pub fn main(files: Vec<String>) -> crate::Result {
                 played: Duration::new(0, 0),
                 duration: source.total_duration().unwrap(),
             },
-            volume: VolumeState(100),
+            volume: VolumeState(volume),
+            modes: ModesState {
+                shuffle: Shuffle::Off,
+                repeat: Repeat::Off,
+                speed: 1.0,
+                crossfeed: config.player.crossfeed,
+            },
         },
         info: InfoState::read(&queue),
+        focus: None,
+        prompt: None,
+        message: None,
+        search: None,
+        collapsed: HashSet::new(),
+        browser,
+        browsing: false,
+        marks: None,
+        selection: None,
+        output_rate,
+        visualizer: VisualizerState {
+            mode: Visualization::Off,
+            levels: Vec::new(),
+        },
     };
 
     sink.set_volume(state.controls.volume.0 as f32 / 100.0);
+    sink.set_speed(state.controls.modes.speed);
     sink.append(source);
     sink.pause();
 
+    // Song loaded in the sink, which the queue may have already moved on from
+    let mut loaded = queue.current().path.to_owned();
+    // Position the loaded song was left at last time
+    let mut resume = None;
+
+    // The sink counts time as it is heard, which differs from the song's at other speeds
+    macro_rules! position {
+        () => {
+            sink.get_pos().mul_f32(state.controls.modes.speed)
+        };
+    }
+    macro_rules! leave {
+        () => {{
+            let position = position!();
+            let duration = state.controls.playback.duration;
+            if duration >= LONG_SONG {
+                if position + RESUME_MARGIN >= duration {
+                    memory.positions.remove(&loaded);
+                } else if position > RESUME_MARGIN {
+                    memory
+                        .positions
+                        .insert(loaded.clone(), position.as_secs_f64());
+                }
+            }
+        }};
+    }
+    macro_rules! offer_resume {
+        () => {{
+            resume = memory
+                .positions
+                .get(&loaded)
+                .map(|&p| Duration::from_secs_f64(p));
+            if let Some(position) = resume {
+                state.message = Some(tr!("Press Enter to resume from {}", format_time(position)));
+            }
+        }};
+    }
+    offer_resume!();
+
     macro_rules! reset {
         () => {{
+            leave!();
             sink.stop();
             sink = Sink::try_new(&device).into_diagnostic()?;
 
-            let source = queue.current().lilac.clone().source();
+            let source = song_source(
+                decoded.get(queue.current().path)?,
+                &crossfeed,
+                state.output_rate,
+                resampler,
+            );
+            decoded.prefetch(queue.current().path, queue.upcoming());
             state.controls.playback.played = Duration::new(0, 0);
             state.controls.playback.duration = source.total_duration().unwrap();
             state.info = InfoState::read(&queue);
 
             sink.set_volume(state.controls.volume.0 as f32 / 100.0);
+            sink.set_speed(state.controls.modes.speed);
             sink.append(source);
             if state.controls.playback.playing {
                 sink.play();
@@ -200,25 +664,265 @@ pub fn main(files: Vec<String>) -> crate::Result {
                 sink.pause();
             }
 
-            stopwatch.reset()
+            started = None;
+            state.marks = None;
+            state.selection = None;
+            loaded = queue.current().path.to_owned();
+            offer_resume!();
+        }};
+    }
+    macro_rules! seek {
+        ($position:expr) => {{
+            let position = $position.min(state.controls.playback.duration);
+            if sink
+                .try_seek(position.div_f32(state.controls.modes.speed))
+                .is_ok()
+            {
+                state.controls.playback.played = position;
+                media.playback(state.controls.playback.playing, position);
+            }
+        }};
+    }
+    macro_rules! set_playing {
+        ($playing:expr) => {{
+            state.controls.playback.playing = $playing;
+            if state.controls.playback.playing {
+                sink.play();
+            } else {
+                sink.pause();
+            }
+        }};
+    }
+
+    macro_rules! reconnect {
+        () => {{
+            let position = state.controls.playback.played;
+            (_stream, device) = device::open(None)?;
+            device_name = device::resolve(None);
+            state.output_rate = device::sample_rate(None);
+
+            // Resuming the same song shouldn't run its start hook again
+            let s = started;
+            reset!();
+            started = s;
+            seek!(position);
         }};
     }
 
     loop {
-        terminal.draw(|f| draw(f, &state)).into_diagnostic()?;
+        if state.controls.playback.playing && started != Some(queue.current().idx) {
+            started = Some(queue.current().idx);
+            hook(&hooks.on_track_start, &queue);
+            memory.played(queue.current().path);
+            queue.history.clone_from(&memory.recent);
+        }
+        let playback = (queue.current().idx, state.controls.playback.playing);
+        if published != Some(playback) {
+            if published.map(|(idx, _)| idx) != Some(playback.0) {
+                let m = &state.info.metadata;
+                media.metadata(
+                    &m.title,
+                    &m.artist,
+                    &m.album,
+                    state.controls.playback.duration,
+                );
+            }
+            media.playback(playback.1, state.controls.playback.played);
+            published = Some(playback);
+        }
+
+        match &mut terminal {
+            Some(t) => {
+                t.draw(|f| draw(f, &state, &theme)).into_diagnostic()?;
+            }
+            None => announcer.announce(&state).into_diagnostic()?,
+        }
+
+        let mut event = rx.recv().into_diagnostic()?;
+        if let Event::Input(key) = &mut event {
+            state.message = None;
+            // Typed text isn't remapped
+            if let (KeyCode::Char(c), None, None) = (key.code, &state.prompt, &state.search) {
+                if let Some(&default) = keys.get(&c) {
+                    key.code = KeyCode::Char(default);
+                }
+            }
+        }
+        match event {
+            Event::Input(KeyEvent {
+                code,
+                kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                ..
+            }) if state.prompt.is_some() => {
+                let prompt = state.prompt.as_mut().unwrap();
+                match code {
+                    KeyCode::Char(c) => prompt.push(c),
+                    KeyCode::Backspace => {
+                        prompt.pop();
+                    }
+                    KeyCode::Enter => {
+                        let line = state.prompt.take().unwrap();
+                        let position = position!();
+                        let segment = segment(&state, position);
+                        state.message =
+                            command(&line, &mut queue, &decoded, &memory, position, segment);
+                        state.info = InfoState::read(&queue);
+                    }
+                    KeyCode::Esc => state.prompt = None,
+                    _ => continue,
+                }
+            }
+            Event::Input(_) if state.prompt.is_some() => continue,
 
-        match rx.recv().into_diagnostic()? {
-            Event::Input(KeyEvent { code, kind, .. }) => match (code, kind) {
+            Event::Input(KeyEvent {
+                code,
+                kind: KeyEventKind::Press | KeyEventKind::Repeat,
+                ..
+            }) if state.search.is_some() => {
+                let search = state.search.as_mut().unwrap();
+                let results = state.info.queue.search(&search.query);
+                match code {
+                    KeyCode::Char(c) => {
+                        search.query.push(c);
+                        search.selected = 0;
+                    }
+                    KeyCode::Backspace => {
+                        search.query.pop();
+                        search.selected = 0;
+                    }
+                    KeyCode::Up => search.selected = search.selected.saturating_sub(1),
+                    KeyCode::Down => {
+                        search.selected = (search.selected + 1).min(results.len().saturating_sub(1))
+                    }
+                    KeyCode::Enter => {
+                        if let Some(&idx) = results.get(search.selected) {
+                            queue.jump(idx);
+                            reset!();
+                        }
+                        state.search = None;
+                    }
+                    KeyCode::Esc => state.search = None,
+                    _ => continue,
+                }
+            }
+            Event::Input(_) if state.search.is_some() => continue,
+
+            Event::Input(KeyEvent {
+                code,
+                kind,
+                modifiers,
+                ..
+            }) => match (code, kind) {
                 (KeyCode::Char(' '), KeyEventKind::Press) => {
-                    state.controls.playback.playing = !state.controls.playback.playing;
-                    if state.controls.playback.playing {
-                        sink.play();
-                        stopwatch.start();
+                    set_playing!(!state.controls.playback.playing)
+                }
+
+                // Not `[` and `]`, which jump between albums
+                (KeyCode::Char(key @ ('<' | '>')), KeyEventKind::Press) => {
+                    let position = position!();
+                    let step = if key == '>' { SPEED_STEP } else { -SPEED_STEP };
+                    let speed = &mut state.controls.modes.speed;
+                    *speed = (*speed + step).clamp(SPEED_RANGE.0, SPEED_RANGE.1);
+                    sink.set_speed(*speed);
+                    // Realigns the sink's clock with the song at the new speed
+                    seek!(position);
+                }
+                (KeyCode::Char('a'), KeyEventKind::Press) => {
+                    let position = position!();
+                    let (selection, message) = match state.selection {
+                        None => (
+                            Some((position, None)),
+                            tr!("Selection starts at {}", format_time(position)),
+                        ),
+                        Some((start, None)) => {
+                            let (a, b) = (start.min(position), start.max(position));
+                            (
+                                Some((a, Some(b))),
+                                tr!("Selected {} to {}", format_time(a), format_time(b)),
+                            )
+                        }
+                        Some(_) => (None, tr!("Selection cleared")),
+                    };
+                    state.selection = selection;
+                    state.message = Some(message);
+                }
+                (KeyCode::Char('x'), KeyEventKind::Press) => {
+                    state.prompt = Some("export ".to_owned());
+                }
+                (KeyCode::Char('m'), KeyEventKind::Press) => {
+                    state.prompt = Some("mark ".to_owned());
+                }
+                (KeyCode::Char('g'), KeyEventKind::Press) => {
+                    if state.info.metadata.bookmarks.is_empty() {
+                        state.message = Some(tr!("No bookmarks"));
                     } else {
-                        sink.pause();
-                        stopwatch.stop();
+                        state.marks = Some(0);
+                        state.browsing = false;
                     }
                 }
+                (KeyCode::Up, KeyEventKind::Press | KeyEventKind::Repeat)
+                    if state.marks.is_some() =>
+                {
+                    state.marks = state.marks.map(|i| i.saturating_sub(1));
+                }
+                (KeyCode::Down, KeyEventKind::Press | KeyEventKind::Repeat)
+                    if state.marks.is_some() =>
+                {
+                    let last = state.info.metadata.bookmarks.len() - 1;
+                    state.marks = state.marks.map(|i| (i + 1).min(last));
+                }
+                (KeyCode::Enter, KeyEventKind::Press) if state.marks.is_some() => {
+                    let position = state.info.metadata.bookmarks[state.marks.unwrap()].position;
+                    state.marks = None;
+                    seek!(position);
+                }
+                (KeyCode::Esc, KeyEventKind::Press) if state.marks.is_some() => state.marks = None,
+
+                (KeyCode::Char('b'), KeyEventKind::Press) if state.browser.is_some() => {
+                    state.browsing = !state.browsing;
+                    state.focus = None;
+                    state.marks = None;
+                }
+                (KeyCode::Up, KeyEventKind::Press | KeyEventKind::Repeat) if state.browsing => {
+                    state.browser.as_mut().unwrap().up();
+                }
+                (KeyCode::Down, KeyEventKind::Press | KeyEventKind::Repeat) if state.browsing => {
+                    state.browser.as_mut().unwrap().down();
+                }
+                (KeyCode::Right, KeyEventKind::Press) if state.browsing => {
+                    state.browser.as_mut().unwrap().expand();
+                }
+                (KeyCode::Left, KeyEventKind::Press) if state.browsing => {
+                    state.browser.as_mut().unwrap().collapse();
+                }
+                (KeyCode::Enter, KeyEventKind::Press) if state.browsing => {
+                    let files = state.browser.as_ref().unwrap().selection();
+                    state.message = Some(tr!("Added {} songs", queue.add(&files)));
+                    state.info = InfoState::read(&queue);
+                }
+                (KeyCode::Esc, KeyEventKind::Press) if state.browsing => state.browsing = false,
+
+                (KeyCode::Char('l'), KeyEventKind::Press | KeyEventKind::Repeat) => {
+                    seek!(position!() + SEEK_STEP)
+                }
+                (KeyCode::Char('h'), KeyEventKind::Press | KeyEventKind::Repeat) => {
+                    seek!(position!().saturating_sub(SEEK_STEP))
+                }
+                (KeyCode::Char(c @ '0'..='9'), KeyEventKind::Press) => {
+                    let tenths = c.to_digit(10).unwrap();
+                    seek!(state.controls.playback.duration * tenths / 10)
+                }
+
+                (KeyCode::Right, KeyEventKind::Press | KeyEventKind::Repeat)
+                    if modifiers.contains(KeyModifiers::SHIFT) =>
+                {
+                    seek!(position!() + SEEK_STEP)
+                }
+                (KeyCode::Left, KeyEventKind::Press | KeyEventKind::Repeat)
+                    if modifiers.contains(KeyModifiers::SHIFT) =>
+                {
+                    seek!(position!().saturating_sub(SEEK_STEP))
+                }
 
                 (KeyCode::Right, KeyEventKind::Press) => {
                     if !queue.next() {
@@ -227,12 +931,153 @@ pub fn main(files: Vec<String>) -> crate::Result {
                     reset!();
                 }
                 (KeyCode::Left, KeyEventKind::Press) => {
-                    if stopwatch.time() < Duration::from_secs(2) {
+                    if position!() < Duration::from_secs(2) {
                         queue.prev();
                     }
                     reset!();
                 }
 
+                (KeyCode::Char(key @ ('+' | '-' | 'f')), KeyEventKind::Press) => {
+                    let result = edit_current(&mut queue, &decoded, |l| match key {
+                        '+' => l.rating = Some(l.rating.map_or(1, |r| (r + 1).min(5))),
+                        '-' => {
+                            l.rating = l.rating.and_then(|r| r.checked_sub(1)).filter(|&r| r > 0)
+                        }
+                        _ => l.favorite = !l.favorite,
+                    });
+                    if let Err(e) = result {
+                        state.message = Some(e.to_string());
+                    }
+                    state.info = InfoState::read(&queue);
+                }
+
+                (KeyCode::Char('e'), KeyEventKind::Press) => {
+                    let enabled = !state.controls.modes.crossfeed;
+                    crossfeed.store(enabled, Ordering::Relaxed);
+                    state.controls.modes.crossfeed = enabled;
+                    state.message = Some(if enabled {
+                        tr!("Crossfeed on")
+                    } else {
+                        tr!("Crossfeed off")
+                    });
+                }
+                (KeyCode::Char('v'), KeyEventKind::Press) => {
+                    state.visualizer.mode = match state.visualizer.mode {
+                        Visualization::Off => Visualization::Waveform,
+                        Visualization::Waveform => Visualization::Spectrum,
+                        Visualization::Spectrum => Visualization::Off,
+                    };
+                    state.visualizer.levels.clear();
+                }
+
+                (KeyCode::Char('s'), KeyEventKind::Press) => {
+                    queue.cycle_shuffle();
+                    decoded.prefetch(queue.current().path, queue.upcoming());
+                    state.controls.modes.shuffle = queue.shuffle;
+                    state.info = InfoState::read(&queue);
+                }
+                (KeyCode::Char('r'), KeyEventKind::Press) => {
+                    queue.cycle_repeat();
+                    decoded.prefetch(queue.current().path, queue.upcoming());
+                    state.controls.modes.repeat = queue.repeat;
+                }
+
+                (KeyCode::Char(':'), KeyEventKind::Press) => state.prompt = Some(String::new()),
+                (KeyCode::Char('c'), KeyEventKind::Press) => {
+                    let song = state.focus.unwrap_or(queue.current().idx);
+                    let albums = &state.info.queue.albums;
+                    if let Some((header, _)) = albums.iter().find(|(_, r)| r.contains(&song)) {
+                        if !state.collapsed.remove(header) {
+                            state.collapsed.insert(header.clone());
+                        }
+                    }
+                }
+                (KeyCode::Char(key @ ('[' | ']')), KeyEventKind::Press) => {
+                    let albums = &state.info.queue.albums;
+                    let current = queue.current().idx;
+                    let album = albums
+                        .iter()
+                        .position(|(_, r)| r.contains(&current))
+                        .unwrap();
+                    let target = if key == ']' {
+                        albums.get(album + 1)
+                    } else {
+                        album.checked_sub(1).and_then(|a| albums.get(a))
+                    };
+                    let Some((_, songs)) = target else {
+                        continue;
+                    };
+                    queue.jump(songs.start);
+                    reset!();
+                }
+                (KeyCode::Char('/'), KeyEventKind::Press) => {
+                    state.search = Some(SearchState {
+                        query: String::new(),
+                        selected: 0,
+                    });
+                }
+                (KeyCode::Tab, KeyEventKind::Press) => {
+                    state.browsing = false;
+                    state.focus = match state.focus {
+                        Some(_) => None,
+                        None => Some(queue.current().idx),
+                    };
+                }
+
+                (KeyCode::Up, KeyEventKind::Press | KeyEventKind::Repeat)
+                    if state.focus.is_some() =>
+                {
+                    state.focus = state.focus.map(|i| i.saturating_sub(1));
+                }
+                (KeyCode::Down, KeyEventKind::Press | KeyEventKind::Repeat)
+                    if state.focus.is_some() =>
+                {
+                    state.focus = state.focus.map(|i| (i + 1).min(queue.songs.len() - 1));
+                }
+                (KeyCode::Enter, KeyEventKind::Press)
+                    if resume.is_some() && state.focus.is_none() =>
+                {
+                    seek!(resume.take().unwrap());
+                }
+                (KeyCode::Enter, KeyEventKind::Press) if state.focus.is_some() => {
+                    queue.jump(state.focus.unwrap());
+                    reset!();
+                }
+                (KeyCode::Char('d') | KeyCode::Delete, KeyEventKind::Press)
+                    if state.focus.is_some() =>
+                {
+                    let selected = state.focus.unwrap();
+                    let current = queue.current().idx == selected;
+                    if !queue.remove(selected) {
+                        continue;
+                    }
+                    state.focus = Some(selected.min(queue.songs.len() - 1));
+                    if current {
+                        reset!();
+                    } else {
+                        state.info = InfoState::read(&queue);
+                    }
+                }
+                (KeyCode::Char('K'), KeyEventKind::Press | KeyEventKind::Repeat)
+                    if state.focus.is_some_and(|i| i > 0) =>
+                {
+                    let selected = state.focus.unwrap();
+                    queue.swap(selected, selected - 1);
+                    state.focus = Some(selected - 1);
+                    state.info = InfoState::read(&queue);
+                }
+                (KeyCode::Char('J'), KeyEventKind::Press | KeyEventKind::Repeat)
+                    if state.focus.is_some_and(|i| i + 1 < queue.songs.len()) =>
+                {
+                    let selected = state.focus.unwrap();
+                    queue.swap(selected, selected + 1);
+                    state.focus = Some(selected + 1);
+                    state.info = InfoState::read(&queue);
+                }
+                (KeyCode::Esc, KeyEventKind::Press) if state.focus.is_some() => {
+                    state.focus = None;
+                }
+
                 (KeyCode::Up, KeyEventKind::Press | KeyEventKind::Repeat) => {
                     if state.controls.volume.0 < 100 {
                         state.controls.volume.0 += 1;
@@ -246,23 +1091,68 @@ pub fn main(files: Vec<String>) -> crate::Result {
                     }
                 }
 
-                (KeyCode::Esc | KeyCode::Char('q'), _) => break,
+                (KeyCode::Esc | KeyCode::Char('q'), KeyEventKind::Press) => break,
                 _ => continue,
             },
 
+            // Not resumed on wake, so that audio doesn't start blaring unexpectedly.
+            // Waking up is also handled in case the sleep notification was missed
+            Event::Sleep | Event::Wake => {
+                set_playing!(false);
+                state.controls.playback.played = position!();
+            }
+
+            Event::Media(command) => match command {
+                media::Command::Play => set_playing!(true),
+                media::Command::Pause => set_playing!(false),
+                media::Command::Toggle => set_playing!(!state.controls.playback.playing),
+                media::Command::Stop => {
+                    set_playing!(false);
+                    seek!(Duration::ZERO);
+                }
+                media::Command::Next => {
+                    if queue.next() {
+                        reset!();
+                    }
+                }
+                media::Command::Previous => {
+                    queue.prev();
+                    reset!();
+                }
+                media::Command::Forward(step) => seek!(position!() + step),
+                media::Command::Backward(step) => seek!(position!().saturating_sub(step)),
+                media::Command::SetPosition(position) => seek!(position),
+            },
+
             Event::Tick => {
-                state.controls.playback.played = stopwatch.time();
-                if state.controls.playback.played >= state.controls.playback.duration
-                    && state.controls.playback.playing
+                ticks += 1;
+                if ticks % DEVICE_CHECK_TICKS == 0
+                    && device_name
+                        .as_deref()
+                        .is_some_and(|n| !device::connected(n))
                 {
-                    if queue.next() {
+                    reconnect!();
+                }
+
+                // Derived from the samples actually played,
+                // so pausing or buffering doesn't make it drift
+                state.controls.playback.played = position!();
+                if state.visualizer.mode != Visualization::Off {
+                    let lilac = decoded.get(&loaded)?;
+                    state
+                        .visualizer
+                        .update(&lilac, state.controls.playback.played);
+                }
+                if state.controls.playback.playing && sink.empty() {
+                    hook(&hooks.on_track_end, &queue);
+                    if queue.advance() {
                         reset!();
                     } else {
-                        while queue.prev() {}
+                        hook(&hooks.on_queue_end, &queue);
+                        queue.rewind();
 
                         state.controls.playback.playing = false;
                         sink.pause();
-                        stopwatch.stop();
                         reset!();
                     }
                 }
@@ -270,20 +1160,31 @@ pub fn main(files: Vec<String>) -> crate::Result {
         }
     }
 
-    terminal.show_cursor().into_diagnostic()?;
-    terminal
-        .backend_mut()
-        .execute(LeaveAlternateScreen)
-        .into_diagnostic()?;
+    if let Some(mut terminal) = terminal {
+        terminal.show_cursor().into_diagnostic()?;
+        terminal
+            .backend_mut()
+            .execute(LeaveAlternateScreen)
+            .into_diagnostic()?;
+    }
 
     crossterm::terminal::disable_raw_mode().into_diagnostic()?;
 
+    leave!();
+    memory.volume = Some(state.controls.volume.0);
+    memory.save()?;
+
     crate::OK
 }
 
 enum Event<T> {
     Input(T),
     Tick,
+    /// The machine is about to sleep
+    Sleep,
+    /// The machine woke up
+    Wake,
+    Media(media::Command),
 }
 
 fn poll(tx: Sender<Event<KeyEvent>>) -> crate::Result {
@@ -291,18 +1192,16 @@ fn poll(tx: Sender<Event<KeyEvent>>) -> crate::Result {
     loop {
         if event::poll(TICK_RATE - last_tick.elapsed()).into_diagnostic()? {
             if let TerminalEvent::Key(k) = event::read().into_diagnostic()? {
-                tx.send(Event::Input(k)).into_diagnostic()?;
-                if let KeyEvent {
-                    code: KeyCode::Esc | KeyCode::Char('q'),
-                    ..
-                } = k
-                {
+                // The player is gone once it stops receiving
+                if tx.send(Event::Input(k)).is_err() {
                     break crate::OK;
                 }
             }
         }
         if last_tick.elapsed() >= TICK_RATE {
-            tx.send(Event::Tick).into_diagnostic()?;
+            if tx.send(Event::Tick).is_err() {
+                break crate::OK;
+            }
             last_tick = Instant::now();
         }
     }
@@ -311,10 +1210,31 @@ fn poll(tx: Sender<Event<KeyEvent>>) -> crate::Result {
 struct State {
     controls: ControlsState,
     info: InfoState,
+    /// Selected song when the queue pane is focused
+    focus: Option<usize>,
+    /// Command being typed
+    prompt: Option<String>,
+    /// Result of the last command, shown until the next key press
+    message: Option<String>,
+    /// Filter applied to the queue pane
+    search: Option<SearchState>,
+    /// Headers of the albums folded in the queue pane
+    collapsed: HashSet<String>,
+    browser: Option<Browser>,
+    /// Whether the browser replaces the metadata pane
+    browsing: bool,
+    /// Selected bookmark when the bookmark menu replaces the metadata pane
+    marks: Option<usize>,
+    /// Start and end of the A-B selection, for exporting part of the current song
+    selection: Option<(Duration, Option<Duration>)>,
+    /// Sample rate of the output device, when it is known
+    output_rate: Option<u32>,
+    visualizer: VisualizerState,
 }
 struct ControlsState {
     playback: PlaybackState,
     volume: VolumeState,
+    modes: ModesState,
 }
 struct PlaybackState {
     playing: bool,
@@ -322,6 +1242,51 @@ struct PlaybackState {
     duration: Duration,
 }
 struct VolumeState(u16);
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Visualization {
+    Off,
+    /// Loudness of the upcoming few seconds
+    Waveform,
+    /// Frequencies of what is currently playing
+    Spectrum,
+}
+struct VisualizerState {
+    mode: Visualization,
+    /// From 0 to `VISUALIZER_SCALE`
+    levels: Vec<u64>,
+}
+
+impl VisualizerState {
+    fn update(&mut self, lilac: &Lilac, position: Duration) {
+        let scale = |level: f32| (level.clamp(0.0, 1.0) * VISUALIZER_SCALE as f32) as u64;
+        self.levels = match self.mode {
+            Visualization::Off => Vec::new(),
+            Visualization::Waveform => {
+                let peaks = lilac
+                    .slice(position..position + WAVEFORM_WINDOW)
+                    .peaks(WAVEFORM_BUCKETS);
+                (0..WAVEFORM_BUCKETS)
+                    .map(|b| {
+                        let peak = peaks.iter().map(|c| c[b].max.max(-c[b].min));
+                        scale(peak.fold(0.0, f32::max))
+                    })
+                    .collect()
+            }
+            Visualization::Spectrum => lilac
+                .spectrum(position, SPECTRUM_BANDS)
+                .into_iter()
+                // From -60 dB to full scale
+                .map(|a| scale(1.0 + (20.0 * a.max(f32::MIN_POSITIVE).log10()) / 60.0))
+                .collect(),
+        };
+    }
+}
+struct ModesState {
+    shuffle: Shuffle,
+    repeat: Repeat,
+    speed: f32,
+    crossfeed: bool,
+}
 struct InfoState {
     metadata: MetadataState,
     queue: QueueState,
@@ -330,6 +1295,9 @@ struct MetadataState {
     title: String,
     artist: String,
     album: String,
+    rating: Option<u8>,
+    favorite: bool,
+    bookmarks: Vec<Bookmark>,
 
     channels: u16,
     sample_rate: u32,
@@ -337,16 +1305,38 @@ struct MetadataState {
 }
 struct QueueState {
     queue: Vec<String>,
+    labels: Vec<String>,
+    albums: Vec<(String, Range<usize>)>,
     current: usize,
 }
+struct SearchState {
+    query: String,
+    /// Position in the results
+    selected: usize,
+}
+
+impl QueueState {
+    /// Indices of the songs whose label contains the query, ignoring case
+    fn search(&self, query: &str) -> Vec<usize> {
+        let query = query.to_lowercase();
+        self.labels
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| l.to_lowercase().contains(&query))
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
 
 impl InfoState {
     fn read(q: &Queue) -> Self {
-        let QueueEl { idx, lilac } = q.current();
+        let QueueEl { idx, lilac, .. } = q.current();
         Self {
             metadata: MetadataState::read(lilac),
             queue: QueueState {
-                queue: q.files().into_iter().map(ToOwned::to_owned).collect(),
+                queue: q.files(),
+                labels: q.labels(),
+                albums: q.albums(),
                 current: idx,
             },
         }
@@ -358,6 +1348,9 @@ impl MetadataState {
             title: l.title().to_owned(),
             artist: l.artist().to_owned(),
             album: l.album().to_owned(),
+            rating: l.rating,
+            favorite: l.favorite,
+            bookmarks: l.bookmarks.clone(),
             channels: l.channels,
             sample_rate: l.sample_rate,
             bit_depth: l.bit_depth,
@@ -365,29 +1358,159 @@ impl MetadataState {
     }
 }
 
-fn draw(f: &mut Frame, s: &State) {
+/// Plain text replacement for the full-screen interface,
+/// printing a line whenever something changes
+struct Announcer {
+    track: Option<usize>,
+    playing: bool,
+    volume: u16,
+    shuffle: Shuffle,
+    repeat: Repeat,
+}
+
+impl Default for Announcer {
+    fn default() -> Self {
+        Self {
+            track: None,
+            playing: false,
+            volume: 100,
+            shuffle: Shuffle::Off,
+            repeat: Repeat::Off,
+        }
+    }
+}
+
+impl Announcer {
+    fn announce(&mut self, s: &State) -> io::Result<()> {
+        // Raw mode doesn't translate newlines into carriage returns
+        let mut out = io::stdout().lock();
+
+        if self.track != Some(s.info.queue.current) {
+            self.track = Some(s.info.queue.current);
+            let m = &s.info.metadata;
+            write!(
+                out,
+                "{}\r\n",
+                tr!("Now playing {} by {} on {}", m.title, m.artist, m.album)
+            )?;
+        }
+        if self.playing != s.controls.playback.playing {
+            self.playing = s.controls.playback.playing;
+            let state = if self.playing {
+                tr!("Playing")
+            } else {
+                tr!("Paused")
+            };
+            write!(out, "{}\r\n", state)?;
+        }
+        if self.volume != s.controls.volume.0 {
+            self.volume = s.controls.volume.0;
+            write!(out, "{}\r\n", tr!("Volume {}", self.volume))?;
+        }
+        if let Some(message) = &s.message {
+            write!(out, "{}\r\n", message)?;
+        }
+        if self.shuffle != s.controls.modes.shuffle {
+            self.shuffle = s.controls.modes.shuffle;
+            let state = match self.shuffle {
+                Shuffle::Off => tr!("Shuffle off"),
+                Shuffle::Songs => tr!("Shuffle on"),
+                Shuffle::Albums => tr!("Shuffle albums"),
+            };
+            write!(out, "{}\r\n", state)?;
+        }
+        if self.repeat != s.controls.modes.repeat {
+            self.repeat = s.controls.modes.repeat;
+            let state = match self.repeat {
+                Repeat::Off => tr!("Repeat off"),
+                Repeat::All => tr!("Repeat all"),
+                Repeat::One => tr!("Repeat one"),
+            };
+            write!(out, "{}\r\n", state)?;
+        }
+
+        out.flush()
+    }
+}
+
+fn draw(f: &mut Frame, s: &State, t: &Theme) {
+    f.render_widget(widgets::Block::default().style(t.text), f.area());
+
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([Constraint::Min(5), Constraint::Length(1)].as_ref())
         .vertical_margin(2)
         .split(f.area());
 
-    draw_controls(f, &s.controls, chunks[1]);
-    draw_info(f, &s.info, chunks[0]);
+    if let Some(prompt) = &s.prompt {
+        draw_line(f, &format!(":{}", prompt), t, chunks[1]);
+    } else if let Some(search) = &s.search {
+        draw_line(f, &format!("/{}", search.query), t, chunks[1]);
+    } else if let Some(message) = &s.message {
+        draw_line(f, message, t, chunks[1]);
+    } else {
+        draw_controls(f, &s.controls, t, chunks[1]);
+    }
+    draw_info(f, s, t, chunks[0]);
+}
+
+fn draw_line(f: &mut Frame, text: &str, t: &Theme, area: Rect) {
+    let area = Layout::default()
+        .constraints([Constraint::Min(1)].as_ref())
+        .horizontal_margin(4)
+        .split(area)[0];
+    let text = ratatui::text::Text::styled(text, t.emphasis);
+    f.render_widget(widgets::Paragraph::new(text), area);
 }
 
-fn draw_controls(f: &mut Frame, s: &ControlsState, area: Rect) {
+fn draw_controls(f: &mut Frame, s: &ControlsState, t: &Theme, area: Rect) {
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Percentage(75), Constraint::Percentage(25)].as_ref())
+        .constraints(
+            [
+                Constraint::Percentage(65),
+                Constraint::Percentage(10),
+                Constraint::Percentage(25),
+            ]
+            .as_ref(),
+        )
         .horizontal_margin(2)
         .split(area);
 
-    draw_playback(f, &s.playback, chunks[0]);
-    draw_volume(f, &s.volume, chunks[1]);
+    draw_playback(f, &s.playback, t, chunks[0]);
+    draw_modes(f, &s.modes, t, chunks[1]);
+    draw_volume(f, &s.volume, t, chunks[2]);
 }
 
-fn draw_playback(f: &mut Frame, s: &PlaybackState, area: Rect) {
+fn draw_modes(f: &mut Frame, s: &ModesState, t: &Theme, area: Rect) {
+    let shuffle = match s.shuffle {
+        Shuffle::Off => String::new(),
+        Shuffle::Songs => tr!("SHUF"),
+        Shuffle::Albums => tr!("ALBM"),
+    };
+    let repeat = match s.repeat {
+        Repeat::Off => String::new(),
+        Repeat::All => tr!("REP"),
+        Repeat::One => tr!("REP1"),
+    };
+    let crossfeed = if s.crossfeed {
+        tr!("XFD")
+    } else {
+        String::new()
+    };
+    let speed = if s.speed == 1.0 {
+        String::new()
+    } else {
+        format!("{}x", s.speed)
+    };
+    let text = ratatui::text::Text::styled(
+        format!("{:<5}{:<5}{:<5}{}", shuffle, repeat, crossfeed, speed),
+        t.emphasis,
+    );
+    f.render_widget(widgets::Paragraph::new(text), area);
+}
+
+fn draw_playback(f: &mut Frame, s: &PlaybackState, t: &Theme, area: Rect) {
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints(
@@ -401,25 +1524,29 @@ fn draw_playback(f: &mut Frame, s: &PlaybackState, area: Rect) {
         .horizontal_margin(2)
         .split(area);
 
-    let play_pause_text =
-        ratatui::text::Text::styled(if s.playing { "PLAY  " } else { "PAUSE " }, BOLD);
+    let play_pause_text = ratatui::text::Text::styled(
+        format!("{:<6}", if s.playing { tr!("PLAY") } else { tr!("PAUSE") }),
+        t.emphasis,
+    );
     let play_pause = widgets::Paragraph::new(play_pause_text).wrap(Wrap { trim: true });
     f.render_widget(play_pause, chunks[0]);
 
     let timeline = widgets::Gauge::default()
         .ratio((s.played.as_secs_f64() / s.duration.as_secs_f64()).min(1.0))
         .label("")
-        .style(WHITE);
+        .style(t.gauge);
     f.render_widget(timeline, chunks[1]);
 
     let played = s.played.as_secs();
-    let timestamp_text =
-        ratatui::text::Text::styled(format!(" {:02}:{:02}", played / 60, played % 60), BOLD);
+    let timestamp_text = ratatui::text::Text::styled(
+        format!(" {:02}:{:02}", played / 60, played % 60),
+        t.emphasis,
+    );
     let timestamp = widgets::Paragraph::new(timestamp_text);
     f.render_widget(timestamp, chunks[2]);
 }
 
-fn draw_volume(f: &mut Frame, s: &VolumeState, area: Rect) {
+fn draw_volume(f: &mut Frame, s: &VolumeState, t: &Theme, area: Rect) {
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Min(1), Constraint::Length(4)].as_ref())
@@ -429,54 +1556,375 @@ fn draw_volume(f: &mut Frame, s: &VolumeState, area: Rect) {
     let gauge = widgets::Gauge::default()
         .percent(s.0)
         .label("")
-        .style(WHITE);
+        .style(t.gauge);
     f.render_widget(gauge, chunks[0]);
 
-    let level_text = ratatui::text::Text::styled(format!(" {:3}", s.0), BOLD);
+    let level_text = ratatui::text::Text::styled(format!(" {:3}", s.0), t.emphasis);
     let level = widgets::Paragraph::new(level_text);
     f.render_widget(level, chunks[1]);
 }
 
-fn draw_info(f: &mut Frame, s: &InfoState, area: Rect) {
+fn draw_info(f: &mut Frame, s: &State, t: &Theme, area: Rect) {
     let chunks = Layout::default()
         .direction(Direction::Horizontal)
         .constraints([Constraint::Percentage(75), Constraint::Percentage(25)].as_ref())
         .horizontal_margin(4)
         .split(area);
 
-    draw_metadata(f, &s.metadata, chunks[0]);
-    draw_queue(f, &s.queue, chunks[1]);
+    match &s.browser {
+        Some(browser) if s.browsing => draw_browser(f, browser, t, chunks[0]),
+        _ if s.marks.is_some() => draw_bookmarks(
+            f,
+            &s.info.metadata.bookmarks,
+            s.marks.unwrap(),
+            t,
+            chunks[0],
+        ),
+        _ if s.visualizer.mode != Visualization::Off => {
+            let left = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Length(8), Constraint::Min(3)].as_ref())
+                .split(chunks[0]);
+            draw_metadata(f, &s.info.metadata, s.output_rate, t, left[0]);
+            draw_visualizer(f, &s.visualizer, t, left[1]);
+        }
+        _ => draw_metadata(f, &s.info.metadata, s.output_rate, t, chunks[0]),
+    }
+    match &s.search {
+        Some(search) => draw_results(f, &s.info.queue, search, t, chunks[1]),
+        None => draw_queue(f, &s.info.queue, s.focus, &s.collapsed, t, chunks[1]),
+    }
+}
+
+fn draw_results(f: &mut Frame, s: &QueueState, search: &SearchState, t: &Theme, area: Rect) {
+    let query = search.query.to_lowercase();
+    let items = s.search(&search.query).into_iter().map(|i| {
+        let label = &s.labels[i];
+        // Lowercasing can shift byte offsets outside of ASCII, only highlight when they line up
+        let lower = label.to_lowercase();
+        let range = lower
+            .find(&query)
+            .map(|start| start..start + query.len())
+            .filter(|r| !r.is_empty() && label.get(r.clone()).is_some());
+        match range {
+            Some(std::ops::Range { start, end }) => Line::from(vec![
+                Span::raw(&label[..start]),
+                Span::styled(&label[start..end], t.emphasis),
+                Span::raw(&label[end..]),
+            ]),
+            _ => Line::raw(label.as_str()),
+        }
+    });
+    let mut state = widgets::ListState::default();
+    state.select(Some(search.selected));
+    f.render_stateful_widget(
+        widgets::List::new(items).highlight_style(t.highlight),
+        area,
+        &mut state,
+    );
+}
+
+fn draw_visualizer(f: &mut Frame, s: &VisualizerState, t: &Theme, area: Rect) {
+    if s.levels.is_empty() {
+        return;
+    }
+    // Stretched to fill the width, as the sparkline draws one level per column
+    let width = area.width as usize;
+    let levels: Vec<u64> = (0..width)
+        .map(|x| s.levels[x * s.levels.len() / width])
+        .collect();
+    let sparkline = widgets::Sparkline::default()
+        .data(&levels)
+        .max(VISUALIZER_SCALE)
+        .style(t.gauge);
+    f.render_widget(sparkline, area);
+}
+
+fn draw_bookmarks(f: &mut Frame, bookmarks: &[Bookmark], selected: usize, t: &Theme, area: Rect) {
+    let items = bookmarks
+        .iter()
+        .map(|b| format!("{}  {}", format_time(b.position), b.name));
+    let mut state = widgets::ListState::default();
+    state.select(Some(selected));
+    f.render_stateful_widget(
+        widgets::List::new(items).highlight_style(t.highlight),
+        area,
+        &mut state,
+    );
+}
+
+/// Source playing a song, resampled to the rate of the output device unless disabled
+fn song_source(
+    lilac: Arc<Lilac>,
+    crossfeed: &Arc<AtomicBool>,
+    output_rate: Option<u32>,
+    resampler: config::Resampler,
+) -> impl Source<Item = f32> {
+    let source = CrossfeedSource::new(lilac.source_shared(), crossfeed.clone());
+    match (resampler.interpolation(), output_rate) {
+        (Some(interpolation), Some(rate)) => ResampledSource::new(source, rate, interpolation),
+        // Sources already at the requested rate are passed through
+        _ => {
+            let rate = source.sample_rate();
+            ResampledSource::new(source, rate, Interpolation::default())
+        }
+    }
+}
+
+fn format_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!("{:02}:{:02}", secs / 60, secs % 60)
+}
+
+fn draw_browser(f: &mut Frame, b: &Browser, t: &Theme, area: Rect) {
+    let (lines, selected) = b.lines();
+    let mut state = widgets::ListState::default();
+    state.select(Some(selected));
+    f.render_stateful_widget(
+        widgets::List::new(lines).highlight_style(t.highlight),
+        area,
+        &mut state,
+    );
 }
 
-fn draw_metadata(f: &mut Frame, s: &MetadataState, area: Rect) {
-    let text = vec![
-        Line::styled(&s.title, BOLD),
+fn draw_metadata(
+    f: &mut Frame,
+    s: &MetadataState,
+    output_rate: Option<u32>,
+    t: &Theme,
+    area: Rect,
+) {
+    let mut text = vec![
+        Line::styled(&s.title, t.emphasis),
         Line::raw(format!("\n{}", s.artist)),
         Line::raw(format!("\n{}", s.album)),
+        Line::styled(
+            format!(
+                "\n{}{} {}",
+                "\u{2605}".repeat(s.rating.unwrap_or(0) as usize),
+                "\u{2606}".repeat(5 - s.rating.unwrap_or(0) as usize),
+                if s.favorite { "\u{2665}" } else { "" },
+            ),
+            t.emphasis,
+        ),
         Line::raw(format!(
-            "\n\n{} bits {} at {} Hz",
-            s.bit_depth,
-            match s.channels {
-                1 => "mono",
-                2 => "stereo",
-                _ => "polyphonic",
-            },
-            s.sample_rate,
+            "\n\n{}",
+            tr!(
+                "{} bits {} at {} Hz",
+                s.bit_depth,
+                match s.channels {
+                    1 => tr!("mono"),
+                    2 => tr!("stereo"),
+                    _ => tr!("polyphonic"),
+                },
+                s.sample_rate,
+            )
         )),
     ];
+    if let Some(rate) = output_rate {
+        text.push(Line::raw(tr!("Output at {} Hz", rate)));
+    }
     f.render_widget(
         widgets::Paragraph::new(text).wrap(Wrap { trim: true }),
         area,
     );
 }
 
-fn draw_queue(f: &mut Frame, s: &QueueState, area: Rect) {
-    let items = s.queue.iter().map(ratatui::text::Text::raw);
+fn draw_queue(
+    f: &mut Frame,
+    s: &QueueState,
+    focus: Option<usize>,
+    collapsed: &HashSet<String>,
+    t: &Theme,
+    area: Rect,
+) {
+    let selected = focus.unwrap_or(s.current);
+    let mut items = Vec::new();
+    let mut selected_row = 0;
+    for (header, songs) in &s.albums {
+        let folded = collapsed.contains(header);
+        // Headers are only worth showing when there are several albums
+        if s.albums.len() > 1 {
+            if folded && songs.contains(&selected) {
+                selected_row = items.len();
+            }
+            let marker = if folded { "+" } else { "-" };
+            items.push(ratatui::text::Text::styled(
+                format!("{} {}", marker, header),
+                t.emphasis,
+            ));
+        }
+        if folded && s.albums.len() > 1 {
+            continue;
+        }
+
+        for i in songs.clone() {
+            if i == selected {
+                selected_row = items.len();
+            }
+            let name = &s.queue[i];
+            // The highlight follows the selection, so mark the current song
+            items.push(if focus.is_some() && i == s.current {
+                ratatui::text::Text::styled(format!("> {}", name), t.emphasis)
+            } else {
+                ratatui::text::Text::raw(name.as_str())
+            });
+        }
+    }
     let mut state = widgets::ListState::default();
-    state.select(Some(s.current));
+    state.select(Some(selected_row));
     f.render_stateful_widget(
-        widgets::List::new(items).highlight_style(BOLD),
+        widgets::List::new(items).highlight_style(t.highlight),
         area,
         &mut state,
     );
 }
+
+/// Complete A-B selection, or else the part between the bookmarks around a position
+fn segment(state: &State, position: Duration) -> Option<Range<Duration>> {
+    if let Some((a, Some(b))) = state.selection {
+        return Some(a..b);
+    }
+
+    let bookmarks = &state.info.metadata.bookmarks;
+    if bookmarks.is_empty() {
+        return None;
+    }
+    let next = bookmarks
+        .iter()
+        .position(|b| b.position > position)
+        .unwrap_or(bookmarks.len());
+    let start = match next {
+        0 => Duration::ZERO,
+        i => bookmarks[i - 1].position,
+    };
+    let end = bookmarks.get(next).map_or(Duration::MAX, |b| b.position);
+    Some(start..end)
+}
+
+/// Writes part of the current song to a LILAC or WAV file, depending on the extension
+///
+/// Without a path, the part is written next to the song with its times in the name.
+fn export(
+    queue: &Queue,
+    decoded: &Decoded,
+    segment: Range<Duration>,
+    path: &str,
+) -> miette::Result<PathBuf> {
+    let current = queue.current();
+    let lilac = decoded.get(current.path)?;
+    let end = segment.end.min(lilac.duration());
+
+    let path = match path {
+        "" => {
+            let stem = current.path.file_stem().unwrap().to_string_lossy();
+            let times = format!("{}-{}", format_time(segment.start), format_time(end));
+            current
+                .path
+                .with_file_name(format!("{} {}.lilac", stem, times.replace(':', ".")))
+        }
+        path => PathBuf::from(paths::expand_home(path)),
+    };
+    transcode::write(&lilac.slice(segment.start..end), &path)?;
+    Ok(path)
+}
+
+/// Edits the rating, favorite flag or bookmarks of the current song and saves it to its file
+fn edit_current(
+    queue: &mut Queue,
+    decoded: &Decoded,
+    edit: impl FnOnce(&mut Lilac),
+) -> crate::Result {
+    let idx = queue.current().idx;
+    let (header, path) = &mut queue.songs[idx];
+    if !is_lilac(path) {
+        return Err(miette!("Only LILAC files can be edited"));
+    }
+    // Headers don't hold any samples, so this copy is cheap
+    let header = Arc::make_mut(header);
+    edit(header);
+
+    let mut lilac = Lilac::clone(&decoded.get(path)?);
+    lilac.rating = header.rating;
+    lilac.favorite = header.favorite;
+    lilac.bookmarks = header.bookmarks.clone();
+
+    // Written next to the file first so that a failure never leaves it truncated
+    let part = transcode::part_path(path);
+    if let Err(e) = lilac.write_file(&part) {
+        fs::remove_file(&part).ok();
+        return Err(e.into());
+    }
+    fs::rename(&part, &*path).into_diagnostic()
+}
+
+/// Runs a command typed at the prompt, returning a message to show
+fn command(
+    line: &str,
+    queue: &mut Queue,
+    decoded: &Decoded,
+    memory: &config::State,
+    position: Duration,
+    segment: Option<Range<Duration>>,
+) -> Option<String> {
+    let line = line.trim();
+    let (name, argument) = line.split_once(' ').unwrap_or((line, ""));
+    match name {
+        "" => None,
+        "add" => {
+            let pattern = paths::expand_home(argument.trim());
+            let files = match glob::glob(&pattern) {
+                Ok(files) => files.filter_map(Result::ok),
+                Err(e) => return Some(e.to_string()),
+            };
+            let files = match playlist::expand(files) {
+                Ok(files) => files,
+                Err(e) => return Some(e.to_string()),
+            };
+            Some(tr!("Added {} songs", queue.add(&files)))
+        }
+        "recent" => Some(tr!("Added {} songs", queue.add(&memory.recent))),
+        "save" => {
+            let path = PathBuf::from(paths::expand_home(argument.trim()));
+            if !playlist::is_playlist(&path) {
+                return Some(tr!("Playlists must end in .m3u or .m3u8"));
+            }
+            let songs = queue.order.iter().map(|&i| {
+                let (lilac, path) = &queue.songs[i];
+                (lilac.as_ref(), path.as_path())
+            });
+            Some(match playlist::save(&path, songs) {
+                Ok(()) => tr!("Wrote `{}`", path.display()),
+                Err(e) => e.to_string(),
+            })
+        }
+        "export" => {
+            let Some(segment) = segment else {
+                return Some(tr!("Select a segment with `a` or add bookmarks first"));
+            };
+            Some(match export(queue, decoded, segment, argument.trim()) {
+                Ok(path) => tr!("Wrote `{}`", path.display()),
+                Err(e) => e.to_string(),
+            })
+        }
+        "mark" => {
+            let name = match argument.trim() {
+                "" => format_time(position),
+                name => name.to_owned(),
+            };
+            let bookmark = Bookmark {
+                name: name.clone(),
+                position,
+            };
+            let result = edit_current(queue, decoded, |l| {
+                l.bookmarks.push(bookmark);
+                l.bookmarks.sort_by_key(|b| b.position);
+            });
+            Some(match result {
+                Ok(()) => tr!("Bookmarked {}", name),
+                Err(e) => e.to_string(),
+            })
+        }
+        _ => Some(tr!("Unknown command `{}`", name)),
+    }
+}
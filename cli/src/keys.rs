@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+/// Keys of the interactive player, configured under `[keys]`
+///
+/// Each entry is a single character, the defaults keep working
+/// unless another action is moved onto them.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Keys {
+    pub play_pause: Option<char>,
+    pub seek_forward: Option<char>,
+    pub seek_backward: Option<char>,
+    pub shuffle: Option<char>,
+    pub repeat: Option<char>,
+    pub browse: Option<char>,
+    pub command: Option<char>,
+    pub search: Option<char>,
+    pub fold_album: Option<char>,
+    pub next_album: Option<char>,
+    pub previous_album: Option<char>,
+    pub remove: Option<char>,
+    pub rate_up: Option<char>,
+    pub rate_down: Option<char>,
+    pub favorite: Option<char>,
+    pub visualizer: Option<char>,
+    pub bookmark: Option<char>,
+    pub bookmarks: Option<char>,
+    pub select: Option<char>,
+    pub export: Option<char>,
+    /// Playback speed, on `<` and `>` by default since `[` and `]` jump between albums
+    pub slower: Option<char>,
+    pub faster: Option<char>,
+    pub crossfeed: Option<char>,
+    pub quit: Option<char>,
+}
+
+impl Keys {
+    /// Maps configured keys to the default key of their action
+    pub fn remap(&self) -> HashMap<char, char> {
+        [
+            (self.play_pause, ' '),
+            (self.seek_forward, 'l'),
+            (self.seek_backward, 'h'),
+            (self.shuffle, 's'),
+            (self.repeat, 'r'),
+            (self.browse, 'b'),
+            (self.command, ':'),
+            (self.search, '/'),
+            (self.fold_album, 'c'),
+            (self.next_album, ']'),
+            (self.previous_album, '['),
+            (self.remove, 'd'),
+            (self.rate_up, '+'),
+            (self.rate_down, '-'),
+            (self.favorite, 'f'),
+            (self.visualizer, 'v'),
+            (self.bookmark, 'm'),
+            (self.bookmarks, 'g'),
+            (self.select, 'a'),
+            (self.export, 'x'),
+            (self.slower, '<'),
+            (self.faster, '>'),
+            (self.crossfeed, 'e'),
+            (self.quit, 'q'),
+        ]
+        .into_iter()
+        .filter_map(|(key, default)| Some((key?, default)))
+        .collect()
+    }
+}
@@ -0,0 +1,541 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{fs, io, process};
+
+use clap::{Subcommand, ValueEnum};
+use lilac::Lilac;
+use miette::{miette, Context, IntoDiagnostic};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Prints the files matching a query, one per line
+    ///
+    /// Conditions are written as FIELD OPERATOR VALUE and combined with `and`,
+    /// e.g. `rating>=4 and genre=jazz`.
+    /// Fields are title, artist, album, album-artist, genre, year, track, disc,
+    /// original-year, label, catalog-number, rating, favorite and compilation.
+    /// Operators are =, !=, <, <=, > and >=.
+    /// Text is compared ignoring case, and songs missing a field never match.
+    /// Songs with several genres match if any of them does, or none of them for !=.
+    Query {
+        #[clap(name = "QUERY")]
+        query: String,
+        /// Library directory, searched recursively
+        #[clap(long, name = "DIR", default_value = ".")]
+        dir: PathBuf,
+        /// Print the files in random order instead of by path
+        ///
+        /// With `albums`, albums are in random order
+        /// but the songs of each one stay in sequence.
+        #[clap(long, value_enum, name = "ORDER")]
+        shuffle: Option<Shuffle>,
+    },
+    /// Updates the metadata index of a library
+    ///
+    /// Only files that were added or modified since the last scan are read,
+    /// judging by their size and modification time.
+    /// Queries and the player's browser keep the index up to date as well,
+    /// so this is mostly useful to run ahead of time, e.g. nightly.
+    Scan {
+        /// Library directory, searched recursively
+        #[clap(long, name = "DIR", default_value = ".")]
+        dir: PathBuf,
+    },
+}
+
+pub fn main(command: Command) -> crate::Result {
+    match command {
+        Command::Query {
+            query,
+            dir,
+            shuffle,
+        } => {
+            let conditions = parse(&query)?;
+            let mut matching: Vec<_> = headers(&dir)?
+                .into_iter()
+                .filter(|(lilac, _)| conditions.iter().all(|c| c.matches(lilac)))
+                .collect();
+            match shuffle {
+                Some(Shuffle::Songs) => fastrand::shuffle(&mut matching),
+                Some(Shuffle::Albums) => matching = shuffle_albums(matching),
+                None => {}
+            }
+            for (_, path) in matching {
+                println!("{}", path.display());
+            }
+            crate::OK
+        }
+        Command::Scan { dir } => {
+            let (headers, changes) = index(&dir)?;
+            status!(
+                "{}",
+                tr!(
+                    "{} files, {} added, {} modified, {} removed",
+                    headers.len(),
+                    changes.added,
+                    changes.modified,
+                    changes.removed
+                )
+            );
+            crate::OK
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Shuffle {
+    Songs,
+    Albums,
+}
+
+/// Puts albums in random order, with their songs by disc and track number
+fn shuffle_albums(songs: Vec<(Lilac, PathBuf)>) -> Vec<(Lilac, PathBuf)> {
+    let mut albums: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (lilac, path) in songs {
+        let key = (lilac.album_artist().to_owned(), lilac.album().to_owned());
+        albums.entry(key).or_default().push((lilac, path));
+    }
+
+    let mut albums: Vec<_> = albums.into_values().collect();
+    fastrand::shuffle(&mut albums);
+    for songs in &mut albums {
+        songs.sort_by(|(a, p), (b, q)| (a.disc, a.track, p).cmp(&(b.disc, b.track, q)));
+    }
+    albums.into_iter().flatten().collect()
+}
+
+struct Condition {
+    field: String,
+    operator: &'static str,
+    value: String,
+}
+
+fn parse(query: &str) -> miette::Result<Vec<Condition>> {
+    const FIELDS: &[&str] = &[
+        "title",
+        "artist",
+        "album",
+        "album-artist",
+        "genre",
+        "year",
+        "track",
+        "disc",
+        "original-year",
+        "label",
+        "catalog-number",
+        "rating",
+        "favorite",
+        "compilation",
+    ];
+
+    query
+        .split(" and ")
+        .map(|condition| {
+            let start = condition
+                .find(['=', '!', '<', '>'])
+                .ok_or_else(|| miette!("Missing operator in `{}`", condition.trim()))?;
+            let (field, rest) = condition.split_at(start);
+            // Longest operators first so that `<=` isn't read as `<`
+            let operator = ["<=", ">=", "!=", "=", "<", ">"]
+                .into_iter()
+                .find(|o| rest.starts_with(o))
+                .ok_or_else(|| miette!("Invalid operator in `{}`", condition.trim()))?;
+
+            let field = field.trim().to_lowercase();
+            if !FIELDS.contains(&field.as_str()) {
+                return Err(miette!(
+                    "Unknown field `{}`, available fields are: {}",
+                    field,
+                    FIELDS.join(", ")
+                ));
+            }
+            Ok(Condition {
+                field,
+                operator,
+                value: rest[operator.len()..].trim().to_owned(),
+            })
+        })
+        .collect()
+}
+
+impl Condition {
+    fn matches(&self, lilac: &Lilac) -> bool {
+        let number = |n: Option<i64>| Some(n?.cmp(&self.value.parse().ok()?));
+        let text = |t: Option<&str>| Some(t?.to_lowercase().cmp(&self.value.to_lowercase()));
+        if self.field == "genre" {
+            let mut genres = lilac.genres.iter().map(|g| self.holds(text(Some(g))));
+            return match self.operator {
+                "!=" => !lilac.genres.is_empty() && genres.all(|h| h),
+                _ => genres.any(|h| h),
+            };
+        }
+
+        let ordering = match self.field.as_str() {
+            "title" => text(lilac.title.as_deref()),
+            "artist" => text(lilac.artist.as_deref()),
+            "album" => text(lilac.album.as_deref()),
+            "album-artist" => text(
+                lilac
+                    .album_artist
+                    .as_deref()
+                    .or(lilac.compilation.then_some(lilac::VARIOUS_ARTISTS)),
+            ),
+            "year" => number(lilac.year.map(i64::from)),
+            "track" => number(lilac.track.map(i64::from)),
+            "disc" => number(lilac.disc.map(i64::from)),
+            "original-year" => number(lilac.original_year.map(i64::from)),
+            "label" => text(lilac.label.as_deref()),
+            "catalog-number" => text(lilac.catalog_number.as_deref()),
+            "rating" => number(lilac.rating.map(i64::from)),
+            "favorite" => self
+                .value
+                .parse()
+                .ok()
+                .map(|v: bool| lilac.favorite.cmp(&v)),
+            "compilation" => self
+                .value
+                .parse()
+                .ok()
+                .map(|v: bool| lilac.compilation.cmp(&v)),
+            _ => unreachable!(),
+        };
+        self.holds(ordering)
+    }
+
+    /// Whether the operator accepts how the field compares to the value
+    fn holds(&self, ordering: Option<Ordering>) -> bool {
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match self.operator {
+            "=" => ordering == Ordering::Equal,
+            "!=" => ordering != Ordering::Equal,
+            "<" => ordering == Ordering::Less,
+            "<=" => ordering != Ordering::Greater,
+            ">" => ordering == Ordering::Greater,
+            _ => ordering != Ordering::Less,
+        }
+    }
+}
+
+/// Tracks found under a directory, grouped by artist then album
+pub struct Library {
+    artists: Vec<Artist>,
+}
+struct Artist {
+    name: String,
+    albums: Vec<Album>,
+}
+struct Album {
+    name: String,
+    tracks: Vec<Track>,
+}
+struct Track {
+    title: String,
+    path: PathBuf,
+}
+
+impl Library {
+    /// Recursively finds LILAC files, only reading their metadata
+    pub fn scan(dir: &Path) -> miette::Result<Self> {
+        let headers = headers(dir)?;
+
+        let mut tree: BTreeMap<_, BTreeMap<_, Vec<_>>> = BTreeMap::new();
+        for (lilac, path) in headers {
+            let artist = lilac.album_artist().to_owned();
+            tree.entry(artist)
+                .or_default()
+                .entry(lilac.album().to_owned())
+                .or_default()
+                .push((lilac.disc, lilac.track, lilac.title().to_owned(), path));
+        }
+
+        let artists = tree
+            .into_iter()
+            .map(|(name, albums)| Artist {
+                name,
+                albums: albums
+                    .into_iter()
+                    .map(|(name, mut tracks)| {
+                        tracks.sort();
+                        Album {
+                            name,
+                            tracks: tracks
+                                .into_iter()
+                                .map(|(_, _, title, path)| Track { title, path })
+                                .collect(),
+                        }
+                    })
+                    .collect(),
+            })
+            .collect();
+        Ok(Self { artists })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.artists.is_empty()
+    }
+
+    /// Every track, by artist, album and track number
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.artists
+            .iter()
+            .flat_map(|a| &a.albums)
+            .flat_map(|a| &a.tracks)
+            .map(|t| t.path.clone())
+            .collect()
+    }
+}
+
+/// Metadata of every LILAC file under a directory, skipping unreadable ones
+pub fn headers(dir: &Path) -> miette::Result<Vec<(Lilac, PathBuf)>> {
+    index(dir).map(|(headers, _)| headers)
+}
+
+/// Metadata of the files of a library as of the last scan
+#[derive(Default, Deserialize, Serialize)]
+struct Index {
+    files: BTreeMap<PathBuf, Entry>,
+}
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    size: u64,
+    modified: SystemTime,
+    lilac: Lilac,
+}
+#[derive(Default)]
+struct Changes {
+    added: usize,
+    modified: usize,
+    removed: usize,
+}
+
+impl Index {
+    /// Location of the index of a library, in the cache directory
+    fn path(dir: &Path) -> Option<PathBuf> {
+        let dir = dir.canonicalize().ok()?;
+        let mut hasher = DefaultHasher::new();
+        dir.hash(&mut hasher);
+        let name = format!("{:016x}.json", hasher.finish());
+        dirs::cache_dir().map(|d| d.join("lilac").join("library").join(name))
+    }
+
+    /// Loads an index, which is simply rebuilt if it can't be read
+    fn load(path: &Path) -> Self {
+        fs::read(path)
+            .ok()
+            .and_then(|s| serde_json::from_slice(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Writes the index next to its destination first and moves it in place,
+    /// so that scans running at the same time never read a partial index
+    fn save(&self, path: &Path) -> crate::Result {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).into_diagnostic()?;
+        }
+        // Unique to the process, as another scan could be writing at the same time
+        let part = path.with_extension(format!("{}.part", process::id()));
+        fs::write(&part, serde_json::to_vec(self).into_diagnostic()?).into_diagnostic()?;
+        fs::rename(&part, path).into_diagnostic()
+    }
+}
+
+/// Reads the metadata of the files that changed since the last scan,
+/// reusing the index for the others
+fn index(dir: &Path) -> miette::Result<(Vec<(Lilac, PathBuf)>, Changes)> {
+    let mut files = Vec::new();
+    walk(dir, &mut files)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to scan `{}`", dir.display()))?;
+
+    let path = Index::path(dir);
+    let mut old = path.as_deref().map(Index::load).unwrap_or_default();
+    let entries: Vec<_> = files
+        .into_par_iter()
+        .filter_map(|file| {
+            let metadata = fs::metadata(&file).ok()?;
+            Some((file, metadata.len(), metadata.modified().ok()?))
+        })
+        .collect();
+
+    let mut changes = Changes::default();
+    let mut index = Index::default();
+    let mut stale = Vec::new();
+    for (file, size, modified) in entries {
+        match old.files.remove(&file) {
+            Some(e) if e.size == size && e.modified == modified => {
+                index.files.insert(file, e);
+            }
+            Some(_) => {
+                changes.modified += 1;
+                stale.push((file, size, modified));
+            }
+            None => {
+                changes.added += 1;
+                stale.push((file, size, modified));
+            }
+        }
+    }
+    changes.removed = old.files.len();
+
+    let read: Vec<_> = stale
+        .into_par_iter()
+        .filter_map(|(file, size, modified)| {
+            let lilac = Lilac::read_header_file(&file).ok()?;
+            Some((
+                file,
+                Entry {
+                    size,
+                    modified,
+                    lilac,
+                },
+            ))
+        })
+        .collect();
+    index.files.extend(read);
+
+    if let Some(path) = path {
+        // The index is only a cache, scanning works all the same without it
+        index.save(&path).ok();
+    }
+    let headers = index
+        .files
+        .into_iter()
+        .map(|(file, e)| (e.lilac, file))
+        .collect();
+    Ok((headers, changes))
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else if path.extension().is_some_and(|e| e == "lilac") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Row {
+    Artist(usize),
+    Album(usize, usize),
+    Track(usize, usize, usize),
+}
+
+/// Tree view of a library, where artists and albums can be expanded
+pub struct Browser {
+    library: Library,
+    expanded: HashSet<Row>,
+    selected: usize,
+}
+
+impl Browser {
+    pub fn new(library: Library) -> Self {
+        Self {
+            library,
+            expanded: HashSet::new(),
+            selected: 0,
+        }
+    }
+
+    fn rows(&self) -> Vec<Row> {
+        let mut rows = Vec::new();
+        for (a, artist) in self.library.artists.iter().enumerate() {
+            rows.push(Row::Artist(a));
+            if !self.expanded.contains(&Row::Artist(a)) {
+                continue;
+            }
+            for (b, album) in artist.albums.iter().enumerate() {
+                rows.push(Row::Album(a, b));
+                if !self.expanded.contains(&Row::Album(a, b)) {
+                    continue;
+                }
+                rows.extend((0..album.tracks.len()).map(|t| Row::Track(a, b, t)));
+            }
+        }
+        rows
+    }
+
+    /// Visible rows, indented by depth, and the index of the selected one
+    pub fn lines(&self) -> (Vec<String>, usize) {
+        let lines = self
+            .rows()
+            .into_iter()
+            .map(|row| {
+                let marker = |row: Row| {
+                    if self.expanded.contains(&row) {
+                        "-"
+                    } else {
+                        "+"
+                    }
+                };
+                match row {
+                    Row::Artist(a) => format!("{} {}", marker(row), self.library.artists[a].name),
+                    Row::Album(a, b) => format!(
+                        "  {} {}",
+                        marker(row),
+                        self.library.artists[a].albums[b].name
+                    ),
+                    Row::Track(a, b, t) => format!(
+                        "      {}",
+                        self.library.artists[a].albums[b].tracks[t].title
+                    ),
+                }
+            })
+            .collect();
+        (lines, self.selected)
+    }
+
+    pub fn up(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+    pub fn down(&mut self) {
+        self.selected = (self.selected + 1).min(self.rows().len() - 1);
+    }
+
+    pub fn expand(&mut self) {
+        let row = self.rows()[self.selected];
+        if !matches!(row, Row::Track(..)) {
+            self.expanded.insert(row);
+        }
+    }
+    /// Collapses the selected row, or selects its parent if it already is
+    pub fn collapse(&mut self) {
+        let rows = self.rows();
+        let row = rows[self.selected];
+        if self.expanded.remove(&row) {
+            return;
+        }
+
+        let parent = match row {
+            Row::Artist(_) => return,
+            Row::Album(a, _) => Row::Artist(a),
+            Row::Track(a, b, _) => Row::Album(a, b),
+        };
+        self.selected = rows.iter().position(|&r| r == parent).unwrap();
+    }
+
+    /// Tracks under the selected row, in order
+    pub fn selection(&self) -> Vec<PathBuf> {
+        let artists = &self.library.artists;
+        let tracks: Vec<&Track> = match self.rows()[self.selected] {
+            Row::Artist(a) => artists[a]
+                .albums
+                .iter()
+                .flat_map(|album| &album.tracks)
+                .collect(),
+            Row::Album(a, b) => artists[a].albums[b].tracks.iter().collect(),
+            Row::Track(a, b, t) => vec![&artists[a].albums[b].tracks[t]],
+        };
+        tracks.into_iter().map(|t| t.path.clone()).collect()
+    }
+}
@@ -1,26 +1,75 @@
 use std::path::PathBuf;
-use std::thread;
+use std::time::Duration;
+use std::{fs, thread};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use lilac::Lilac;
-use miette::{Context, IntoDiagnostic};
+use miette::{miette, Context, IntoDiagnostic};
 use rodio::{Sink, Source};
 
 type Result = miette::Result<()>;
 const OK: Result = Result::Ok(());
 
+#[macro_use]
+mod i18n;
+#[macro_use]
+mod output;
+
+mod broadcast;
+mod budget;
+mod config;
+mod device;
+mod effect;
+mod hooks;
+#[cfg(feature = "online")]
+mod http;
 mod interactive;
+mod keys;
+mod library;
+mod media;
+mod paths;
+mod playlist;
+mod probe;
+mod provider;
+mod queue;
+mod render;
+mod script;
+mod sleep;
+mod stats;
+mod stream;
+mod summary;
+mod tag;
+mod theme;
 mod transcode;
+mod watch;
 
 /// LILAC playback and transcoding utility
 ///
 /// If neither of the subcommands are detected,
 /// opens an interactive player and load the provided files.
 #[derive(Parser)]
+struct Cli {
+    /// Never access the network, answering requests from the cache only
+    #[clap(long, global = true)]
+    #[cfg_attr(not(feature = "online"), allow(dead_code))]
+    offline: bool,
+    /// Apply a profile of the configuration file over the rest of it
+    ///
+    /// Defaults to the LILAC_PROFILE environment variable.
+    #[clap(long, global = true, name = "PROFILE")]
+    profile: Option<String>,
+    #[clap(subcommand)]
+    command: Opt,
+}
+
+#[derive(Subcommand)]
 enum Opt {
-    /// Plays a LILAC file
+    /// Plays a file
+    ///
+    /// Accepts any supported input format, which is decoded on the fly.
+    /// An M3U playlist plays its entries one after the other.
     Play {
-        /// File to play
+        /// File or playlist to play
         #[clap(name = "FILE")]
         file: PathBuf,
         /// Playback volume
@@ -28,70 +77,341 @@ enum Opt {
         /// Should be anywhere between 0.0 and 1.0 inclusively
         #[clap(short, long, name = "VOLUME", default_value = "1.0")]
         volume: f32,
+        /// Playback speed, which also shifts the pitch
+        ///
+        /// Should be anywhere between 0.25 and 4.0 inclusively
+        #[clap(short, long, name = "SPEED", default_value = "1.0")]
+        speed: f32,
+        /// Output device, as listed by the devices subcommand
+        #[clap(short, long, name = "DEVICE")]
+        device: Option<String>,
     },
+    /// Lists the audio output devices
+    Devices,
+    /// Lists the formats that can be read and written
+    Capabilities {
+        /// Print a JSON object instead
+        #[clap(long)]
+        json: bool,
+    },
+    /// Prints a JSON Schema of LILAC files
+    ///
+    /// Meant for validating implementations in other languages
+    /// against this one.
+    Schema {
+        /// Print a description of the layout of the samples and their checksum instead
+        #[clap(long)]
+        layout: bool,
+    },
+    /// Streams files to an Icecast server
+    ///
+    /// Files are decoded and encoded to Ogg Vorbis in real time,
+    /// with the title, artist and album sent along with every track.
+    Broadcast(broadcast::Opt),
+    /// Streams files as raw PCM over TCP
+    ///
+    /// Meant to feed a Snapcast server for synchronized multi-room playback,
+    /// or any other sink reading 16 bit little endian samples.
+    Stream(stream::Opt),
     /// Transcodes a file to or from LILAC
     ///
     /// Supports transcoding from MP3, FLAC,
     /// OGG and WAV, and transcoding to WAV.
     /// Input and output formats are automatically inferred
-    Transcode {
-        /// Glob matching the input files
-        #[clap(name = "GLOB")]
-        glob: String,
-        /// Output files naming pattern
+    Transcode(transcode::Opt),
+    /// Cuts a time range out of a LILAC file
+    ///
+    /// Everything outside of the range is discarded.
+    /// Times are given as seconds or as [[HH:]MM:]SS,
+    /// with an optional fractional part.
+    Cut {
+        /// File to cut
+        #[clap(name = "FILE")]
+        file: PathBuf,
+        /// Start of the range to keep
+        #[clap(short, long, name = "FROM", value_parser = parse_duration)]
+        from: Option<Duration>,
+        /// End of the range to keep
+        #[clap(short, long, name = "TO", value_parser = parse_duration)]
+        to: Option<Duration>,
+        /// Write the result to this file instead of overwriting the input
+        #[clap(short, long, name = "OUTPUT")]
+        output: Option<PathBuf>,
+    },
+    /// Renders a spectrogram or a waveform of a file to PNG images
+    ///
+    /// Accepts any supported input format.
+    Render(render::Opt),
+    /// Prints information about a file
+    ///
+    /// Accepts any supported input format.
+    Probe {
+        /// File to inspect
+        #[clap(name = "FILE")]
+        file: PathBuf,
+        /// Print a single line of JSON instead of text
         ///
-        /// %F is replaced with the input filename without extension,
-        /// %E with the output format extension,
-        /// %e with the input format extension,
-        /// %T with the song title,
-        /// %A with the song artist,
-        /// %a with the song album.
-        #[clap(name = "PATTERN", default_value = "%F.%E")]
-        output: String,
-        /// Keep input files after transcoding
-        #[clap(short, long)]
-        keep: bool,
+        /// The JSON output is stable and meant to be consumed by other tools.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Prints information about several files
+    ///
+    /// Same as probe, for every file matching the globs.
+    Info {
+        /// Globs matching the files to inspect
+        #[clap(name = "GLOBS", required = true)]
+        globs: Vec<String>,
+        /// Print one line of JSON per file instead of text
+        #[clap(long)]
+        json: bool,
+    },
+    /// Measures the levels of files
+    ///
+    /// Accepts any supported input format.
+    /// With `--authenticity`, also flags files that were likely
+    /// transcoded from a lossy format or upsampled.
+    Stats(stats::Opt),
+    /// Converts a single file to the given destination
+    ///
+    /// Unlike transcode, the output format is inferred from
+    /// the destination extension and the source is always kept.
+    /// Meant to be used as a backend by other tools,
+    /// e.g. `lilac convert $source $dest` in beets' convert plugin.
+    Convert {
+        /// Input file
+        #[clap(name = "SOURCE")]
+        source: PathBuf,
+        /// Output file
+        #[clap(name = "DEST")]
+        dest: PathBuf,
+        /// Remix the output to the given number of channels
+        #[clap(short, long, name = "CHANNELS", value_parser = clap::value_parser!(u16).range(1..))]
+        channels: Option<u16>,
+    },
+    /// Joins several files into a single one
+    ///
+    /// All inputs must share the same channel count and sample rate.
+    /// The output format is inferred from its extension
+    /// and the metadata is taken from the first input.
+    Join {
+        /// Files to join, in order
+        #[clap(name = "FILES", required = true)]
+        inputs: Vec<PathBuf>,
+        /// Output file
+        #[clap(short, long, name = "OUTPUT")]
+        output: PathBuf,
+    },
+
+    /// Edits the metadata of a LILAC file in place
+    Tag(tag::Opt),
+    /// Queries a collection of LILAC files
+    #[clap(subcommand)]
+    Library(library::Command),
+    /// Manages M3U playlists
+    ///
+    /// Playlists can also be given anywhere files are played.
+    #[clap(subcommand)]
+    Playlist(playlist::Command),
+    /// Queues transcode jobs to run later
+    ///
+    /// The queue is kept on disk, so that long conversions can be paused
+    /// and resumed across reboots.
+    #[clap(subcommand)]
+    Queue(queue::Command),
+    /// Transcodes files as they appear in watched directories
+    ///
+    /// Runs until interrupted, following the rules of a TOML file
+    /// mapping directories to presets and destinations.
+    /// Files are picked up once their size stops changing between two scans
+    /// and failed files are retried on the following scans.
+    Watch {
+        /// Rules file
+        #[clap(long, name = "RULES")]
+        config: PathBuf,
     },
 
     Interactive {
+        /// Files or M3U playlists to queue
         queue: Vec<String>,
+        /// Output device, as listed by the devices subcommand
+        #[clap(short, long, name = "DEVICE")]
+        device: Option<String>,
+        /// Print plain text status lines instead of drawing the full-screen interface
+        ///
+        /// Track changes, play/pause and volume changes are announced on stdout,
+        /// which works well with terminal screen readers.
+        #[clap(long)]
+        plain: bool,
+        /// Directory of LILAC files to browse by artist and album
+        ///
+        /// Press `b` to open the browser. Without any files,
+        /// the whole library is queued.
+        #[clap(long, name = "DIR")]
+        library: Option<PathBuf>,
+        /// Initial volume, from 0 to 100
+        ///
+        /// Defaults to the volume when the player was last closed.
+        #[clap(short, long, name = "VOLUME", value_parser = clap::value_parser!(u16).range(0..=100))]
+        volume: Option<u16>,
     },
 }
 
 fn main() -> miette::Result<()> {
-    match Opt::parse() {
-        Opt::Play { file, volume } => play(file, volume),
-        Opt::Transcode { glob, output, keep } => transcode::main(glob, output, keep),
-        Opt::Interactive { queue } => interactive::main(queue),
+    let cli = Cli::parse();
+    if let Some(profile) = cli.profile {
+        config::set_profile(profile);
+    }
+    #[cfg(feature = "online")]
+    http::set_offline(cli.offline);
+
+    match cli.command {
+        Opt::Play {
+            file,
+            volume,
+            speed,
+            device,
+        } => play(file, volume, speed, device),
+        Opt::Devices => device::list(),
+        Opt::Capabilities { json } => capabilities(json),
+        Opt::Schema { layout } => schema(layout),
+        Opt::Broadcast(opt) => broadcast::main(opt),
+        Opt::Stream(opt) => stream::main(opt),
+        Opt::Transcode(opt) => transcode::main(opt),
+        Opt::Cut {
+            file,
+            from,
+            to,
+            output,
+        } => cut(file, from, to, output),
+        Opt::Render(opt) => render::main(opt),
+        Opt::Probe { file, json } => probe::main(file, json),
+        Opt::Info { globs, json } => probe::info(globs, json),
+        Opt::Stats(opt) => stats::main(opt),
+        Opt::Convert {
+            source,
+            dest,
+            channels,
+        } => transcode::convert(source, dest, channels),
+        Opt::Join { inputs, output } => transcode::join(inputs, output),
+        Opt::Tag(opt) => tag::main(opt),
+        Opt::Library(command) => library::main(command),
+        Opt::Playlist(command) => playlist::main(command),
+        Opt::Queue(command) => queue::main(command),
+        Opt::Watch { config } => watch::main(config),
+        Opt::Interactive {
+            queue,
+            device,
+            plain,
+            library,
+            volume,
+        } => interactive::main(queue, device, plain, library, volume),
     }?;
 
     Ok(())
 }
 
-fn play(file: PathBuf, volume: f32) -> Result {
-    let lilac = Lilac::read_file(file)?;
-    println!(
-        "Now playing {} by {} on {}",
-        lilac.title(),
-        lilac.artist(),
-        lilac.album(),
-    );
-
-    let (_stream, device) = rodio::OutputStream::try_default()
-        .into_diagnostic()
-        .context("no audio device")?;
+fn play(file: PathBuf, volume: f32, speed: f32, device: Option<String>) -> Result {
+    if !(0.25..=4.0).contains(&speed) {
+        return Err(miette!("Speed must be between 0.25 and 4.0"));
+    }
+    let (_stream, device) = device::open(device.as_deref())?;
 
     let sink = Sink::try_new(&device)
         .into_diagnostic()
         .context("failed to create sink")?;
-
-    let source = lilac.source();
-    let duration = source.total_duration().unwrap();
-
     sink.set_volume(volume);
-    sink.append(source);
+    sink.set_speed(speed);
     sink.play();
 
-    thread::sleep(duration);
+    for file in playlist::expand([file])? {
+        let (lilac, _) = transcode::decode(&file)?;
+        status!(
+            "{}",
+            tr!(
+                "Now playing {} by {} on {}",
+                lilac.title(),
+                lilac.artist(),
+                lilac.album(),
+            )
+        );
+
+        let source = lilac.source();
+        let duration = source.total_duration().unwrap();
+
+        sink.append(source);
+        thread::sleep(duration.div_f32(speed));
+    }
+    OK
+}
+
+fn capabilities(json: bool) -> Result {
+    let capabilities = lilac::capabilities();
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string(&capabilities).into_diagnostic()?
+        );
+        return OK;
+    }
+
+    println!("Decoders: {}", capabilities.decoders().join(", "));
+    println!("Encoders: {}", capabilities.encoders().join(", "));
+    println!("Features: {}", capabilities.features().join(", "));
+    OK
+}
+
+fn schema(layout: bool) -> Result {
+    let schema = if layout {
+        serde_json::to_string_pretty(&lilac::layout())
+    } else {
+        serde_json::to_string_pretty(&lilac::json_schema())
+    };
+    println!("{}", schema.into_diagnostic()?);
+    OK
+}
+
+fn cut(
+    file: PathBuf,
+    from: Option<Duration>,
+    to: Option<Duration>,
+    output: Option<PathBuf>,
+) -> Result {
+    let mut lilac = Lilac::read_file(&file)?;
+    lilac.trim(from.unwrap_or(Duration::ZERO)..to.unwrap_or(Duration::MAX));
+
+    let output = output.unwrap_or(file);
+    // Written next to the output first so that a failure never leaves the input truncated
+    let part = transcode::part_path(&output);
+    if let Err(e) = lilac.write_file(&part) {
+        fs::remove_file(&part).ok();
+        return Err(e.into());
+    }
+    fs::rename(&part, &output).into_diagnostic()?;
+    status!("{}", tr!("Wrote `{}`", output.display()));
     OK
 }
+
+fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let invalid = || format!("invalid time `{}`", s);
+
+    let mut parts = s.trim_end_matches('s').rsplit(':');
+    let seconds: f64 = parts
+        .next()
+        .and_then(|s| s.parse().ok())
+        .filter(|s: &f64| s.is_finite() && *s >= 0.0)
+        .ok_or_else(invalid)?;
+
+    let mut total = seconds;
+    for multiplier in [60.0, 3600.0] {
+        match parts.next() {
+            Some(p) => total += p.parse::<u32>().map_err(|_| invalid())? as f64 * multiplier,
+            None => break,
+        }
+    }
+    if parts.next().is_some() {
+        return Err(invalid());
+    }
+
+    Ok(Duration::from_secs_f64(total))
+}
@@ -0,0 +1,109 @@
+//! System media controls, such as MPRIS on Linux
+//!
+//! Only available with the `media-controls` feature, without which [`Controls`] does nothing.
+
+use std::time::Duration;
+
+#[cfg(feature = "media-controls")]
+use souvlaki::{
+    MediaControlEvent, MediaControls, MediaMetadata, MediaPlayback, MediaPosition, PlatformConfig,
+    SeekDirection,
+};
+
+/// Fallback seek step when the system doesn't specify one
+#[cfg(feature = "media-controls")]
+const SEEK_STEP: Duration = Duration::from_secs(5);
+
+/// Request from the system, e.g. a media key press
+pub enum Command {
+    Play,
+    Pause,
+    Toggle,
+    Stop,
+    Next,
+    Previous,
+    Forward(Duration),
+    Backward(Duration),
+    SetPosition(Duration),
+}
+
+pub struct Controls {
+    #[cfg(feature = "media-controls")]
+    controls: Option<MediaControls>,
+}
+
+#[cfg(feature = "media-controls")]
+impl Controls {
+    /// Registers the player with the system, calling `notify` with its requests
+    ///
+    /// Failing to do so, e.g. without a D-Bus session, isn't an error
+    /// and leaves the player without media controls.
+    pub fn new<F: Fn(Command) + Send + 'static>(notify: F) -> Self {
+        let config = PlatformConfig {
+            dbus_name: "lilac",
+            display_name: "LILAC",
+            hwnd: None,
+        };
+        let controls = MediaControls::new(config).ok().and_then(|mut c| {
+            c.attach(move |event| {
+                let command = match event {
+                    MediaControlEvent::Play => Command::Play,
+                    MediaControlEvent::Pause => Command::Pause,
+                    MediaControlEvent::Toggle => Command::Toggle,
+                    MediaControlEvent::Stop => Command::Stop,
+                    MediaControlEvent::Next => Command::Next,
+                    MediaControlEvent::Previous => Command::Previous,
+                    MediaControlEvent::Seek(SeekDirection::Forward) => Command::Forward(SEEK_STEP),
+                    MediaControlEvent::Seek(SeekDirection::Backward) => {
+                        Command::Backward(SEEK_STEP)
+                    }
+                    MediaControlEvent::SeekBy(SeekDirection::Forward, d) => Command::Forward(d),
+                    MediaControlEvent::SeekBy(SeekDirection::Backward, d) => Command::Backward(d),
+                    MediaControlEvent::SetPosition(MediaPosition(p)) => Command::SetPosition(p),
+                    _ => return,
+                };
+                notify(command);
+            })
+            .ok()?;
+            Some(c)
+        });
+        Self { controls }
+    }
+
+    /// Publishes the metadata of the current song
+    pub fn metadata(&mut self, title: &str, artist: &str, album: &str, duration: Duration) {
+        if let Some(controls) = &mut self.controls {
+            controls
+                .set_metadata(MediaMetadata {
+                    title: Some(title),
+                    artist: Some(artist),
+                    album: Some(album),
+                    duration: Some(duration),
+                    ..Default::default()
+                })
+                .ok();
+        }
+    }
+
+    /// Publishes the playback state, from which the system extrapolates the position
+    pub fn playback(&mut self, playing: bool, position: Duration) {
+        if let Some(controls) = &mut self.controls {
+            let progress = Some(MediaPosition(position));
+            let playback = if playing {
+                MediaPlayback::Playing { progress }
+            } else {
+                MediaPlayback::Paused { progress }
+            };
+            controls.set_playback(playback).ok();
+        }
+    }
+}
+
+#[cfg(not(feature = "media-controls"))]
+impl Controls {
+    pub fn new<F: Fn(Command) + Send + 'static>(_: F) -> Self {
+        Self {}
+    }
+    pub fn metadata(&mut self, _: &str, _: &str, _: &str, _: Duration) {}
+    pub fn playback(&mut self, _: bool, _: Duration) {}
+}
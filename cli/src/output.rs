@@ -0,0 +1,27 @@
+//! Keeps human-readable messages out of stdout when it carries data
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Sends all following status messages to stderr,
+/// for commands that write machine-readable data to stdout
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::SeqCst);
+}
+
+pub fn stdout_reserved() -> bool {
+    STDOUT_RESERVED.load(Ordering::SeqCst)
+}
+
+/// Prints a human-readable status message,
+/// to stderr if stdout has been reserved for data
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::stdout_reserved() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
@@ -0,0 +1,110 @@
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+use miette::{miette, IntoDiagnostic};
+
+/// Longest file name most filesystems accept
+const MAX_COMPONENT_LEN: usize = 255;
+/// Longest path Windows accepts without the `\\?\` prefix
+const MAX_PATH: usize = 260;
+/// Longest path Windows accepts with the `\\?\` prefix
+const MAX_EXTENDED_PATH: usize = 32767;
+
+static RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+static RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Makes a pattern-expanded output path usable on the current platform
+///
+/// On Windows, reserved device names such as `CON` get suffixed with an underscore,
+/// characters that can't appear in file names are replaced with underscores,
+/// and paths longer than `MAX_PATH` get the `\\?\` prefix.
+pub fn sanitize(path: &Path) -> miette::Result<PathBuf> {
+    let mut sanitized = PathBuf::new();
+    for c in path.components() {
+        match c {
+            Component::Normal(name) => {
+                let name = if cfg!(windows) {
+                    escape(&name.to_string_lossy())
+                } else {
+                    name.to_owned()
+                };
+                if name.len() > MAX_COMPONENT_LEN {
+                    return Err(miette!(
+                        "File name `{}` is longer than {} bytes",
+                        name.to_string_lossy(),
+                        MAX_COMPONENT_LEN
+                    ));
+                }
+                sanitized.push(name);
+            }
+            c => sanitized.push(c),
+        }
+    }
+
+    if cfg!(windows) && sanitized.as_os_str().len() >= MAX_PATH {
+        sanitized = extended(&sanitized)?;
+    }
+    Ok(sanitized)
+}
+
+fn escape(name: &str) -> OsString {
+    let mut name: String = name
+        .chars()
+        .map(|c| {
+            if RESERVED_CHARS.contains(&c) || c.is_control() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+
+    // Windows silently strips trailing dots and spaces
+    let trimmed = name.trim_end_matches(['.', ' ']).len();
+    if trimmed < name.len() {
+        name.replace_range(trimmed.., &"_".repeat(name.len() - trimmed));
+    }
+
+    let stem_len = name.find('.').unwrap_or(name.len());
+    let stem = name[..stem_len].trim_end();
+    if RESERVED_NAMES.iter().any(|r| stem.eq_ignore_ascii_case(r)) {
+        name.insert(stem_len, '_');
+    }
+
+    name.into()
+}
+
+fn extended(path: &Path) -> miette::Result<PathBuf> {
+    let absolute = std::path::absolute(path).into_diagnostic()?;
+    let absolute = absolute.to_string_lossy();
+
+    let extended = if absolute.starts_with(r"\\?\") {
+        absolute.into_owned()
+    } else if let Some(unc) = absolute.strip_prefix(r"\\") {
+        format!(r"\\?\UNC\{}", unc)
+    } else {
+        format!(r"\\?\{}", absolute)
+    };
+
+    if extended.len() > MAX_EXTENDED_PATH {
+        return Err(miette!(
+            "Path `{}` is longer than {} characters",
+            extended,
+            MAX_EXTENDED_PATH
+        ));
+    }
+    Ok(extended.into())
+}
+
+/// Replaces a leading `~` with the home directory, as a shell would
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix('~'), dirs::home_dir()) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            format!("{}{}", home.display(), rest)
+        }
+        _ => path.to_owned(),
+    }
+}
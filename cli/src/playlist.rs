@@ -0,0 +1,114 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use clap::Subcommand;
+use lilac::Lilac;
+use miette::{Context, IntoDiagnostic};
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Writes files to an M3U playlist
+    ///
+    /// Playlists among the files are expanded, so this also merges playlists.
+    /// Entries are written relative to the playlist when possible.
+    Save {
+        /// Playlist to write
+        #[clap(name = "OUTPUT")]
+        output: PathBuf,
+        #[clap(name = "FILES")]
+        files: Vec<PathBuf>,
+    },
+}
+
+pub fn main(command: Command) -> crate::Result {
+    match command {
+        Command::Save { output, files } => {
+            let files = expand(files)?;
+            let songs: Vec<_> = files
+                .into_iter()
+                .filter_map(|path| match Lilac::read_header_file(&path) {
+                    Ok(l) => Some((l, path)),
+                    Err(e) => {
+                        eprintln!("{:#}", e);
+                        None
+                    }
+                })
+                .collect();
+            save(&output, songs.iter().map(|(l, p)| (l, p.as_path())))?;
+            status!("{}", tr!("Wrote `{}`", output.display()));
+            crate::OK
+        }
+    }
+}
+
+/// Whether a file is an M3U or M3U8 playlist, judging by its extension
+pub fn is_playlist(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("m3u") || e.eq_ignore_ascii_case("m3u8"))
+}
+
+/// Replaces playlists with their entries, keeping other files as they are
+pub fn expand(files: impl IntoIterator<Item = PathBuf>) -> miette::Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    for file in files {
+        if is_playlist(&file) {
+            expanded.extend(read(&file)?);
+        } else {
+            expanded.push(file);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Entries of a playlist, resolved relative to its directory
+///
+/// Comments and extended M3U directives such as `#EXTINF` are skipped,
+/// the metadata of each track being read from the file itself.
+pub fn read(path: &Path) -> miette::Result<Vec<PathBuf>> {
+    let contents = fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read `{}`", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    Ok(contents
+        .lines()
+        .map(|l| l.trim_start_matches('\u{feff}').trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| {
+            let entry = Path::new(l.strip_prefix("file://").unwrap_or(l));
+            dir.join(entry)
+        })
+        .collect())
+}
+
+/// Writes an extended M3U playlist, in UTF-8
+pub fn save<'a>(
+    path: &Path,
+    songs: impl IntoIterator<Item = (&'a Lilac, &'a Path)>,
+) -> crate::Result {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_owned());
+
+    let mut contents = String::from("#EXTM3U\n");
+    for (lilac, file) in songs {
+        // Headers don't carry the samples, in which case the length is unknown
+        let seconds = match lilac.duration().as_secs() {
+            0 => -1,
+            s => s as i64,
+        };
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_owned());
+        let entry = file.strip_prefix(&dir).unwrap_or(&file);
+        contents += &format!(
+            "#EXTINF:{},{} - {}\n{}\n",
+            seconds,
+            lilac.artist(),
+            lilac.title(),
+            entry.display()
+        );
+    }
+
+    fs::write(path, contents)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write `{}`", path.display()))
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lilac::SampleFormat;
+use miette::{miette, IntoDiagnostic};
+use serde::Serialize;
+
+use crate::{budget, transcode};
+
+/// Stable description of a file, printed by `lilac probe --json`
+///
+/// Fields may be added but existing ones are never renamed or removed.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Probe<'a> {
+    file: &'a Path,
+    format: &'static str,
+
+    title: Option<&'a str>,
+    artist: Option<&'a str>,
+    year: Option<i32>,
+    album: Option<&'a str>,
+    track: Option<u32>,
+    /// Every genre joined by `; `
+    genre: Option<String>,
+    genres: &'a [String],
+    disc: Option<u32>,
+    album_artist: Option<&'a str>,
+    compilation: bool,
+    composer: Option<&'a str>,
+    comment: Option<&'a str>,
+    original_year: Option<i32>,
+    label: Option<&'a str>,
+    catalog_number: Option<&'a str>,
+    rating: Option<u8>,
+    favorite: bool,
+    tags: &'a BTreeMap<String, String>,
+    source_checksum: Option<u32>,
+
+    channels: u16,
+    sample_rate: u32,
+    bit_depth: u32,
+    sample_format: SampleFormat,
+    /// In seconds
+    duration: f64,
+    samples: usize,
+    /// In bytes
+    file_size: u64,
+}
+
+pub fn main(file: PathBuf, json: bool) -> crate::Result {
+    if json {
+        crate::output::reserve_stdout();
+    }
+    print(&file, json)
+}
+
+/// Describes every file matching the globs, separated by blank lines
+/// or as one JSON object per line
+pub fn info(globs: Vec<String>, json: bool) -> crate::Result {
+    if json {
+        crate::output::reserve_stdout();
+    }
+
+    let mut first = true;
+    for pattern in globs {
+        let mut files = glob::glob(&pattern).into_diagnostic()?.peekable();
+        if files.peek().is_none() {
+            return Err(miette!("No files matching `{}`", pattern));
+        }
+        for file in files {
+            if !json && !first {
+                println!();
+            }
+            first = false;
+            print(&file.into_diagnostic()?, json)?;
+        }
+    }
+
+    crate::OK
+}
+
+fn print(file: &Path, json: bool) -> crate::Result {
+    let (lilac, format) = transcode::decode(file)?;
+    let probe = Probe {
+        file,
+        format: format.extension(),
+
+        title: lilac.title.as_deref(),
+        artist: lilac.artist.as_deref(),
+        year: lilac.year,
+        album: lilac.album.as_deref(),
+        track: lilac.track,
+        genre: lilac.genre(),
+        genres: &lilac.genres,
+        disc: lilac.disc,
+        album_artist: lilac.album_artist.as_deref(),
+        compilation: lilac.compilation,
+        composer: lilac.composer.as_deref(),
+        comment: lilac.comment.as_deref(),
+        original_year: lilac.original_year,
+        label: lilac.label.as_deref(),
+        catalog_number: lilac.catalog_number.as_deref(),
+        rating: lilac.rating,
+        favorite: lilac.favorite,
+        tags: &lilac.tags,
+        source_checksum: lilac.source_checksum,
+
+        channels: lilac.channels,
+        sample_rate: lilac.sample_rate,
+        bit_depth: lilac.bit_depth,
+        sample_format: lilac.sample_format,
+        duration: lilac.duration().as_secs_f64(),
+        samples: lilac.sample_count(),
+        file_size: fs::metadata(file).into_diagnostic()?.len(),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string(&probe).into_diagnostic()?);
+        return crate::OK;
+    }
+
+    println!("File:        {}", probe.file.display());
+    println!("Format:      {}", probe.format);
+    println!("Title:       {}", lilac.title());
+    println!("Artist:      {}", lilac.artist());
+    println!("Album:       {}", lilac.album());
+    if let Some(year) = probe.year {
+        println!("Year:        {}", year);
+    }
+    if let Some(year) = probe.original_year {
+        println!("Orig. year:  {}", year);
+    }
+    if let Some(track) = probe.track {
+        println!("Track:       {}", track);
+    }
+    if let Some(disc) = probe.disc {
+        println!("Disc:        {}", disc);
+    }
+    for (name, value) in [
+        ("Genre", probe.genre.as_deref()),
+        ("Album artist", probe.album_artist),
+        ("Composer", probe.composer),
+        ("Comment", probe.comment),
+        ("Label", probe.label),
+        ("Catalog", probe.catalog_number),
+    ] {
+        if let Some(value) = value {
+            println!("{:<12} {}", format!("{}:", name), value);
+        }
+    }
+    if let Some(rating) = probe.rating {
+        println!("Rating:      {}/5", rating);
+    }
+    if probe.compilation {
+        println!("Compilation: yes");
+    }
+    if probe.favorite {
+        println!("Favorite:    yes");
+    }
+    for (name, value) in probe.tags {
+        println!("{:<12} {}", format!("{}:", name), value);
+    }
+    if let Some(checksum) = probe.source_checksum {
+        let matches = if checksum == lilac.checksum() {
+            "matches"
+        } else {
+            "differs"
+        };
+        println!("Source hash: {:08x} ({})", checksum, matches);
+    }
+    println!("Channels:    {}", probe.channels);
+    println!("Sample rate: {} Hz", probe.sample_rate);
+    match probe.sample_format {
+        SampleFormat::Int => println!("Bit depth:   {}", probe.bit_depth),
+        SampleFormat::Float => println!("Bit depth:   {} (float)", probe.bit_depth),
+    }
+    println!("Duration:    {:.3} s", probe.duration);
+    println!("Samples:     {}", probe.samples);
+    println!("Size:        {}", budget::format_size(probe.file_size));
+
+    crate::OK
+}
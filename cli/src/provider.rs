@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use lilac::Lilac;
+use miette::miette;
+
+use crate::{config, library};
+
+#[cfg(feature = "discogs")]
+mod discogs;
+#[cfg(feature = "musicbrainz")]
+mod musicbrainz;
+
+/// Tags holding the identifiers of a track, named as MusicBrainz Picard does
+pub const MUSICBRAINZ_RECORDING: &str = "MUSICBRAINZ_TRACKID";
+pub const MUSICBRAINZ_RELEASE: &str = "MUSICBRAINZ_ALBUMID";
+pub const DISCOGS_RELEASE: &str = "DISCOGS_RELEASE_ID";
+const ACOUSTID_FINGERPRINT: &str = "ACOUSTID_FINGERPRINT";
+const IDS: [&str; 3] = [MUSICBRAINZ_RECORDING, MUSICBRAINZ_RELEASE, DISCOGS_RELEASE];
+
+/// What is known about a track, to look it up by
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// Unknown for library files, whose samples aren't read
+    #[cfg_attr(not(feature = "musicbrainz"), allow(dead_code))]
+    pub duration: Option<Duration>,
+    /// Chromaprint fingerprint, as stored by AcoustID taggers
+    pub fingerprint: Option<String>,
+    /// Identifiers found in the tags, by tag name
+    pub ids: BTreeMap<&'static str, String>,
+}
+
+impl Query {
+    pub fn new(lilac: &Lilac) -> Self {
+        Self {
+            title: lilac.title.clone(),
+            artist: lilac.artist.clone(),
+            album: lilac.album.clone(),
+            duration: Some(lilac.duration()).filter(|d| !d.is_zero()),
+            fingerprint: tag(lilac, ACOUSTID_FINGERPRINT).map(ToOwned::to_owned),
+            ids: IDS
+                .into_iter()
+                .filter_map(|name| Some((name, tag(lilac, name)?.to_owned())))
+                .collect(),
+        }
+    }
+}
+
+/// Metadata of a track suggested by a provider
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Candidate {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub year: Option<i32>,
+    pub track: Option<u32>,
+    pub disc: Option<u32>,
+    pub genres: Vec<String>,
+    pub label: Option<String>,
+    pub catalog_number: Option<String>,
+    /// Identifiers to store in the tags, so that later lookups are exact
+    pub ids: BTreeMap<&'static str, String>,
+    /// Confidence, from 0 to 1
+    pub score: f32,
+}
+
+impl Candidate {
+    /// Overwrites the fields of a track with the known ones
+    pub fn apply(&self, lilac: &mut Lilac) {
+        let set = |field: &mut Option<String>, value: &Option<String>| {
+            if let Some(v) = value {
+                *field = Some(v.clone());
+            }
+        };
+        set(&mut lilac.title, &self.title);
+        set(&mut lilac.artist, &self.artist);
+        set(&mut lilac.album, &self.album);
+        set(&mut lilac.album_artist, &self.album_artist);
+        set(&mut lilac.label, &self.label);
+        set(&mut lilac.catalog_number, &self.catalog_number);
+        if !self.genres.is_empty() {
+            lilac.genres = self.genres.clone();
+        }
+        lilac.year = self.year.or(lilac.year);
+        lilac.track = self.track.or(lilac.track);
+        lilac.disc = self.disc.or(lilac.disc);
+        for (&name, id) in &self.ids {
+            lilac.tags.insert(name.to_owned(), id.clone());
+        }
+    }
+}
+
+/// Source of metadata to tag tracks with
+///
+/// Each kind of lookup returns no candidates when the provider doesn't support it,
+/// so that [`MetadataProvider::lookup`] falls through to less precise ones.
+pub trait MetadataProvider {
+    fn name(&self) -> &'static str;
+
+    /// Looks a track up by the identifiers in [`Query::ids`]
+    fn by_id(&self, _query: &Query) -> miette::Result<Vec<Candidate>> {
+        Ok(Vec::new())
+    }
+    /// Looks a track up by [`Query::fingerprint`], which is set
+    fn by_fingerprint(&self, _query: &Query) -> miette::Result<Vec<Candidate>> {
+        Ok(Vec::new())
+    }
+    /// Searches tracks by title, artist and album
+    fn by_title(&self, _query: &Query) -> miette::Result<Vec<Candidate>> {
+        Ok(Vec::new())
+    }
+
+    /// Looks a track up by the most precise means available, best candidates first
+    fn lookup(&self, query: &Query) -> miette::Result<Vec<Candidate>> {
+        let mut candidates = self.by_id(query)?;
+        if candidates.is_empty() && query.fingerprint.is_some() {
+            candidates = self.by_fingerprint(query)?;
+        }
+        if candidates.is_empty() && query.title.is_some() {
+            candidates = self.by_title(query)?;
+        }
+        candidates.sort_by(|a, b| b.score.total_cmp(&a.score));
+        Ok(candidates)
+    }
+}
+
+/// Opens the provider with the given name
+///
+/// Only `local` works offline, the others need lilac to be built
+/// with the feature of the same name.
+#[cfg_attr(not(feature = "discogs"), allow(unused_variables))]
+pub fn open(
+    name: &str,
+    library: Option<&Path>,
+    file: &Path,
+    config: &config::Providers,
+) -> miette::Result<Box<dyn MetadataProvider>> {
+    match name {
+        "local" => {
+            let dir =
+                library.ok_or_else(|| miette!("The `local` provider needs a library to search"))?;
+            Ok(Box::new(Local::new(dir, file)?))
+        }
+        #[cfg(feature = "musicbrainz")]
+        "musicbrainz" => Ok(Box::new(musicbrainz::MusicBrainz)),
+        #[cfg(feature = "discogs")]
+        "discogs" => {
+            let token = config
+                .discogs_token
+                .clone()
+                .ok_or_else(|| miette!("The `discogs` provider needs a `discogs-token`"))?;
+            Ok(Box::new(discogs::Discogs::new(token)))
+        }
+        #[cfg(not(feature = "musicbrainz"))]
+        "musicbrainz" => Err(miette!("lilac was built without the `musicbrainz` feature")),
+        #[cfg(not(feature = "discogs"))]
+        "discogs" => Err(miette!("lilac was built without the `discogs` feature")),
+        _ => Err(miette!("Unknown metadata provider `{}`", name)),
+    }
+}
+
+/// Matches tracks against the files of a library, without going online
+pub struct Local {
+    songs: Vec<(Lilac, PathBuf)>,
+}
+
+impl Local {
+    /// Reads the library, leaving out the file being looked up
+    pub fn new(dir: &Path, file: &Path) -> miette::Result<Self> {
+        let file = file.canonicalize().ok();
+        let mut songs = library::headers(dir)?;
+        songs.retain(|(_, path)| path.canonicalize().ok() != file);
+        Ok(Self { songs })
+    }
+
+    fn matching(&self, score: impl Fn(&Lilac) -> f32) -> Vec<Candidate> {
+        self.songs
+            .iter()
+            .map(|(lilac, _)| (lilac, score(lilac)))
+            .filter(|&(_, score)| score > 0.0)
+            .map(|(lilac, score)| Candidate {
+                score,
+                ..Candidate::from(lilac)
+            })
+            .collect()
+    }
+}
+
+impl MetadataProvider for Local {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn by_id(&self, query: &Query) -> miette::Result<Vec<Candidate>> {
+        Ok(self.matching(|lilac| {
+            let found = query
+                .ids
+                .iter()
+                .any(|(&name, id)| tag(lilac, name) == Some(id.as_str()));
+            if found {
+                1.0
+            } else {
+                0.0
+            }
+        }))
+    }
+    fn by_fingerprint(&self, query: &Query) -> miette::Result<Vec<Candidate>> {
+        Ok(self.matching(|lilac| {
+            if tag(lilac, ACOUSTID_FINGERPRINT) == query.fingerprint.as_deref() {
+                1.0
+            } else {
+                0.0
+            }
+        }))
+    }
+    fn by_title(&self, query: &Query) -> miette::Result<Vec<Candidate>> {
+        let same = |a: &Option<String>, b: &Option<String>| match (a, b) {
+            (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+            _ => false,
+        };
+        Ok(self.matching(|lilac| {
+            if !same(&lilac.title, &query.title) {
+                return 0.0;
+            }
+            let mut score = 0.5;
+            if same(&lilac.artist, &query.artist) {
+                score += 0.3;
+            }
+            if same(&lilac.album, &query.album) {
+                score += 0.2;
+            }
+            score
+        }))
+    }
+}
+
+impl From<&Lilac> for Candidate {
+    fn from(lilac: &Lilac) -> Self {
+        Self {
+            title: lilac.title.clone(),
+            artist: lilac.artist.clone(),
+            album: lilac.album.clone(),
+            album_artist: lilac.album_artist.clone(),
+            year: lilac.year,
+            track: lilac.track,
+            disc: lilac.disc,
+            genres: lilac.genres.clone(),
+            label: lilac.label.clone(),
+            catalog_number: lilac.catalog_number.clone(),
+            ids: IDS
+                .into_iter()
+                .filter_map(|name| Some((name, tag(lilac, name)?.to_owned())))
+                .collect(),
+            score: 0.0,
+        }
+    }
+}
+
+/// Value of a tag, ignoring case and separators in its name
+///
+/// ID3 frames name the same tags as Vorbis comments with spaces, such as `MusicBrainz Album Id`.
+fn tag<'a>(lilac: &'a Lilac, name: &str) -> Option<&'a str> {
+    let normalize = |s: &str| {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<String>()
+    };
+    let name = normalize(name);
+    lilac
+        .tags
+        .iter()
+        .find(|(k, _)| normalize(k) == name)
+        .map(|(_, v)| v.as_str())
+}
@@ -0,0 +1,177 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use super::{Candidate, MetadataProvider, Query, DISCOGS_RELEASE};
+
+const API: &str = "https://api.discogs.com";
+/// Releases fetched from a search, each costing a request
+const SEARCH_LIMIT: &str = "3";
+
+/// Looks tracks up in the Discogs database, which needs a personal access token
+pub struct Discogs {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct Search {
+    results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    id: u64,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    id: u64,
+    title: String,
+    #[serde(default)]
+    artists: Vec<Artist>,
+    /// Zero when unknown
+    #[serde(default)]
+    year: i32,
+    #[serde(default)]
+    labels: Vec<Label>,
+    #[serde(default)]
+    genres: Vec<String>,
+    #[serde(default)]
+    tracklist: Vec<Track>,
+}
+
+#[derive(Deserialize)]
+struct Artist {
+    name: String,
+    #[serde(default)]
+    join: String,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    name: String,
+    catno: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Track {
+    /// Such as `3`, `2-3` for the third track of the second disc, or `A3` on vinyl
+    position: String,
+    title: String,
+    #[serde(default)]
+    artists: Vec<Artist>,
+}
+
+impl Discogs {
+    pub fn new(token: String) -> Self {
+        Self { token }
+    }
+
+    fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> miette::Result<T> {
+        let authorization = format!("Discogs token={}", self.token);
+        crate::http::get(
+            &format!("{}/{}", API, path),
+            query,
+            &[("Authorization", &authorization)],
+        )
+    }
+
+    /// Candidate for the track of a release with the title being looked up
+    fn release(&self, id: u64, query: &Query, score: f32) -> miette::Result<Option<Candidate>> {
+        let Some(title) = &query.title else {
+            return Ok(None);
+        };
+        let release: Release = self.get(&format!("releases/{}", id), &[])?;
+        let Some(track) = release
+            .tracklist
+            .iter()
+            .find(|t| t.title.eq_ignore_ascii_case(title))
+        else {
+            return Ok(None);
+        };
+
+        let (disc, number) = position(&track.position);
+        let label = release.labels.first();
+        Ok(Some(Candidate {
+            title: Some(track.title.clone()),
+            artist: credit(&track.artists).or_else(|| credit(&release.artists)),
+            album: Some(release.title.clone()),
+            album_artist: credit(&release.artists),
+            year: (release.year > 0).then_some(release.year),
+            track: number,
+            disc,
+            genres: release.genres.clone(),
+            label: label.map(|l| l.name.clone()),
+            catalog_number: label.and_then(|l| l.catno.clone()),
+            ids: [(DISCOGS_RELEASE, release.id.to_string())].into(),
+            score,
+        }))
+    }
+}
+
+impl MetadataProvider for Discogs {
+    fn name(&self) -> &'static str {
+        "discogs"
+    }
+
+    fn by_id(&self, query: &Query) -> miette::Result<Vec<Candidate>> {
+        let Some(id) = query
+            .ids
+            .get(DISCOGS_RELEASE)
+            .and_then(|id| id.parse().ok())
+        else {
+            return Ok(Vec::new());
+        };
+        Ok(self.release(id, query, 1.0)?.into_iter().collect())
+    }
+
+    fn by_title(&self, query: &Query) -> miette::Result<Vec<Candidate>> {
+        let mut params = vec![("type", "release"), ("per_page", SEARCH_LIMIT)];
+        for (name, value) in [
+            ("track", &query.title),
+            ("artist", &query.artist),
+            ("release_title", &query.album),
+        ] {
+            if let Some(value) = value {
+                params.push((name, value.as_str()));
+            }
+        }
+        let search: Search = self.get("database/search", &params)?;
+
+        let mut candidates = Vec::new();
+        for (i, result) in search.results.iter().enumerate() {
+            // Discogs doesn't score results, only ranks them
+            let score = 0.9 - 0.1 * i as f32;
+            candidates.extend(self.release(result.id, query, score)?);
+        }
+        Ok(candidates)
+    }
+}
+
+/// Artists joined the way Discogs displays them, without the numbers telling
+/// homonyms apart, such as ` (2)`
+fn credit(artists: &[Artist]) -> Option<String> {
+    (!artists.is_empty()).then(|| {
+        let mut credit = String::new();
+        for artist in artists {
+            let name = match artist.name.rsplit_once(" (") {
+                Some((name, n)) if n.trim_end_matches(')').parse::<u32>().is_ok() => name,
+                _ => &artist.name,
+            };
+            credit += name;
+            match artist.join.trim() {
+                "" => {}
+                "," => credit += ", ",
+                join => credit += &format!(" {} ", join),
+            }
+        }
+        credit.trim_end().to_owned()
+    })
+}
+
+/// Disc and track numbers of a track position, when they are numbers
+fn position(position: &str) -> (Option<u32>, Option<u32>) {
+    match position.split_once('-') {
+        Some((disc, track)) => (disc.parse().ok(), track.parse().ok()),
+        None => (None, position.parse().ok()),
+    }
+}
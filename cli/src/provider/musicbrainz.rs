@@ -0,0 +1,237 @@
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use super::{Candidate, MetadataProvider, Query, MUSICBRAINZ_RECORDING, MUSICBRAINZ_RELEASE};
+
+const API: &str = "https://musicbrainz.org/ws/2";
+/// Recordings kept from a search
+const SEARCH_LIMIT: &str = "5";
+
+/// Looks tracks up in the MusicBrainz database, by MBID or by name
+pub struct MusicBrainz;
+
+#[derive(Deserialize)]
+struct Search {
+    recordings: Vec<Recording>,
+}
+
+#[derive(Deserialize)]
+struct Recording {
+    id: String,
+    title: String,
+    /// Relevance of search results, from 0 to 100
+    score: Option<u8>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<Credit>,
+    #[serde(default)]
+    releases: Vec<Release>,
+    #[serde(default)]
+    genres: Vec<Genre>,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    id: String,
+    title: String,
+    date: Option<String>,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<Credit>,
+    #[serde(default)]
+    media: Vec<Medium>,
+    #[serde(rename = "label-info", default)]
+    label_info: Vec<LabelInfo>,
+    #[serde(default)]
+    genres: Vec<Genre>,
+}
+
+#[derive(Deserialize)]
+struct Medium {
+    position: Option<u32>,
+    /// Named `track` in search results and `tracks` in lookups
+    #[serde(alias = "tracks", default)]
+    track: Vec<Track>,
+}
+
+#[derive(Deserialize)]
+struct Track {
+    position: Option<u32>,
+    title: String,
+    #[serde(rename = "artist-credit", default)]
+    artist_credit: Vec<Credit>,
+    recording: Option<TrackRecording>,
+}
+
+#[derive(Deserialize)]
+struct TrackRecording {
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Credit {
+    name: String,
+    #[serde(default)]
+    joinphrase: String,
+}
+
+#[derive(Deserialize)]
+struct LabelInfo {
+    #[serde(rename = "catalog-number")]
+    catalog_number: Option<String>,
+    label: Option<Label>,
+}
+
+#[derive(Deserialize)]
+struct Label {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Genre {
+    name: String,
+}
+
+impl MetadataProvider for MusicBrainz {
+    fn name(&self) -> &'static str {
+        "musicbrainz"
+    }
+
+    fn by_id(&self, query: &Query) -> miette::Result<Vec<Candidate>> {
+        let recording = query.ids.get(MUSICBRAINZ_RECORDING);
+        if let Some(release) = query.ids.get(MUSICBRAINZ_RELEASE) {
+            let release: Release = get(
+                &format!("release/{}", release),
+                &[("inc", "recordings+labels+artist-credits+genres")],
+            )?;
+            // Without a recording to find on the release, its title has to do
+            let track = release.media.iter().find_map(|m| {
+                let track = m.track.iter().find(|t| match (recording, &query.title) {
+                    (Some(id), _) => t.recording.as_ref().is_some_and(|r| &r.id == id),
+                    (None, Some(title)) => t.title.eq_ignore_ascii_case(title),
+                    (None, None) => false,
+                })?;
+                Some((m, track))
+            });
+            if let Some((medium, track)) = track {
+                let mut candidate = candidate(&release, &track.title, &[]);
+                candidate.artist = credit(&track.artist_credit).or(candidate.artist);
+                candidate.track = track.position;
+                candidate.disc = medium.position;
+                if let Some(r) = &track.recording {
+                    candidate.ids.insert(MUSICBRAINZ_RECORDING, r.id.clone());
+                }
+                candidate.score = 1.0;
+                return Ok(vec![candidate]);
+            }
+        }
+
+        let Some(recording) = recording else {
+            return Ok(Vec::new());
+        };
+        let recording: Recording = get(
+            &format!("recording/{}", recording),
+            &[("inc", "artist-credits+releases+genres")],
+        )?;
+        Ok(candidates(recording, 1.0))
+    }
+
+    fn by_title(&self, query: &Query) -> miette::Result<Vec<Candidate>> {
+        let mut terms = Vec::new();
+        for (field, value) in [
+            ("recording", &query.title),
+            ("artist", &query.artist),
+            ("release", &query.album),
+        ] {
+            if let Some(value) = value {
+                terms.push(format!("{}:\"{}\"", field, escape(value)));
+            }
+        }
+        if let Some(duration) = query.duration {
+            let ms = duration.as_millis();
+            terms.push(format!(
+                "dur:[{} TO {}]",
+                ms.saturating_sub(3000),
+                ms + 3000
+            ));
+        }
+
+        let terms = terms.join(" AND ");
+        let search: Search = get(
+            "recording",
+            &[("query", terms.as_str()), ("limit", SEARCH_LIMIT)],
+        )?;
+        Ok(search
+            .recordings
+            .into_iter()
+            .flat_map(|r| {
+                let score = r.score.unwrap_or(0) as f32 / 100.0;
+                candidates(r, score)
+            })
+            .collect())
+    }
+}
+
+/// One candidate per release of a recording, the first ones being preferred
+fn candidates(recording: Recording, score: f32) -> Vec<Candidate> {
+    let genres: Vec<_> = recording.genres.iter().map(|g| g.name.clone()).collect();
+    recording
+        .releases
+        .iter()
+        .enumerate()
+        .map(|(i, release)| {
+            let mut candidate = candidate(release, &recording.title, &genres);
+            // Releases are credited to their album artist
+            candidate.artist = credit(&recording.artist_credit).or(candidate.artist);
+            // Search results list the track on each release as the only one
+            if let Some(medium) = release.media.first() {
+                candidate.disc = medium.position;
+                candidate.track = medium.track.first().and_then(|t| t.position);
+            }
+            candidate
+                .ids
+                .insert(MUSICBRAINZ_RECORDING, recording.id.clone());
+            candidate.score = score * (1.0 - 0.01 * i as f32);
+            candidate
+        })
+        .collect()
+}
+
+fn candidate(release: &Release, title: &str, genres: &[String]) -> Candidate {
+    let label = release.label_info.first();
+    Candidate {
+        title: Some(title.to_owned()),
+        artist: credit(&release.artist_credit),
+        album: Some(release.title.clone()),
+        album_artist: credit(&release.artist_credit),
+        year: release.date.as_ref().and_then(|d| d.get(..4)?.parse().ok()),
+        genres: if genres.is_empty() {
+            release.genres.iter().map(|g| g.name.clone()).collect()
+        } else {
+            genres.to_vec()
+        },
+        label: label.and_then(|l| Some(l.label.as_ref()?.name.clone())),
+        catalog_number: label.and_then(|l| l.catalog_number.clone()),
+        ids: [(MUSICBRAINZ_RELEASE, release.id.clone())].into(),
+        ..Default::default()
+    }
+}
+
+/// Artists joined the way MusicBrainz displays them, such as `A feat. B`
+fn credit(credits: &[Credit]) -> Option<String> {
+    (!credits.is_empty()).then(|| {
+        credits
+            .iter()
+            .map(|c| format!("{}{}", c.name, c.joinphrase))
+            .collect()
+    })
+}
+
+/// Escapes a phrase for the Lucene syntax of search queries
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn get<T: DeserializeOwned>(path: &str, query: &[(&str, &str)]) -> miette::Result<T> {
+    let mut query = query.to_vec();
+    query.push(("fmt", "json"));
+    crate::http::get(&format!("{}/{}", API, path), &query, &[])
+}
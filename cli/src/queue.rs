@@ -0,0 +1,221 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use std::{env, fs, process};
+
+use clap::{Parser, Subcommand};
+use miette::{miette, Context, IntoDiagnostic};
+use serde::{Deserialize, Serialize};
+
+use crate::transcode;
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Adds a transcode job to the queue
+    ///
+    /// Takes the same arguments as the transcode subcommand,
+    /// which are checked right away and run later from the current directory.
+    Add {
+        #[clap(
+            name = "ARGS",
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        args: Vec<String>,
+    },
+    /// Lists the jobs of the queue and their state
+    Status,
+    /// Runs the pending jobs in the order they were added
+    ///
+    /// Jobs added while the queue runs are picked up too.
+    /// Interrupting with Ctrl+C pauses the queue,
+    /// which resumes with the interrupted job when run again, even after a reboot.
+    Run,
+}
+
+/// Arguments of a job, parsed the same way as by the transcode subcommand
+#[derive(Parser)]
+#[clap(name = "lilac queue add")]
+struct JobArgs {
+    #[clap(flatten)]
+    opt: transcode::Opt,
+}
+
+/// Jobs kept in the state directory, oldest first
+#[derive(Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Queue {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Job {
+    id: u64,
+    args: Vec<String>,
+    /// Working directory the arguments are relative to
+    dir: PathBuf,
+    added: SystemTime,
+    state: State,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum State {
+    Pending,
+    /// Also the state of jobs that were interrupted, which are resumed first
+    Running,
+    Done,
+    Failed(String),
+}
+
+impl Queue {
+    fn path() -> miette::Result<PathBuf> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|d| d.join("lilac").join("queue.json"))
+            .ok_or_else(|| miette!("No directory to keep the queue in"))
+    }
+
+    /// Loads the queue, which is empty until a job is added
+    ///
+    /// Unlike other state, a queue that can't be read is an error
+    /// rather than being forgotten.
+    fn load() -> miette::Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read(&path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read `{}`", path.display()))?;
+        serde_json::from_slice(&contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid queue `{}`", path.display()))
+    }
+
+    /// Writes the queue next to its destination first and moves it in place,
+    /// so that a crash never leaves a partial queue
+    fn save(&self) -> crate::Result {
+        let path = Self::path()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).into_diagnostic()?;
+        }
+        let part = path.with_extension(format!("{}.part", process::id()));
+        fs::write(&part, serde_json::to_vec(self).into_diagnostic()?).into_diagnostic()?;
+        fs::rename(&part, &path).into_diagnostic()
+    }
+
+    fn job(&mut self, id: u64) -> Option<&mut Job> {
+        self.jobs.iter_mut().find(|j| j.id == id)
+    }
+}
+
+pub fn main(command: Command) -> crate::Result {
+    match command {
+        Command::Add { args } => {
+            parse(&args)?;
+            let dir = env::current_dir().into_diagnostic()?;
+
+            let mut queue = Queue::load()?;
+            let id = queue.next_id + 1;
+            queue.next_id = id;
+            queue.jobs.push(Job {
+                id,
+                args,
+                dir,
+                added: SystemTime::now(),
+                state: State::Pending,
+            });
+            queue.save()?;
+            status!("{}", tr!("Added job {} to the queue", id));
+            crate::OK
+        }
+        Command::Status => {
+            let queue = Queue::load()?;
+            if queue.jobs.is_empty() {
+                status!("{}", tr!("The queue is empty"));
+            }
+            for job in &queue.jobs {
+                let state = match &job.state {
+                    State::Pending => "pending",
+                    State::Running => "running",
+                    State::Done => "done",
+                    State::Failed(_) => "failed",
+                };
+                println!(
+                    "{:>4}  {:<8} {} ({})",
+                    job.id,
+                    state,
+                    job.args.join(" "),
+                    job.dir.display()
+                );
+                if let State::Failed(e) = &job.state {
+                    println!("      {}", e);
+                }
+            }
+            crate::OK
+        }
+        Command::Run => run(),
+    }
+}
+
+fn run() -> crate::Result {
+    transcode::handle_interrupts()?;
+
+    let (mut done, mut failed) = (0, 0);
+    while !transcode::interrupted() {
+        // Reloaded before every job, to pick up the ones added in the meantime
+        let mut queue = Queue::load()?;
+        let Some(job) = queue
+            .jobs
+            .iter_mut()
+            .find(|j| matches!(j.state, State::Pending | State::Running))
+        else {
+            break;
+        };
+        job.state = State::Running;
+        let (id, args, dir) = (job.id, job.args.clone(), job.dir.clone());
+        queue.save()?;
+
+        status!("{}", tr!("Running job {}", id));
+        // An interrupted batch exits the process, leaving the job running
+        let result = run_job(&args, &dir);
+
+        let mut queue = Queue::load()?;
+        if let Some(job) = queue.job(id) {
+            job.state = match &result {
+                Ok(()) => State::Done,
+                Err(e) => State::Failed(format!("{:#}", e)),
+            };
+        }
+        queue.save()?;
+        match result {
+            Ok(()) => done += 1,
+            Err(e) => {
+                eprintln!("{}: {:#}", tr!("Job {} failed", id), e);
+                failed += 1;
+            }
+        }
+    }
+
+    status!("{}", tr!("{} jobs done, {} failed", done, failed));
+    crate::OK
+}
+
+fn run_job(args: &[String], dir: &Path) -> crate::Result {
+    env::set_current_dir(dir)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to enter `{}`", dir.display()))?;
+    transcode::main(parse(args)?)
+}
+
+/// Parses the arguments of a job, reporting mistakes as clap would
+fn parse(args: &[String]) -> miette::Result<transcode::Opt> {
+    JobArgs::try_parse_from(
+        std::iter::once("lilac queue add").chain(args.iter().map(String::as_str)),
+    )
+    .map(|a| a.opt)
+    .map_err(|e| miette!("{}", e.render().to_string().trim_end()))
+}
@@ -0,0 +1,140 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use clap::{ArgGroup, Args};
+use lilac::Lilac;
+use miette::{Context, IntoDiagnostic};
+use rayon::prelude::*;
+
+use crate::transcode;
+
+/// Quietest level drawn on spectrograms, in decibels relative to full scale
+const FLOOR_DB: f32 = -90.0;
+/// Colors of the waveform peaks and RMS
+const PEAK: [u8; 3] = [200, 162, 200];
+const RMS: [u8; 3] = [134, 96, 160];
+/// Colors of the spectrogram, from quiet to loud
+const GRADIENT: [[u8; 3]; 4] = [[0, 0, 0], [62, 24, 92], [200, 162, 200], [255, 255, 255]];
+
+#[derive(Args)]
+#[clap(group(ArgGroup::new("images").required(true).multiple(true)))]
+pub struct Opt {
+    /// File to render
+    #[clap(name = "FILE")]
+    file: PathBuf,
+    /// Write a spectrogram of the whole track to this PNG file
+    ///
+    /// Time goes from left to right and frequencies from 50 Hz at the bottom
+    /// up to 16 kHz or the Nyquist frequency at the top, on a logarithmic scale.
+    #[clap(long, name = "SPECTROGRAM", group = "images")]
+    spectrogram: Option<PathBuf>,
+    /// Write a waveform of the whole track to this PNG file, one lane per channel
+    #[clap(long, name = "WAVEFORM", group = "images")]
+    waveform: Option<PathBuf>,
+    /// Size of the images in pixels, as `WIDTHxHEIGHT`
+    #[clap(long, name = "SIZE", default_value = "1920x400", value_parser = parse_size)]
+    size: (u32, u32),
+}
+
+/// Renders images of a file
+pub fn main(opt: Opt) -> crate::Result {
+    let (lilac, _) = transcode::decode(&opt.file)?;
+    let (width, height) = opt.size;
+
+    if let Some(path) = &opt.spectrogram {
+        write(path, width, height, &spectrogram(&lilac, width, height))?;
+        status!("{}", tr!("Wrote `{}`", path.display()));
+    }
+    if let Some(path) = &opt.waveform {
+        write(path, width, height, &waveform(&lilac, width, height))?;
+        status!("{}", tr!("Wrote `{}`", path.display()));
+    }
+    crate::OK
+}
+
+/// RGBA pixels of a spectrogram, row by row from the top
+fn spectrogram(lilac: &Lilac, width: u32, height: u32) -> Vec<u8> {
+    let duration = lilac.duration();
+    let columns: Vec<Vec<f32>> = (0..width)
+        .into_par_iter()
+        .map(|x| lilac.spectrum(duration.mul_f64(x as f64 / width as f64), height as usize))
+        .collect();
+
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for y in (0..height as usize).rev() {
+        for column in &columns {
+            let db = 20.0 * column[y].max(f32::MIN_POSITIVE).log10();
+            let level = (1.0 - db / FLOOR_DB).clamp(0.0, 1.0);
+            pixels.extend(gradient(level));
+            pixels.push(u8::MAX);
+        }
+    }
+    pixels
+}
+
+/// RGBA pixels of a waveform with a transparent background, row by row from the top
+fn waveform(lilac: &Lilac, width: u32, height: u32) -> Vec<u8> {
+    let peaks = lilac.peaks(width as usize);
+    // Channels that don't fit are left out
+    let lanes = peaks.len().clamp(1, height as usize);
+    let lane = height as usize / lanes;
+
+    let mut pixels = vec![0; (width * height * 4) as usize];
+    for (c, channel) in peaks.iter().take(lanes).enumerate() {
+        // Rows of a lane from its top, for a level between -1 and 1
+        let row = |level: f32| {
+            let row = ((1.0 - level.clamp(-1.0, 1.0)) / 2.0 * (lane - 1) as f32).round();
+            c * lane + row as usize
+        };
+        for (x, peak) in channel.iter().enumerate() {
+            let mut paint = |rows: std::ops::RangeInclusive<usize>, color: [u8; 3]| {
+                for y in rows {
+                    let i = (y * width as usize + x) * 4;
+                    pixels[i..i + 3].copy_from_slice(&color);
+                    pixels[i + 3] = u8::MAX;
+                }
+            };
+            paint(row(peak.max)..=row(peak.min), PEAK);
+            paint(row(peak.rms)..=row(-peak.rms), RMS);
+        }
+    }
+    pixels
+}
+
+/// Color of a level from 0 to 1, interpolated along [`GRADIENT`]
+fn gradient(level: f32) -> [u8; 3] {
+    let position = level * (GRADIENT.len() - 1) as f32;
+    let i = (position as usize).min(GRADIENT.len() - 2);
+    let t = position - i as f32;
+    let (a, b) = (GRADIENT[i], GRADIENT[i + 1]);
+    [0, 1, 2].map(|c| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * t).round() as u8)
+}
+
+fn write(path: &Path, width: u32, height: u32, pixels: &[u8]) -> crate::Result {
+    let context = || format!("Failed to write `{}`", path.display());
+    let file = File::create(path)
+        .into_diagnostic()
+        .wrap_err_with(context)?;
+
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .into_diagnostic()
+        .wrap_err_with(context)?;
+    writer
+        .write_image_data(pixels)
+        .into_diagnostic()
+        .wrap_err_with(context)
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("invalid size `{}`, expected `WIDTHxHEIGHT`", s);
+    let (width, height) = s.split_once('x').ok_or_else(invalid)?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
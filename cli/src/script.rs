@@ -0,0 +1,89 @@
+use std::path::Path;
+
+use lilac::Lilac;
+use miette::{miette, Context, IntoDiagnostic};
+use rhai::{Array, Dynamic, Engine, Scope, AST};
+
+/// Rhai script computing output paths, for naming rules that patterns can't express
+///
+/// The script has access to the metadata of the track as variables
+/// (`title`, `artist`, `album`, `year`, `track`, `genre`, `disc`,
+/// `album_artist`, `composer`, `comment`, `original_year`, `label` and `catalog_number`,
+/// which are `()` when missing,
+/// `genre` holding every genre separated by `; `),
+/// the genres as an array in `genres`,
+/// the input filename without extension as `stem`,
+/// the directory of the input relative to the --recursive root as `dir`,
+/// the input extension as `input_extension` and the output extension as `extension`.
+/// It must evaluate to the output path.
+///
+/// ```rhai
+/// let artist = if artist == () { "Various" } else { artist };
+/// `${artist}/${stem}.${extension}`
+/// ```
+pub struct NameScript {
+    engine: Engine,
+    ast: AST,
+}
+
+impl NameScript {
+    pub fn load(path: &Path) -> miette::Result<Self> {
+        let engine = Engine::new();
+        let ast = engine
+            .compile_file(path.to_owned())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid name script `{}`", path.display()))?;
+        Ok(Self { engine, ast })
+    }
+
+    pub fn name(
+        &self,
+        lilac: &Lilac,
+        stem: &str,
+        dir: &str,
+        input_extension: &str,
+        extension: &str,
+    ) -> miette::Result<String> {
+        let string = |s: &Option<String>| s.clone().map_or(Dynamic::UNIT, Dynamic::from);
+        let int = |i: Option<i64>| i.map_or(Dynamic::UNIT, Dynamic::from);
+
+        let mut scope = Scope::new();
+        scope
+            .push_dynamic("title", string(&lilac.title))
+            .push_dynamic("artist", string(&lilac.artist))
+            .push_dynamic("album", string(&lilac.album))
+            .push_dynamic("year", int(lilac.year.map(i64::from)))
+            .push_dynamic("track", int(lilac.track.map(i64::from)))
+            .push_dynamic("genre", string(&lilac.genre()))
+            .push(
+                "genres",
+                lilac
+                    .genres
+                    .iter()
+                    .cloned()
+                    .map(Dynamic::from)
+                    .collect::<Array>(),
+            )
+            .push_dynamic("disc", int(lilac.disc.map(i64::from)))
+            .push_dynamic("album_artist", string(&lilac.album_artist))
+            .push_dynamic("composer", string(&lilac.composer))
+            .push_dynamic("comment", string(&lilac.comment))
+            .push_dynamic("original_year", int(lilac.original_year.map(i64::from)))
+            .push_dynamic("label", string(&lilac.label))
+            .push_dynamic("catalog_number", string(&lilac.catalog_number))
+            .push("stem", stem.to_owned())
+            .push("dir", dir.to_owned())
+            .push("input_extension", input_extension.to_owned())
+            .push("extension", extension.to_owned());
+
+        let name: String = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .into_diagnostic()
+            .wrap_err("Name script failed")?;
+        if name.is_empty() {
+            return Err(miette!("Name script returned an empty path"));
+        }
+        Ok(name)
+    }
+}
@@ -0,0 +1,69 @@
+use std::io::{BufRead, BufReader};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often the clocks are compared when sleep notifications aren't available
+const CLOCK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// Difference between the clocks above which the machine is considered to have slept
+const CLOCK_JUMP: Duration = Duration::from_secs(5);
+
+/// Calls `notify` with `true` before the machine goes to sleep
+/// and with `false` once it wakes up
+///
+/// On Linux, this listens to logind's `PrepareForSleep` signal through `dbus-monitor`.
+/// Elsewhere, or if that isn't available, waking up is detected from the wall clock
+/// jumping ahead of the monotonic one, which doesn't advance while asleep,
+/// so only `false` is ever sent.
+pub fn watch<F: Fn(bool) + Send + 'static>(notify: F) {
+    thread::spawn(move || {
+        if cfg!(target_os = "linux") {
+            logind(&notify);
+        }
+        clock(&notify);
+    });
+}
+
+fn logind(notify: &impl Fn(bool)) {
+    let Ok(mut child) = Command::new("dbus-monitor")
+        .args([
+            "--system",
+            "type='signal',interface='org.freedesktop.login1.Manager',member='PrepareForSleep'",
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    else {
+        return;
+    };
+
+    // The signal's only argument is printed on the line following its header
+    let mut signal = false;
+    for line in BufReader::new(child.stdout.take().unwrap()).lines() {
+        let Ok(line) = line else { break };
+        let line = line.trim();
+        if line.starts_with("signal ") {
+            signal = line.contains("member=PrepareForSleep");
+        } else if signal {
+            match line {
+                "boolean true" => notify(true),
+                "boolean false" => notify(false),
+                _ => continue,
+            }
+            signal = false;
+        }
+    }
+    child.wait().ok();
+}
+
+fn clock(notify: &impl Fn(bool)) {
+    loop {
+        let (instant, system) = (Instant::now(), SystemTime::now());
+        thread::sleep(CLOCK_CHECK_INTERVAL);
+        let elapsed = system.elapsed().unwrap_or_default();
+        if elapsed > instant.elapsed() + CLOCK_JUMP {
+            notify(false);
+        }
+    }
+}
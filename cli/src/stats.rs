@@ -0,0 +1,117 @@
+use std::path::Path;
+
+use clap::Args;
+use lilac::{Authenticity, Lilac, Verdict};
+use miette::{miette, IntoDiagnostic};
+use serde::Serialize;
+
+use crate::transcode;
+
+#[derive(Args)]
+pub struct Opt {
+    /// Globs matching the files to analyse
+    #[clap(name = "GLOBS", required = true)]
+    globs: Vec<String>,
+    /// Also look for signs of a lossy or lower resolution source
+    ///
+    /// Files whose spectrum is cut off sharply below 20 kHz, as MP3 and AAC encoders do,
+    /// or at the Nyquist frequency of a lower sample rate are flagged.
+    /// High bitrate encodes keep too much to be detected.
+    #[clap(long)]
+    authenticity: bool,
+    /// Print one line of JSON per file instead of text
+    #[clap(long)]
+    json: bool,
+}
+
+/// Measurements of a file, printed by `lilac stats --json`
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Stats<'a> {
+    file: &'a Path,
+    /// Loudest sample, in decibels relative to full scale
+    peak: f64,
+    /// In decibels relative to full scale
+    rms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authenticity: Option<Authenticity>,
+}
+
+pub fn main(opt: Opt) -> crate::Result {
+    if opt.json {
+        crate::output::reserve_stdout();
+    }
+
+    let (mut total, mut flagged) = (0, 0);
+    for pattern in &opt.globs {
+        let mut files = glob::glob(pattern).into_diagnostic()?.peekable();
+        if files.peek().is_none() {
+            return Err(miette!("No files matching `{}`", pattern));
+        }
+        for file in files {
+            let file = file.into_diagnostic()?;
+            let (lilac, _) = transcode::decode(&file)?;
+            let stats = measure(&file, &lilac, opt.authenticity);
+            if opt.json {
+                println!("{}", serde_json::to_string(&stats).into_diagnostic()?);
+            } else {
+                if total > 0 {
+                    println!();
+                }
+                print(&stats);
+            }
+
+            total += 1;
+            if let Some(Verdict::Lossy | Verdict::Upsampled(_)) =
+                stats.authenticity.map(|a| a.verdict)
+            {
+                flagged += 1;
+            }
+        }
+    }
+
+    if opt.authenticity {
+        status!(
+            "{}",
+            tr!("{} of {} files look lossy or upsampled", flagged, total)
+        );
+    }
+    crate::OK
+}
+
+fn measure<'a>(file: &'a Path, lilac: &Lilac, authenticity: bool) -> Stats<'a> {
+    let peaks: Vec<_> = lilac.peaks(1).into_iter().flatten().collect();
+    let peak = peaks.iter().map(|p| p.max.max(-p.min)).fold(0f32, f32::max);
+    let power = peaks.iter().map(|p| p.rms * p.rms).sum::<f32>() / peaks.len().max(1) as f32;
+
+    Stats {
+        file,
+        peak: 20.0 * (peak as f64).log10(),
+        rms: 10.0 * (power as f64).log10(),
+        authenticity: authenticity.then(|| lilac.authenticity()),
+    }
+}
+
+fn print(stats: &Stats) {
+    println!("File:        {}", stats.file.display());
+    println!("Peak:        {:.2} dBFS", stats.peak);
+    println!("RMS:         {:.2} dBFS", stats.rms);
+
+    let Some(authenticity) = stats.authenticity else {
+        return;
+    };
+    if let Some(cutoff) = authenticity.cutoff {
+        println!(
+            "Cutoff:      {:.1} kHz (-{:.0} dB)",
+            cutoff / 1000.0,
+            authenticity.drop
+        );
+    }
+    let verdict = match authenticity.verdict {
+        Verdict::Clean => "clean".to_owned(),
+        Verdict::Lossy => "likely transcoded from a lossy format".to_owned(),
+        Verdict::Upsampled(rate) => format!("likely upsampled from {} Hz", rate),
+        Verdict::Inconclusive => "too short or quiet to tell".to_owned(),
+    };
+    println!("Verdict:     {}", verdict);
+}
@@ -0,0 +1,93 @@
+use std::io::{BufWriter, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use miette::{Context, IntoDiagnostic};
+use rodio::source::UniformSourceIterator;
+
+use crate::transcode;
+
+/// Frames written at once
+const BLOCK_FRAMES: usize = 1024;
+/// How far ahead of real time samples are sent, to absorb network hiccups
+const LEAD: Duration = Duration::from_millis(500);
+
+#[derive(Args)]
+pub struct Opt {
+    /// Files to stream, in order
+    #[clap(name = "FILES", required = true)]
+    files: Vec<String>,
+    /// Address of the receiving end, as `host:port`
+    #[clap(long, name = "ADDRESS")]
+    to: String,
+    /// Sample rate of the stream, which every file is resampled to
+    #[clap(long, name = "RATE", default_value = "48000")]
+    rate: u32,
+    /// Channel count of the stream, which every file is remixed to
+    #[clap(long, name = "CHANNELS", default_value = "2", value_parser = clap::value_parser!(u16).range(1..))]
+    channels: u16,
+    /// Start over from the first file after the last one
+    #[clap(long = "loop")]
+    repeat: bool,
+}
+
+/// Streams files as raw 16 bit little endian PCM over TCP, in real time
+///
+/// This is the format expected by a Snapcast `tcp` source,
+/// whose sample format must match `--rate` and `--channels`.
+pub fn main(opt: Opt) -> crate::Result {
+    let stream = TcpStream::connect(&opt.to)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to connect to `{}`", opt.to))?;
+    let mut stream = BufWriter::new(stream);
+
+    let start = Instant::now();
+    let mut sent = 0;
+    loop {
+        for file in &opt.files {
+            let (lilac, _) = transcode::decode(file.as_ref())?;
+            status!(
+                "{}",
+                tr!(
+                    "Now streaming {} by {} on {}",
+                    lilac.title(),
+                    lilac.artist(),
+                    lilac.album(),
+                )
+            );
+
+            let mut samples =
+                UniformSourceIterator::<_, i16>::new(lilac.source(), opt.channels, opt.rate);
+            let mut block = Vec::with_capacity(BLOCK_FRAMES * opt.channels as usize * 2);
+            loop {
+                block.clear();
+                for s in samples.by_ref().take(BLOCK_FRAMES * opt.channels as usize) {
+                    block.extend_from_slice(&s.to_le_bytes());
+                }
+                if block.is_empty() {
+                    break;
+                }
+
+                stream
+                    .write_all(&block)
+                    .and_then(|_| stream.flush())
+                    .into_diagnostic()
+                    .wrap_err(tr!("Lost the connection to the server"))?;
+                sent += block.len() / (2 * opt.channels as usize);
+
+                // Paced over the whole queue so track boundaries don't leave gaps
+                let ahead = Duration::from_secs_f64(sent as f64 / opt.rate as f64)
+                    .saturating_sub(start.elapsed());
+                thread::sleep(ahead.saturating_sub(LEAD));
+            }
+        }
+
+        if !opt.repeat {
+            break;
+        }
+    }
+
+    crate::OK
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::budget;
+
+/// Measurements of a single transcoded input
+pub struct Stats {
+    pub format: &'static str,
+    pub input_size: u64,
+    pub output_size: u64,
+    /// Duration of the decoded audio
+    pub audio: Duration,
+    /// Time spent transcoding
+    pub elapsed: Duration,
+}
+
+/// Totals of a transcode batch, overall and per input format
+#[derive(Default)]
+pub struct Summary {
+    failed: usize,
+    total: Totals,
+    formats: BTreeMap<&'static str, Totals>,
+}
+
+#[derive(Default)]
+struct Totals {
+    files: usize,
+    input_size: u64,
+    output_size: u64,
+    audio: Duration,
+    elapsed: Duration,
+}
+
+impl Summary {
+    pub fn add(&mut self, stats: &Stats) {
+        self.total.add(stats);
+        self.formats.entry(stats.format).or_default().add(stats);
+    }
+    pub fn fail(&mut self) {
+        self.failed += 1;
+    }
+
+    /// Prints the totals, with real-time factors computed against the wall time of the batch
+    /// overall and against the time spent on each file per format
+    pub fn print(&self, wall: Duration) {
+        if self.total.files + self.failed > 0 {
+            status!(
+                "{}",
+                tr!("{} succeeded, {} failed", self.total.files, self.failed)
+            );
+        }
+        if self.total.files == 0 {
+            return;
+        }
+
+        status!(
+            "{}",
+            tr!(
+                "Transcoded {} files in {} s",
+                self.total.files,
+                format!("{:.1}", wall.as_secs_f64())
+            )
+        );
+        status!(
+            "  {}",
+            tr!(
+                "{} -> {}, {}x real time",
+                budget::format_size(self.total.input_size),
+                budget::format_size(self.total.output_size),
+                format!("{:.1}", realtime(self.total.audio, wall))
+            )
+        );
+        for (format, t) in &self.formats {
+            status!(
+                "  {:<5} {}",
+                format,
+                tr!(
+                    "{} files, {} s, {}x real time",
+                    t.files,
+                    format!("{:.1}", t.elapsed.as_secs_f64()),
+                    format!("{:.1}", realtime(t.audio, t.elapsed))
+                )
+            );
+        }
+    }
+}
+
+impl Totals {
+    fn add(&mut self, stats: &Stats) {
+        self.files += 1;
+        self.input_size += stats.input_size;
+        self.output_size += stats.output_size;
+        self.audio += stats.audio;
+        self.elapsed += stats.elapsed;
+    }
+}
+
+fn realtime(audio: Duration, elapsed: Duration) -> f64 {
+    audio.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON)
+}
@@ -0,0 +1,212 @@
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Args;
+use lilac::Lilac;
+use miette::{miette, IntoDiagnostic};
+
+use crate::config::Config;
+use crate::provider::{self, Query};
+use crate::transcode;
+
+#[derive(Args)]
+pub struct Opt {
+    /// LILAC file to edit
+    #[clap(name = "FILE")]
+    file: PathBuf,
+    /// Parse tags out of the filename using a pattern
+    ///
+    /// The pattern is matched against the filename without extension.
+    /// %T matches the title,
+    /// %A the artist,
+    /// %a the album,
+    /// %n the track number
+    /// and %y the year.
+    /// Tags given explicitly take precedence over parsed ones.
+    #[clap(long, name = "PATTERN")]
+    from_filename: Option<String>,
+    /// Look the file up with a metadata provider and apply the best match
+    ///
+    /// Can be repeated, providers are tried in order until one finds a match.
+    /// `local` searches the library given with `--library` and works offline,
+    /// `musicbrainz` and `discogs` need lilac to be built with the features
+    /// of the same names.
+    /// Parsed and explicit tags take precedence over looked up ones.
+    #[clap(long, name = "PROVIDER")]
+    lookup: Vec<String>,
+    /// Library searched by the `local` provider
+    #[clap(long, name = "DIR")]
+    library: Option<PathBuf>,
+
+    #[clap(long)]
+    title: Option<String>,
+    #[clap(long)]
+    artist: Option<String>,
+    #[clap(long)]
+    album: Option<String>,
+    #[clap(long)]
+    year: Option<i32>,
+    #[clap(long)]
+    track: Option<u32>,
+    /// Can be repeated for several genres, which replace the existing ones
+    #[clap(long)]
+    genre: Vec<String>,
+    #[clap(long)]
+    disc: Option<u32>,
+    #[clap(long)]
+    album_artist: Option<String>,
+    #[clap(long)]
+    composer: Option<String>,
+    #[clap(long)]
+    comment: Option<String>,
+    #[clap(long)]
+    original_year: Option<i32>,
+    #[clap(long)]
+    label: Option<String>,
+    #[clap(long)]
+    catalog_number: Option<String>,
+    /// Rating from 1 to 5, or 0 to clear it
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=5))]
+    rating: Option<u8>,
+    #[clap(long)]
+    favorite: Option<bool>,
+    /// Whether the album gathers tracks of various artists
+    #[clap(long)]
+    compilation: Option<bool>,
+}
+
+pub fn main(opt: Opt) -> crate::Result {
+    let mut lilac = Lilac::read_file(&opt.file)?;
+
+    if !opt.lookup.is_empty() {
+        lookup(&mut lilac, &opt)?;
+    }
+    if let Some(pattern) = &opt.from_filename {
+        let stem = opt
+            .file
+            .file_stem()
+            .ok_or_else(|| miette!("Invalid filename"))?
+            .to_string_lossy();
+        let fields = parse_filename(pattern, &stem).ok_or_else(|| {
+            miette!(
+                "`{}` doesn't match the pattern `{}`",
+                opt.file.display(),
+                pattern
+            )
+        })?;
+        for (field, value) in fields {
+            apply(&mut lilac, field, value)?;
+        }
+    }
+
+    let set = |field: &mut Option<String>, value: &Option<String>| {
+        if let Some(v) = value {
+            *field = Some(v.clone());
+        }
+    };
+    set(&mut lilac.title, &opt.title);
+    set(&mut lilac.artist, &opt.artist);
+    set(&mut lilac.album, &opt.album);
+    set(&mut lilac.album_artist, &opt.album_artist);
+    set(&mut lilac.composer, &opt.composer);
+    set(&mut lilac.comment, &opt.comment);
+    set(&mut lilac.label, &opt.label);
+    set(&mut lilac.catalog_number, &opt.catalog_number);
+    if !opt.genre.is_empty() {
+        lilac.genres = opt.genre.clone();
+    }
+    lilac.year = opt.year.or(lilac.year);
+    lilac.track = opt.track.or(lilac.track);
+    lilac.original_year = opt.original_year.or(lilac.original_year);
+    lilac.disc = opt.disc.or(lilac.disc);
+    if let Some(rating) = opt.rating {
+        lilac.rating = (rating > 0).then_some(rating);
+    }
+    lilac.favorite = opt.favorite.unwrap_or(lilac.favorite);
+    lilac.compilation = opt.compilation.unwrap_or(lilac.compilation);
+
+    // Written next to the file first so that a failure never leaves it truncated
+    let part = transcode::part_path(&opt.file);
+    if let Err(e) = lilac.write_file(&part) {
+        fs::remove_file(&part).ok();
+        return Err(e.into());
+    }
+    fs::rename(&part, &opt.file).into_diagnostic()?;
+
+    status!("{}", tr!("Tagged `{}`", opt.file.display()));
+    crate::OK
+}
+
+/// Applies the best match of the first provider finding one
+fn lookup(lilac: &mut Lilac, opt: &Opt) -> crate::Result {
+    let config = Config::load()?;
+    #[cfg(feature = "online")]
+    crate::http::configure(&config.network)?;
+    let query = Query::new(lilac);
+    for name in &opt.lookup {
+        let provider = provider::open(name, opt.library.as_deref(), &opt.file, &config.providers)?;
+        if let Some(candidate) = provider.lookup(&query)?.into_iter().next() {
+            candidate.apply(lilac);
+            status!(
+                "{}",
+                tr!(
+                    "Matched {} by {} on {} with {}",
+                    lilac.title(),
+                    lilac.artist(),
+                    lilac.album(),
+                    provider.name(),
+                )
+            );
+            return crate::OK;
+        }
+    }
+    Err(miette!("No match found for `{}`", opt.file.display()))
+}
+
+fn apply(lilac: &mut Lilac, field: char, value: &str) -> crate::Result {
+    let number = || {
+        value
+            .trim()
+            .parse()
+            .map_err(|_| miette!("`{}` is not a number", value))
+    };
+    match field {
+        'T' => lilac.title = Some(value.to_owned()),
+        'A' => lilac.artist = Some(value.to_owned()),
+        'a' => lilac.album = Some(value.to_owned()),
+        'n' => lilac.track = Some(number()?),
+        'y' => lilac.year = Some(number()? as i32),
+        _ => return Err(miette!("Unknown placeholder `%{}`", field)),
+    }
+    crate::OK
+}
+
+/// Matches a name against a pattern, returning the value of each placeholder
+///
+/// Every placeholder extends up to the first occurrence of the text that follows it,
+/// so two placeholders can't be directly next to each other.
+fn parse_filename<'a>(pattern: &str, name: &'a str) -> Option<Vec<(char, &'a str)>> {
+    let mut parts = pattern.split('%');
+    let mut rest = name.strip_prefix(parts.next()?)?;
+
+    let mut fields = Vec::new();
+    let mut parts = parts.peekable();
+    while let Some(part) = parts.next() {
+        let mut chars = part.chars();
+        let field = chars.next()?;
+        let literal = chars.as_str();
+
+        let (value, after) = if parts.peek().is_none() {
+            (rest.strip_suffix(literal)?, "")
+        } else if literal.is_empty() {
+            return None;
+        } else {
+            let i = rest.find(literal)?;
+            (&rest[..i], &rest[i + literal.len()..])
+        };
+        fields.push((field, value));
+        rest = after;
+    }
+
+    rest.is_empty().then_some(fields)
+}
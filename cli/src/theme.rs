@@ -0,0 +1,67 @@
+use std::env;
+
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+/// Player color scheme, selected with `theme` in the configuration file
+#[derive(Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ThemeName {
+    #[default]
+    Default,
+    HighContrast,
+    /// Blue and orange instead of colors that are hard to tell apart with deuteranopia
+    Deuteranopia,
+    Monochrome,
+}
+
+pub struct Theme {
+    /// Base style of the whole interface
+    pub text: Style,
+    /// Title, play state and counters
+    pub emphasis: Style,
+    /// Timeline and volume gauges
+    pub gauge: Style,
+    /// Current track in the queue
+    pub highlight: Style,
+}
+
+impl Theme {
+    /// Theme with the given name, or monochrome if the `NO_COLOR` environment variable is set
+    pub fn new(name: ThemeName) -> Self {
+        let no_color = env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+        let name = if no_color {
+            ThemeName::Monochrome
+        } else {
+            name
+        };
+
+        let bold = Style::new().add_modifier(Modifier::BOLD);
+        match name {
+            ThemeName::Default => Self {
+                text: Style::new(),
+                emphasis: bold,
+                gauge: Style::new().fg(Color::White),
+                highlight: bold,
+            },
+            ThemeName::HighContrast => Self {
+                text: Style::new().fg(Color::White).bg(Color::Black),
+                emphasis: bold.fg(Color::Yellow),
+                gauge: Style::new().fg(Color::Yellow).bg(Color::Black),
+                highlight: bold.add_modifier(Modifier::REVERSED),
+            },
+            ThemeName::Deuteranopia => Self {
+                text: Style::new(),
+                emphasis: bold.fg(Color::Rgb(230, 159, 0)),
+                gauge: Style::new().fg(Color::Rgb(0, 114, 178)),
+                highlight: bold.fg(Color::Rgb(86, 180, 233)),
+            },
+            ThemeName::Monochrome => Self {
+                text: Style::new(),
+                emphasis: bold,
+                gauge: Style::new(),
+                highlight: bold.add_modifier(Modifier::REVERSED),
+            },
+        }
+    }
+}
@@ -1,10 +1,26 @@
-use std::fs::{self, File};
-use std::io::{BufReader, Read, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{mem, process};
 
-use lilac::Lilac;
+use clap::{Args, ValueEnum};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use lilac::{Cue, Lilac};
 use miette::{miette, IntoDiagnostic};
 use rayon::prelude::*;
+use serde::Deserialize;
+
+use crate::budget::{self, MemoryBudget};
+use crate::config::{Config, Preset};
+use crate::effect::Effect;
+use crate::paths;
+use crate::script::NameScript;
+use crate::summary::{Stats, Summary};
 
 static MP3_MAGIC_NUMBERS: &[&[u8]] = &[&[0xFF, 0xFB], &[0xFF, 0xF3], &[0xFF, 0xF2], b"ID3"];
 static FLAC_MAGIC_NUMBER: &[u8] = b"fLaC";
@@ -12,23 +28,622 @@ static OGG_MAGIC_NUMBER: &[u8] = b"OggS";
 static WAV_MAGIC_NUMBER: &[u8] = b"WAVE";
 const WAV_MAGIC_NUMBER_OFFSET: usize = 8;
 
-pub fn main(glob: String, output: String, keep: bool) -> crate::Result {
-    let files = glob::glob(&glob).into_diagnostic()?;
-    let results: Vec<miette::Result<(PathBuf, PathBuf)>> = files
-        .par_bridge()
-        .map(|r| transcode(r.into_diagnostic()?, &output, keep))
+#[derive(Args, Default)]
+pub struct Opt {
+    /// Glob matching the input files, or a directory with --recursive
+    ///
+    /// `-` reads a single input from stdin,
+    /// which is written to the output pattern with %F replaced by "stdin"
+    /// unless --stdout is also given.
+    #[clap(name = "GLOB")]
+    glob: String,
+    /// Output files naming pattern
+    ///
+    /// %F is replaced with the input filename without extension,
+    /// %E with the output format extension,
+    /// %e with the input format extension,
+    /// %T with the song title,
+    /// %A with the song artist,
+    /// %a with the song album,
+    /// %n with the track number padded to two digits
+    /// and %d with the directory of the input relative to the --recursive root.
+    ///
+    /// Missing metadata is replaced with "Unknown" ("00" for track numbers),
+    /// or with a custom fallback given as %T?{fallback}.
+    ///
+    /// [default: %F.%E, or %d/%F.%E with --out]
+    #[clap(name = "PATTERN")]
+    output: Option<String>,
+    /// Compute output paths with a Rhai script instead of the pattern
+    ///
+    /// The script gets the metadata of the track as variables
+    /// and must evaluate to the output path.
+    #[clap(long, name = "SCRIPT", conflicts_with = "PATTERN")]
+    name_script: Option<PathBuf>,
+    #[clap(skip)]
+    script: Option<NameScript>,
+    /// Transcode every supported file in the GLOB directory and its subdirectories
+    ///
+    /// Symbolic links to directories aren't followed.
+    #[clap(short, long)]
+    recursive: bool,
+    /// Write outputs relative to this directory instead of next to their input
+    ///
+    /// Together with --recursive, the directory structure of the inputs is mirrored.
+    #[clap(short = 'o', long, value_name = "DIR")]
+    out: Option<PathBuf>,
+    /// Keep input files after transcoding
+    #[clap(short, long)]
+    keep: bool,
+    /// Store the checksum of the input audio in the outputs and only remove inputs
+    /// once the outputs are read back and decode to the exact same audio
+    ///
+    /// Can't be combined with options changing the audio.
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "CHANNELS", "BITS", "EFFECT", "DB", "ALBUM_DB", "FADE_IN", "FADE_OUT", "stdout"
+        ]
+    )]
+    verify_hash: bool,
+    /// Move input files to this directory after transcoding instead of deleting them
+    ///
+    /// Together with --recursive, the directory structure of the inputs is mirrored.
+    #[clap(long, value_name = "DIR", conflicts_with = "keep")]
+    move_originals: Option<PathBuf>,
+    /// Hard link input files into the --move-originals directory instead of moving them
+    ///
+    /// Inputs are copied when they can't be linked,
+    /// for example when the directory is on another filesystem.
+    #[clap(long, requires = "move_originals")]
+    link: bool,
+    /// Use the options of a preset from the configuration file
+    ///
+    /// Options given on the command line take precedence over the preset,
+    /// and the preset's effects are applied before the command line ones.
+    #[clap(short, long, name = "PRESET")]
+    preset: Option<String>,
+    /// Output format
+    ///
+    /// Defaults to WAV for LILAC inputs and LILAC for everything else.
+    #[clap(long, name = "FORMAT")]
+    to: Option<Target>,
+    /// Remix the output to the given number of channels
+    ///
+    /// Downmixing averages channels together
+    /// and upmixing duplicates them.
+    #[clap(short, long, name = "CHANNELS", value_parser = clap::value_parser!(u16).range(1..))]
+    channels: Option<u16>,
+    /// Convert the output to the given bit depth
+    #[clap(short, long, name = "BITS", value_parser = clap::value_parser!(u32).range(1..=32))]
+    bit_depth: Option<u32>,
+    /// Add dither when the bit depth is reduced instead of only rounding samples
+    ///
+    /// Recommended when going down to 16 bits or less, for example for CDs or phones.
+    #[clap(long)]
+    dither: bool,
+    /// Ignore sidecar CUE sheets
+    ///
+    /// By default, an input with a .cue file of the same name next to it
+    /// is split into one output per track of the sheet,
+    /// and %F is replaced with the input filename followed by the track number.
+    #[clap(long)]
+    no_cue: bool,
+    /// What to do when several outputs of the batch end up with the same path
+    #[clap(long, value_enum, name = "ACTION", default_value = "suffix")]
+    on_collision: Collision,
+    /// What to do when an output file already exists
+    ///
+    /// Inputs are kept when any of their outputs is skipped.
+    #[clap(long, value_enum, name = "POLICY", default_value = "overwrite")]
+    on_conflict: Conflict,
+    /// Print which outputs would be written for each input without writing or deleting anything
+    #[clap(long, conflicts_with = "stdout")]
+    dry_run: bool,
+    /// Apply a SoX-style effect to every output
+    ///
+    /// Can be repeated, effects are applied in order.
+    /// Supported effects are
+    /// `trim START [LENGTH]`,
+    /// `fade IN [STOP [OUT]]`,
+    /// `gain DB`,
+    /// `norm [DB]`,
+    /// `channels N`
+    /// and `crossfeed`.
+    #[clap(short, long = "effect", name = "EFFECT", value_parser = str::parse::<Effect>)]
+    effects: Vec<Effect>,
+    /// Change the volume of every output by the given amount of decibels
+    ///
+    /// Applied after effects, samples that overflow are clipped.
+    #[clap(long, name = "DB", allow_hyphen_values = true)]
+    gain: Option<f64>,
+    /// Fade in every output from silence over the given duration
+    #[clap(long, name = "FADE_IN", value_parser = crate::parse_duration)]
+    fade_in: Option<Duration>,
+    /// Fade out every output to silence over the given duration
+    #[clap(long, name = "FADE_OUT", value_parser = crate::parse_duration)]
+    fade_out: Option<Duration>,
+    /// Normalize each album so that its loudest track peaks at the given amount of decibels
+    ///
+    /// Every track of an album gets the same gain, keeping their relative loudness,
+    /// and effects are applied after it.
+    /// The tracks of an album are all decoded before any is written,
+    /// which takes more memory.
+    #[clap(
+        long,
+        name = "ALBUM_DB",
+        allow_hyphen_values = true,
+        conflicts_with = "stdout"
+    )]
+    album_norm: Option<f64>,
+    /// Limit the memory used by files decoded at the same time
+    ///
+    /// Accepts sizes like 512M or 4G.
+    /// The decoded size of each file is estimated from its size and format,
+    /// and files wait for others to finish when the limit would be exceeded.
+    #[clap(short, long, name = "SIZE", value_parser = budget::parse_size)]
+    memory_limit: Option<u64>,
+    /// Spill decoded audio larger than this size to a temporary file
+    ///
+    /// Accepts sizes like 512M or 4G.
+    /// Spilled audio is memory-mapped instead of being kept in memory,
+    /// which allows transcoding very long recordings on machines with little memory.
+    #[clap(long, name = "SPILL_SIZE", value_parser = budget::parse_size)]
+    spill_threshold: Option<u64>,
+    /// Number of files transcoded at the same time
+    ///
+    /// Defaults to the number of CPU cores.
+    /// Inputs are scheduled album by album,
+    /// albums being inputs in the same directory with the same album tag.
+    #[clap(short, long, name = "JOBS")]
+    jobs: Option<NonZeroUsize>,
+    /// Write the output to stdout instead of a file
+    ///
+    /// The input must be a single file or `-`, which is always kept,
+    /// and the output format defaults to LILAC.
+    #[clap(long)]
+    stdout: bool,
+}
+
+impl Opt {
+    /// Options for transcoding single files, as done by the watch daemon
+    pub fn for_file(
+        filename: PathBuf,
+        preset: Option<String>,
+        pattern: Option<String>,
+        out: Option<PathBuf>,
+        keep: bool,
+    ) -> Self {
+        Self {
+            glob: filename.to_string_lossy().into_owned(),
+            output: pattern,
+            preset,
+            out,
+            keep,
+            ..Default::default()
+        }
+    }
+
+    /// Applies the preset and loads the name script
+    pub fn prepare(&mut self) -> crate::Result {
+        let config = Config::load()?;
+        if let Some(name) = &self.preset {
+            let preset = config.presets.get(name).ok_or_else(|| {
+                let mut names: Vec<_> = config.presets.keys().map(String::as_str).collect();
+                names.sort_unstable();
+                miette!(
+                    "Unknown preset `{}`, available presets are: {}",
+                    name,
+                    names.join(", ")
+                )
+            })?;
+            self.apply(preset);
+        }
+        // Presets can change the audio too
+        if self.verify_hash
+            && (self.channels.is_some() || self.bit_depth.is_some() || !self.effects.is_empty())
+        {
+            return Err(miette!(
+                "--verify-hash can't be used with a preset that changes the audio"
+            ));
+        }
+        if self.name_script.is_none() {
+            self.output = self.output.take().or(config.transcode.pattern);
+        }
+        self.jobs = self.jobs.or(config.transcode.jobs);
+        self.memory_limit = self.memory_limit.or(config.transcode.memory_limit);
+
+        self.script = self
+            .name_script
+            .as_deref()
+            .map(NameScript::load)
+            .transpose()?;
+        crate::OK
+    }
+
+    fn apply(&mut self, preset: &Preset) {
+        self.output = self.output.take().or_else(|| preset.pattern.clone());
+        self.keep |= preset.keep.unwrap_or_default();
+        self.to = self.to.or(preset.to);
+        self.channels = self.channels.or(preset.channels);
+        self.bit_depth = self.bit_depth.or(preset.bit_depth);
+        self.dither |= preset.dither.unwrap_or_default();
+        self.effects = preset
+            .effects
+            .iter()
+            .cloned()
+            .chain(mem::take(&mut self.effects))
+            .collect();
+    }
+
+    /// Output path relative to the input directory
+    fn name(
+        &self,
+        lilac: &Lilac,
+        stem: &str,
+        dir: &Path,
+        format: &Format,
+        target: Target,
+    ) -> miette::Result<String> {
+        let dir = dir.to_string_lossy();
+        match &self.script {
+            Some(script) => script.name(lilac, stem, &dir, format.extension(), target.extension()),
+            None => {
+                let pattern = match (self.output.as_deref(), &self.out) {
+                    (Some(pattern), _) => pattern,
+                    (None, Some(_)) => "%d/%F.%E",
+                    (None, None) => "%F.%E",
+                };
+                Ok(expand(pattern, stem, &dir, format, target, lilac))
+            }
+        }
+    }
+
+    /// Directory of an input relative to the --recursive root
+    fn relative_dir<'a>(&self, filename: &'a Path) -> &'a Path {
+        let parent = filename.parent().unwrap_or(Path::new(""));
+        let dir = if self.recursive {
+            parent.strip_prefix(&self.glob).unwrap_or(parent)
+        } else {
+            Path::new("")
+        };
+        if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        }
+    }
+
+    /// Path of an output, relative to --out or to the directory of the input
+    fn output_path(&self, filename: &Path, output: String) -> PathBuf {
+        match self.out.as_deref().or(filename.parent()) {
+            Some(base) => base.join(output),
+            None => PathBuf::from(output),
+        }
+    }
+
+    /// Output format for an input format
+    fn target(&self, format: &Format) -> Target {
+        self.to.unwrap_or(match format {
+            Format::Lilac if !self.stdout => Target::Wav,
+            _ => Target::Lilac,
+        })
+    }
+
+    /// Applies the format conversions, before splitting by CUE sheet
+    fn convert(&self, lilac: Lilac) -> Lilac {
+        let mut lilac = match self.channels {
+            Some(c) => lilac.remix(c),
+            None => lilac,
+        };
+        match self.bit_depth {
+            Some(b) if self.dither => lilac.dither(b),
+            Some(b) => lilac.set_bit_depth(b),
+            None => {}
+        }
+        lilac
+    }
+
+    /// Applies the effects, to every output
+    fn apply_effects(&self, lilac: Lilac) -> Lilac {
+        let mut lilac = self.effects.iter().fold(lilac, |l, e| e.apply(l));
+        if let Some(db) = self.gain {
+            lilac.amplify(db);
+        }
+        if let Some(d) = self.fade_in {
+            lilac.fade_in(d);
+        }
+        if let Some(d) = self.fade_out {
+            lilac.fade_out(d);
+        }
+        lilac
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    Lilac,
+    Wav,
+}
+
+impl Target {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Target::Lilac => "lilac",
+            Target::Wav => "wav",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+enum Collision {
+    /// Append a number to the name of the later outputs in input order, as in `name (1).lilac`
+    #[default]
+    Suffix,
+    /// Fail the later inputs, in input order
+    Error,
+}
+
+#[derive(Clone, Copy, PartialEq, Default, ValueEnum)]
+enum Conflict {
+    /// Replace the existing file
+    #[default]
+    Overwrite,
+    /// Leave the existing file alone and don't write the output
+    Skip,
+    /// Append a number to the name of the output, as in `name (1).lilac`
+    Rename,
+    /// Fail the input
+    Fail,
+}
+
+pub fn main(mut opt: Opt) -> crate::Result {
+    opt.prepare()?;
+    if opt.glob == "-" || opt.stdout {
+        return pipe(&opt);
+    }
+
+    let budget = opt.memory_limit.map(MemoryBudget::new);
+    lilac::set_spill_threshold(opt.spill_threshold.map(|s| (s / 4) as usize));
+    handle_interrupts()?;
+
+    let start = Instant::now();
+    let files: Vec<_> = if opt.recursive {
+        let mut files = Vec::new();
+        walk(Path::new(&opt.glob), &mut files).into_diagnostic()?;
+        files.sort_unstable();
+        files.into_iter().map(Ok).collect()
+    } else {
+        glob::glob(&opt.glob)
+            .into_diagnostic()?
+            .map(|r| r.into_diagnostic())
+            .collect()
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(opt.jobs.map_or(0, NonZeroUsize::get))
+        .build()
+        .into_diagnostic()?;
+
+    // Outputs are named in input order before any input is transcoded,
+    // so that collisions are resolved the same way on every run
+    let headers: Vec<_> = pool.install(|| {
+        files
+            .into_par_iter()
+            .map(|f| f.and_then(|f| read_header(&f).map(|(h, format)| (f, h, format))))
+            .collect()
+    });
+    let mut claimed = HashSet::new();
+    let plans: Vec<_> = headers
+        .into_iter()
+        .map(|h| h.and_then(|(f, header, format)| Plan::new(f, header, format, &opt, &mut claimed)))
         .collect();
+
+    let progress = MultiProgress::new();
+    let overall = progress.add(
+        ProgressBar::new(plans.len() as u64).with_style(
+            ProgressStyle::with_template("{wide_bar} {pos}/{len} [{elapsed_precise}, ETA {eta}]")
+                .unwrap(),
+        ),
+    );
+    let batch = Batch {
+        opt: &opt,
+        budget: budget.as_ref(),
+        progress: &progress,
+        overall: &overall,
+    };
+    let results: Vec<miette::Result<Outcome>> = pool.install(|| {
+        albums(plans)
+            .into_par_iter()
+            .flat_map(|album| batch.album(album))
+            .collect()
+    });
+    overall.finish_and_clear();
+    let mut remaining = Vec::new();
+    let mut summary = Summary::default();
     for r in results {
         match r {
-            Ok((i, o)) => println!("`{}` -> `{}`", i.display(), o.display()),
-            Err(e) => eprintln!("{:#}", e),
+            Ok(Outcome::Done(i, o, stats)) => {
+                print_outputs(&i, &o);
+                summary.add(&stats);
+            }
+            Ok(Outcome::Planned(i, o)) => print_outputs(&i, &o),
+            Ok(Outcome::Interrupted(i)) => remaining.push(i),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                summary.fail();
+            }
         }
     }
+    if !opt.dry_run {
+        summary.print(start.elapsed());
+    }
+
+    if !remaining.is_empty() {
+        eprintln!(
+            "{}",
+            tr!(
+                "Interrupted before transcoding {} files, which were left untouched:",
+                remaining.len()
+            )
+        );
+        for i in &remaining {
+            eprintln!("  `{}`", i.display());
+        }
+        eprintln!("{}", tr!("Run the same command again to resume"));
+        process::exit(130);
+    }
 
     crate::OK
 }
 
-enum Format {
+/// State shared by the inputs of a batch
+struct Batch<'a> {
+    opt: &'a Opt,
+    budget: Option<&'a MemoryBudget>,
+    progress: &'a MultiProgress,
+    overall: &'a ProgressBar,
+}
+
+impl Batch<'_> {
+    /// Transcodes the inputs of an album, running the album-level steps on them
+    fn album(&self, plans: Vec<miette::Result<Plan>>) -> Vec<miette::Result<Outcome>> {
+        // Inputs that couldn't be listed or planned are alone in their group
+        let plans = match plans.into_iter().collect::<miette::Result<Vec<_>>>() {
+            Ok(plans) => plans,
+            Err(e) => return vec![Err(e)],
+        };
+
+        if self.opt.dry_run {
+            self.overall.inc(plans.len() as u64);
+            return plans
+                .into_iter()
+                .map(|p| {
+                    let outputs = p.outputs.into_iter().flatten().collect();
+                    Ok(Outcome::Planned(p.filename, outputs))
+                })
+                .collect();
+        }
+
+        let Some(db) = self.opt.album_norm else {
+            return plans
+                .into_par_iter()
+                .map(|plan| {
+                    let bar = self.spinner(&plan.filename);
+                    bar.set_prefix(tr!("waiting"));
+                    let _reservation = self
+                        .budget
+                        .map(|b| b.reserve(budget::estimate(&plan.filename)));
+                    let result = transcode(plan, self.opt, &bar);
+                    self.finish(&bar);
+                    result
+                })
+                .collect();
+        };
+
+        // A single reservation for the whole album, as reserving track by track
+        // could leave several albums each waiting for the others to finish
+        let bars: Vec<_> = plans.iter().map(|p| self.spinner(&p.filename)).collect();
+        for bar in &bars {
+            bar.set_prefix(tr!("waiting"));
+        }
+        let _reservation = self
+            .budget
+            .map(|b| b.reserve(plans.iter().map(|p| budget::estimate(&p.filename)).sum()));
+        let inputs: Vec<_> = plans
+            .into_par_iter()
+            .zip(&bars)
+            .map(|(plan, bar)| Input::decode(plan, self.opt, bar))
+            .collect();
+
+        let peak = inputs
+            .iter()
+            .flatten()
+            .map(|i| i.lilac.peak())
+            .fold(0.0, f64::max);
+        let gain = (peak > 0.0).then(|| db - 20.0 * peak.log10());
+        inputs
+            .into_par_iter()
+            .zip(&bars)
+            .map(|(input, bar)| {
+                let result = input.and_then(|i| i.write(self.opt, bar, gain));
+                self.finish(bar);
+                result
+            })
+            .collect()
+    }
+
+    fn spinner(&self, filename: &Path) -> ProgressBar {
+        let bar = self.progress.insert_before(
+            self.overall,
+            ProgressBar::new_spinner()
+                .with_style(ProgressStyle::with_template("{spinner} {prefix:10} {msg}").unwrap())
+                .with_message(filename.display().to_string()),
+        );
+        bar.enable_steady_tick(Duration::from_millis(100));
+        bar
+    }
+
+    fn finish(&self, bar: &ProgressBar) {
+        bar.finish_and_clear();
+        self.overall.inc(1);
+    }
+}
+
+/// Groups the inputs of a batch by album, so that album-level steps see all of their tracks
+///
+/// Inputs are grouped by directory and album tag.
+fn albums(plans: Vec<miette::Result<Plan>>) -> Vec<Vec<miette::Result<Plan>>> {
+    let mut albums: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    let mut unlisted = Vec::new();
+    for plan in plans {
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(e) => {
+                unlisted.push(vec![Err(e)]);
+                continue;
+            }
+        };
+        let dir = plan
+            .filename
+            .parent()
+            .map(Path::to_owned)
+            .unwrap_or_default();
+        albums
+            .entry((dir, plan.album.clone()))
+            .or_default()
+            .push(Ok(plan));
+    }
+    albums.into_values().chain(unlisted).collect()
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Makes the first Ctrl+C roll back the files in progress and the second one exit immediately
+pub fn handle_interrupts() -> crate::Result {
+    // Queued jobs run several batches in the same process, and handlers can only be set once
+    static HANDLED: AtomicBool = AtomicBool::new(false);
+    if HANDLED.swap(true, Ordering::SeqCst) {
+        return crate::OK;
+    }
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            process::exit(130);
+        }
+        eprintln!(
+            "{}",
+            tr!("Interrupted, rolling back files in progress... (press Ctrl+C again to abort)")
+        );
+    })
+    .into_diagnostic()
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[derive(Clone, Copy)]
+pub enum Format {
     Lilac,
     Mp3,
     Flac,
@@ -36,74 +651,603 @@ enum Format {
     Wav,
 }
 
-fn transcode(filename: PathBuf, output: &str, keep: bool) -> miette::Result<(PathBuf, PathBuf)> {
-    let reader = BufReader::new(File::open(&filename).into_diagnostic()?);
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Lilac => "lilac",
+            Format::Mp3 => "mp3",
+            Format::Flac => "flac",
+            Format::Ogg => "ogg",
+            Format::Wav => "wav",
+        }
+    }
 
-    let (lilac, format) = match filename
+    /// Format matching the extension of a file, if it's a known one
+    fn of(filename: &Path) -> Option<Self> {
+        let extension = filename.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "lilac" => Some(Format::Lilac),
+            "mp3" => Some(Format::Mp3),
+            "flac" => Some(Format::Flac),
+            "ogg" => Some(Format::Ogg),
+            "wav" => Some(Format::Wav),
+            _ => None,
+        }
+    }
+}
+
+/// Converts a single file to an explicit destination
+///
+/// The output format is inferred from the destination extension
+/// and the source file is always kept.
+pub fn convert(source: PathBuf, dest: PathBuf, channels: Option<u16>) -> crate::Result {
+    let (lilac, _) = decode(&source)?;
+    let lilac = match channels {
+        Some(c) => lilac.remix(c),
+        None => lilac,
+    };
+
+    write(&lilac, &dest)
+}
+
+/// Decodes all inputs and writes them back to back into a single file
+///
+/// The output format is inferred from the destination extension
+/// and the metadata is taken from the first input.
+pub fn join(inputs: Vec<PathBuf>, dest: PathBuf) -> crate::Result {
+    let tracks = inputs
+        .par_iter()
+        .map(|f| decode(f).map(|(l, _)| l))
+        .collect::<miette::Result<Vec<_>>>()?;
+    let lilac = Lilac::concat(&tracks)?;
+    drop(tracks);
+
+    write(&lilac, &dest)?;
+    status!("{}", tr!("`{}` <- {} files", dest.display(), inputs.len()));
+    crate::OK
+}
+
+/// Writes a file in the format matching its extension, defaulting to LILAC
+pub fn write(lilac: &Lilac, dest: &Path) -> crate::Result {
+    if let Some(p) = dest.parent() {
+        fs::create_dir_all(p).into_diagnostic()?;
+    }
+    match dest
         .extension()
-        .map(|e| e.to_str().map(|e| e.to_lowercase()))
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .as_deref()
     {
-        Some(Some(s)) => match s.as_ref() {
-            "lilac" => (Lilac::read(reader)?, Format::Lilac),
-            "mp3" => (Lilac::from_mp3(reader)?, Format::Mp3),
-            "flac" => (Lilac::from_flac(reader)?, Format::Flac),
-            "ogg" => (Lilac::from_ogg(reader)?, Format::Ogg),
-            "wav" => (Lilac::from_wav(reader)?, Format::Wav),
-            _ => detect(reader)?,
-        },
-        _ => detect(reader)?,
+        Some("wav") => lilac.to_wav_file(dest)?,
+        _ => lilac.write_file(dest)?,
+    }
+
+    crate::OK
+}
+
+/// Decodes a file, inferring its format from its extension or magic number
+pub fn decode(filename: &Path) -> miette::Result<(Lilac, Format)> {
+    let mut reader = BufReader::new(File::open(filename).into_diagnostic()?);
+    let format = match Format::of(filename) {
+        Some(format) => format,
+        None => sniff(&mut reader)?,
     };
+    Ok((decode_as(reader, format)?, format))
+}
 
-    let output = output
-        .replace(
-            "%F",
-            filename
-                .file_stem()
-                .ok_or_else(|| miette!("Invalid filename"))?
-                .to_string_lossy()
-                .as_ref(),
-        )
-        .replace(
-            "%E",
-            match format {
-                Format::Lilac => "wav",
-                _ => "lilac",
-            },
-        )
-        .replace(
-            "%e",
-            match format {
-                Format::Lilac => "lilac",
-                Format::Mp3 => "mp3",
-                Format::Flac => "flac",
-                Format::Ogg => "ogg",
-                Format::Wav => "wav",
-            },
-        )
-        .replace("%T", lilac.title())
-        .replace("%A", lilac.artist())
-        .replace("%a", lilac.album());
-    let outfile = filename
-        .parent()
-        .map(|p| p.join(&output))
-        .unwrap_or_else(|| PathBuf::from(output));
+/// Reads the metadata of a file without decoding its samples, inferring its format as [`decode`]
+pub fn read_header(filename: &Path) -> miette::Result<(Lilac, Format)> {
+    let mut reader = BufReader::new(File::open(filename).into_diagnostic()?);
+    let format = match Format::of(filename) {
+        Some(format) => format,
+        None => sniff(&mut reader)?,
+    };
+    let header = match format {
+        Format::Lilac => Lilac::read_header(reader)?,
+        Format::Mp3 => Lilac::from_mp3_header(reader)?,
+        Format::Flac => Lilac::from_flac_header(reader)?,
+        Format::Ogg => Lilac::from_ogg_header(reader)?,
+        Format::Wav => Lilac::from_wav_header(reader)?,
+    };
+    Ok((header, format))
+}
+
+fn decode_as<R: Read + Seek>(reader: R, format: Format) -> miette::Result<Lilac> {
+    let lilac = match format {
+        Format::Lilac => Lilac::read(reader)?,
+        Format::Mp3 => Lilac::from_mp3(reader)?,
+        Format::Flac => Lilac::from_flac(reader)?,
+        Format::Ogg => Lilac::from_ogg(reader)?,
+        Format::Wav => Lilac::from_wav(reader)?,
+    };
+    Ok(lilac)
+}
 
+/// Transcodes the single file given as glob by prepared options,
+/// returning the outputs that were written
+pub fn transcode_file(opt: &Opt) -> miette::Result<Vec<PathBuf>> {
+    let filename = PathBuf::from(&opt.glob);
+    let (header, format) = read_header(&filename)?;
+    let plan = Plan::new(filename, header, format, opt, &mut HashSet::new())?;
+    let outcome = transcode(plan, opt, &ProgressBar::hidden())?;
+    match outcome {
+        Outcome::Done(_, outfiles, _) | Outcome::Planned(_, outfiles) => Ok(outfiles),
+        Outcome::Interrupted(_) => Err(miette!("Interrupted")),
+    }
+}
+
+/// Transcodes a single input read from stdin or written to stdout
+fn pipe(opt: &Opt) -> crate::Result {
+    if opt.stdout {
+        crate::output::reserve_stdout();
+    }
+
+    let (lilac, format) = if opt.glob == "-" {
+        // Read fully so that the format can be detected and decoders can seek
+        let mut input = Vec::new();
+        io::stdin()
+            .lock()
+            .read_to_end(&mut input)
+            .into_diagnostic()?;
+        detect(Cursor::new(input))?
+    } else {
+        decode(Path::new(&opt.glob))?
+    };
+    let lilac = opt.apply_effects(opt.convert(lilac));
+    let target = opt.target(&format);
+
+    if opt.stdout {
+        // WAV headers are written last and need seeking
+        let mut output = Cursor::new(Vec::new());
+        match target {
+            Target::Lilac => lilac.write(&mut output)?,
+            Target::Wav => lilac.to_wav(&mut output)?,
+        }
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(output.get_ref()).into_diagnostic()?;
+        return stdout.flush().into_diagnostic();
+    }
+
+    let output = opt.name(&lilac, "stdin", Path::new("."), &format, target)?;
+    let outfile = match &opt.out {
+        Some(out) => out.join(output),
+        None => PathBuf::from(output),
+    };
+    let Some(outfile) = claim(&mut HashSet::new(), paths::sanitize(&outfile)?, opt)? else {
+        print_outputs(Path::new("stdin"), &[]);
+        return crate::OK;
+    };
+    if opt.dry_run {
+        print_outputs(Path::new("stdin"), &[outfile]);
+        return crate::OK;
+    }
     if let Some(p) = outfile.parent() {
         fs::create_dir_all(p).into_diagnostic()?;
     }
+    match target {
+        Target::Lilac => lilac.write_file(&outfile)?,
+        Target::Wav => lilac.to_wav_file(&outfile)?,
+    }
+    status!("{}", tr!("Wrote `{}`", outfile.display()));
+    crate::OK
+}
+
+fn print_outputs(input: &Path, outputs: &[PathBuf]) {
+    if outputs.is_empty() {
+        status!("{}", tr!("`{}` skipped", input.display()));
+        return;
+    }
+    let o: Vec<_> = outputs
+        .iter()
+        .map(|o| format!("`{}`", o.display()))
+        .collect();
+    status!("`{}` -> {}", input.display(), o.join(", "));
+}
+
+enum Outcome {
+    Done(PathBuf, Vec<PathBuf>, Stats),
+    /// Outputs that would have been written in a dry run
+    Planned(PathBuf, Vec<PathBuf>),
+    Interrupted(PathBuf),
+}
+
+fn transcode(plan: Plan, opt: &Opt, bar: &ProgressBar) -> miette::Result<Outcome> {
+    if interrupted() {
+        return Ok(Outcome::Interrupted(plan.filename));
+    }
+    Input::decode(plan, opt, bar)?.write(opt, bar, None)
+}
+
+/// Outputs of an input, named before any input of the batch is transcoded
+struct Plan {
+    filename: PathBuf,
+    format: Format,
+    /// Album tag of the input, read along with the rest of its header
+    album: Option<String>,
+    cue: Option<Cue>,
+    /// Path of the output of each track, `None` for skipped ones
+    outputs: Vec<Option<PathBuf>>,
+}
+
+impl Plan {
+    /// Names the outputs of an input from its header, claiming their paths
+    fn new(
+        filename: PathBuf,
+        header: Lilac,
+        format: Format,
+        opt: &Opt,
+        claimed: &mut HashSet<PathBuf>,
+    ) -> miette::Result<Self> {
+        let target = opt.target(&format);
+        let stem = filename
+            .file_stem()
+            .ok_or_else(|| miette!("Invalid filename"))?
+            .to_string_lossy()
+            .into_owned();
+        let cue = filename.with_extension("cue");
+        let cue = if !opt.no_cue && cue.is_file() {
+            Some(Cue::read_file(&cue)?)
+        } else {
+            None
+        };
+
+        let album = header.album.clone();
+        let outputs = split(header, cue.as_ref())
+            .iter()
+            .map(|lilac| {
+                let stem = match cue {
+                    Some(_) => format!("{} {:02}", stem, lilac.track.unwrap_or_default()),
+                    None => stem.clone(),
+                };
+                let output =
+                    opt.name(lilac, &stem, opt.relative_dir(&filename), &format, target)?;
+                claim(
+                    claimed,
+                    paths::sanitize(&opt.output_path(&filename, output))?,
+                    opt,
+                )
+            })
+            .collect::<miette::Result<_>>()?;
+        Ok(Self {
+            filename,
+            format,
+            album,
+            cue,
+            outputs,
+        })
+    }
+}
+
+/// Splits an input into one track per entry of its CUE sheet, if it has one
+fn split(lilac: Lilac, cue: Option<&Cue>) -> Vec<Lilac> {
+    match cue {
+        Some(cue) => lilac.split_by_cue(cue),
+        None => vec![lilac],
+    }
+}
+
+/// Input decoded and converted, waiting to be written
+struct Input {
+    plan: Plan,
+    lilac: Lilac,
+    /// Checksum of the input as decoded, before any processing
+    source_checksum: Option<u32>,
+    input_size: u64,
+    /// Duration of the input before conversion
+    audio: Duration,
+    start: Instant,
+}
+
+impl Input {
+    fn decode(plan: Plan, opt: &Opt, bar: &ProgressBar) -> miette::Result<Self> {
+        let start = Instant::now();
+        let input_size = fs::metadata(&plan.filename).into_diagnostic()?.len();
+        bar.set_prefix(tr!("decoding"));
+        let reader = BufReader::new(File::open(&plan.filename).into_diagnostic()?);
+        let lilac = decode_as(reader, plan.format)?;
+        let audio = lilac.duration();
+        let source_checksum = opt.verify_hash.then(|| lilac.checksum());
+        Ok(Self {
+            plan,
+            lilac: opt.convert(lilac),
+            source_checksum,
+            input_size,
+            audio,
+            start,
+        })
+    }
+
+    /// Writes the outputs of the input, first changing its volume by the album gain
+    fn write(
+        self,
+        opt: &Opt,
+        bar: &ProgressBar,
+        album_gain: Option<f64>,
+    ) -> miette::Result<Outcome> {
+        let Self {
+            plan,
+            mut lilac,
+            source_checksum,
+            input_size,
+            audio,
+            start,
+        } = self;
+        let Plan {
+            filename,
+            format,
+            cue,
+            outputs,
+            ..
+        } = plan;
+        if interrupted() {
+            return Ok(Outcome::Interrupted(filename));
+        }
+        if let Some(db) = album_gain {
+            lilac.amplify(db);
+        }
+        let target = opt.target(&format);
+
+        // Outputs are written next to their destination and only moved in place
+        // once all of them are complete, so that an interrupted or failed input leaves nothing behind
+        let mut outfiles = Vec::with_capacity(outputs.len());
+        let mut skipped = false;
+        bar.set_prefix(tr!("writing"));
+        let tracks = split(lilac, cue.as_ref());
+        let written = tracks
+            .into_iter()
+            .zip(outputs)
+            .try_for_each(|(lilac, outfile)| {
+                let mut lilac = opt.apply_effects(lilac);
+                // Only the same as the source's without a CUE sheet, which splits it
+                let expected = opt.verify_hash.then(|| lilac.checksum());
+                lilac.source_checksum = source_checksum;
+                let Some(outfile) = outfile else {
+                    skipped = true;
+                    return crate::OK;
+                };
+
+                if let Some(p) = outfile.parent() {
+                    fs::create_dir_all(p).into_diagnostic()?;
+                }
+
+                let part = part_path(&outfile);
+                outfiles.push((part.clone(), outfile.clone()));
+                match target {
+                    Target::Lilac => lilac.write_file(&part)?,
+                    Target::Wav => lilac.to_wav_file(&part)?,
+                }
+                // Make sure the output is on disk before the input can be deleted
+                OpenOptions::new()
+                    .write(true)
+                    .open(&part)
+                    .and_then(|f| f.sync_all())
+                    .into_diagnostic()?;
+
+                if opt.verify_hash {
+                    bar.set_prefix(tr!("verifying"));
+                    let written = match target {
+                        Target::Lilac => Lilac::read_file(&part)?,
+                        Target::Wav => Lilac::from_wav_file(&part)?,
+                    };
+                    if Some(written.checksum()) != expected {
+                        return Err(miette!(
+                            "`{}` doesn't decode to the same audio as `{}`",
+                            outfile.display(),
+                            filename.display()
+                        ));
+                    }
+                }
+                crate::OK
+            });
+
+        if written.is_err() || interrupted() {
+            for (part, _) in &outfiles {
+                fs::remove_file(part).ok();
+            }
+            return written.map(|()| Outcome::Interrupted(filename));
+        }
+        let mut output_size = 0;
+        for (part, outfile) in &outfiles {
+            output_size += fs::metadata(part).into_diagnostic()?.len();
+            fs::rename(part, outfile).into_diagnostic()?;
+        }
+
+        // Inputs with skipped outputs weren't fully transcoded and are always kept
+        if !skipped {
+            if let Some(dir) = &opt.move_originals {
+                let dir = dir.join(opt.relative_dir(&filename));
+                relocate(&filename, &dir, opt.link)?;
+            } else if !opt.keep {
+                fs::remove_file(&filename).into_diagnostic()?;
+            }
+        }
+        let outfiles = outfiles.into_iter().map(|(_, o)| o).collect();
+        let stats = Stats {
+            format: format.extension(),
+            input_size,
+            output_size,
+            audio,
+            elapsed: start.elapsed(),
+        };
+        Ok(Outcome::Done(filename, outfiles, stats))
+    }
+}
+
+/// Moves or links an input into a directory instead of deleting it
+fn relocate(filename: &Path, dir: &Path, link: bool) -> crate::Result {
+    fs::create_dir_all(dir).into_diagnostic()?;
+    let dest = dir.join(
+        filename
+            .file_name()
+            .ok_or_else(|| miette!("Invalid filename"))?,
+    );
+    if dest.exists() {
+        return Err(miette!("`{}` already exists", dest.display()));
+    }
+
+    let relocated = if link {
+        fs::hard_link(filename, &dest)
+    } else {
+        fs::rename(filename, &dest)
+    };
+    // Links and renames don't work across filesystems
+    if relocated.is_err() {
+        fs::copy(filename, &dest).into_diagnostic()?;
+        if !link {
+            fs::remove_file(filename).into_diagnostic()?;
+        }
+    }
+
+    crate::OK
+}
 
-    match format {
-        Format::Lilac => lilac.to_wav_file(&outfile)?,
-        _ => lilac.write_file(&outfile)?,
+/// Reserves an output path for the current batch,
+/// so that two outputs are never written to the same file,
+/// and applies the conflict policy if it already exists
+///
+/// Returns `None` if the output should be skipped.
+fn claim(
+    claimed: &mut HashSet<PathBuf>,
+    path: PathBuf,
+    opt: &Opt,
+) -> miette::Result<Option<PathBuf>> {
+    let rename = if claimed.contains(&path) {
+        match opt.on_collision {
+            Collision::Error => {
+                return Err(miette!(
+                    "`{}` is already the output of another input",
+                    path.display()
+                ))
+            }
+            Collision::Suffix => true,
+        }
+    } else if path.exists() {
+        match opt.on_conflict {
+            Conflict::Overwrite => false,
+            Conflict::Rename => true,
+            Conflict::Skip => return Ok(None),
+            Conflict::Fail => return Err(miette!("`{}` already exists", path.display())),
+        }
+    } else {
+        false
+    };
+
+    let path = if rename {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        (1..)
+            .map(|n| {
+                let mut name = OsString::from(format!("{} ({})", stem, n));
+                if let Some(e) = path.extension() {
+                    name.push(".");
+                    name.push(e);
+                }
+                path.with_file_name(name)
+            })
+            .find(|p| !claimed.contains(p) && !p.exists())
+            .unwrap()
+    } else {
+        path
+    };
+    claimed.insert(path.clone());
+    Ok(Some(path))
+}
+
+/// Replaces the placeholders of an output naming pattern,
+/// leaving unknown ones untouched
+fn expand(
+    pattern: &str,
+    stem: &str,
+    dir: &str,
+    format: &Format,
+    target: Target,
+    lilac: &Lilac,
+) -> String {
+    let track = lilac.track.map(|t| format!("{:02}", t));
+
+    let mut output = String::with_capacity(pattern.len());
+    let mut rest = pattern;
+    while let Some(i) = rest.find('%') {
+        output.push_str(&rest[..i]);
+        let placeholder = rest[i + 1..].chars().next();
+        rest = &rest[i + 1 + placeholder.map_or(0, char::len_utf8)..];
+
+        let (field, default) = match placeholder {
+            Some('T') => (&lilac.title, lilac::UNKNOWN),
+            Some('A') => (&lilac.artist, lilac::UNKNOWN),
+            Some('a') => (&lilac.album, lilac::UNKNOWN),
+            Some('n') => (&track, "00"),
+            Some('F') => {
+                output.push_str(stem);
+                continue;
+            }
+            Some('d') => {
+                output.push_str(dir);
+                continue;
+            }
+            Some('E') => {
+                output.push_str(target.extension());
+                continue;
+            }
+            Some('e') => {
+                output.push_str(format.extension());
+                continue;
+            }
+            Some(c) => {
+                output.push('%');
+                output.push(c);
+                continue;
+            }
+            None => {
+                output.push('%');
+                continue;
+            }
+        };
+        let fallback = match rest.strip_prefix("?{").and_then(|r| r.split_once('}')) {
+            Some((fallback, r)) => {
+                rest = r;
+                fallback
+            }
+            None => default,
+        };
+        output.push_str(field.as_deref().unwrap_or(fallback));
     }
+    output.push_str(rest);
+    output
+}
 
-    if !keep {
-        fs::remove_file(&filename).into_diagnostic()?;
+/// Lists the files with a supported extension in a directory and its subdirectories
+///
+/// Symbolic links to directories aren't followed, as they could form a loop.
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            walk(&path, files)?;
+        } else if path.is_file()
+            && path.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+                ["lilac", "mp3", "flac", "ogg", "wav"].contains(&e.to_lowercase().as_str())
+            })
+        {
+            files.push(path);
+        }
     }
-    Ok((filename, outfile))
+    Ok(())
+}
+
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+    part.into()
 }
 
 fn detect<R: Read + Seek>(mut reader: R) -> miette::Result<(Lilac, Format)> {
+    let format = sniff(&mut reader)?;
+    Ok((decode_as(reader, format)?, format))
+}
+
+/// Infers the format of a file from its magic number, defaulting to LILAC
+fn sniff<R: Read + Seek>(reader: &mut R) -> miette::Result<Format> {
     let magic_numer_len = MP3_MAGIC_NUMBERS
         .iter()
         .fold(0, |max, n| max.max(n.len()))
@@ -115,19 +1259,45 @@ fn detect<R: Read + Seek>(mut reader: R) -> miette::Result<(Lilac, Format)> {
     reader.read_exact(&mut magic_number).into_diagnostic()?;
     reader.seek(SeekFrom::Start(0)).into_diagnostic()?;
 
-    let result = if MP3_MAGIC_NUMBERS
+    let format = if MP3_MAGIC_NUMBERS
         .iter()
         .any(|n| &magic_number[..n.len()] == *n)
     {
-        (Lilac::from_mp3(reader)?, Format::Mp3)
+        Format::Mp3
     } else if FLAC_MAGIC_NUMBER == &magic_number[..FLAC_MAGIC_NUMBER.len()] {
-        (Lilac::from_flac(reader)?, Format::Flac)
+        Format::Flac
     } else if OGG_MAGIC_NUMBER == &magic_number[..OGG_MAGIC_NUMBER.len()] {
-        (Lilac::from_ogg(reader)?, Format::Ogg)
-    } else if WAV_MAGIC_NUMBER == &magic_number[WAV_MAGIC_NUMBER_OFFSET..WAV_MAGIC_NUMBER.len()] {
-        (Lilac::from_wav(reader)?, Format::Wav)
+        Format::Ogg
+    } else if WAV_MAGIC_NUMBER
+        == &magic_number[WAV_MAGIC_NUMBER_OFFSET..WAV_MAGIC_NUMBER_OFFSET + WAV_MAGIC_NUMBER.len()]
+    {
+        Format::Wav
     } else {
-        (Lilac::read(reader)?, Format::Lilac)
+        Format::Lilac
     };
-    Ok(result)
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use lilac::testing::{self, Fixture, Signal};
+
+    use super::*;
+
+    #[test]
+    fn detect_wav_and_lilac() {
+        let expected = Fixture::new(Signal::Tone(440.0)).tagged().build();
+
+        let mut wav = Cursor::new(Vec::new());
+        expected.to_wav(&mut wav).unwrap();
+        let (actual, format) = detect(Cursor::new(wav.into_inner())).unwrap();
+        assert!(matches!(format, Format::Wav));
+        testing::assert_similar(&expected, &actual, 0.0);
+
+        let mut file = Vec::new();
+        expected.write(&mut file).unwrap();
+        let (actual, format) = detect(Cursor::new(file)).unwrap();
+        assert!(matches!(format, Format::Lilac));
+        assert_eq!(expected, actual);
+    }
 }
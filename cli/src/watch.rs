@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{fs, thread};
+
+use miette::{miette, Context, IntoDiagnostic};
+use serde::Deserialize;
+
+use crate::transcode::{self, Opt, Target};
+
+/// Rules file of the watch daemon
+///
+/// ```toml
+/// interval = 10
+///
+/// [[rule]]
+/// dir = "/srv/inbox/phone"
+/// glob = "*.flac"
+/// preset = "phone"
+/// out = "/srv/library"
+/// ```
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Rules {
+    /// Seconds between two scans of the watched directories
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// Attempts after the first one before giving up on a file
+    #[serde(default = "default_retries")]
+    retries: u32,
+    #[serde(rename = "rule")]
+    rules: Vec<Rule>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct Rule {
+    /// Watched directory
+    dir: PathBuf,
+    /// Glob matching the files to transcode in the directory
+    #[serde(default = "default_glob")]
+    glob: String,
+    preset: Option<String>,
+    /// Output naming pattern, as for transcode
+    pattern: Option<String>,
+    /// Destination directory, next to the inputs by default,
+    /// in which case the glob must not match LILAC or WAV files
+    out: Option<PathBuf>,
+    /// Keep inputs after transcoding instead of deleting them
+    #[serde(default)]
+    keep: bool,
+}
+
+impl Rule {
+    /// Rejects rules whose outputs would be transcoded again by the same rule,
+    /// which would go back and forth between formats forever
+    fn check(&self) -> crate::Result {
+        let same_dir = match &self.out {
+            None => true,
+            Some(out) => match (out.canonicalize(), self.dir.canonicalize()) {
+                (Ok(out), Ok(dir)) => out == dir,
+                _ => out == &self.dir,
+            },
+        };
+        let pattern = glob::Pattern::new(&self.glob)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Invalid glob `{}`", self.glob))?;
+        let matches_outputs = [Target::Lilac, Target::Wav]
+            .into_iter()
+            .any(|t| pattern.matches(&format!("output.{}", t.extension())));
+        if same_dir && matches_outputs {
+            return Err(miette!(
+                "The rule for `{}` would pick up its own outputs, \
+                 set `out` to another directory or narrow its `glob`",
+                self.dir.display()
+            ));
+        }
+        crate::OK
+    }
+}
+
+fn default_interval() -> u64 {
+    5
+}
+fn default_retries() -> u32 {
+    3
+}
+fn default_glob() -> String {
+    "*".to_owned()
+}
+
+/// What the daemon knows about a matched file
+enum Seen {
+    /// Waiting for its size to stop changing, in case it is still being copied
+    Settling(u64),
+    /// Failed this many times
+    Failed(u32),
+    /// Transcoded, given up on or kept in place
+    Done,
+}
+
+pub fn main(config: PathBuf) -> crate::Result {
+    let rules = fs::read_to_string(&config)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read `{}`", config.display()))?;
+    let rules: Rules = toml::from_str(&rules)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Invalid rules file `{}`", config.display()))?;
+    for rule in &rules.rules {
+        rule.check()?;
+    }
+
+    transcode::handle_interrupts()?;
+    status!(
+        "{}",
+        tr!(
+            "Watching {} directories, press Ctrl+C to stop",
+            rules.rules.len()
+        )
+    );
+
+    let mut seen = HashMap::new();
+    while !transcode::interrupted() {
+        for rule in &rules.rules {
+            if let Err(e) = scan(rule, rules.retries, &mut seen) {
+                eprintln!("{:#}", e);
+            }
+        }
+        thread::sleep(Duration::from_secs(rules.interval));
+    }
+
+    crate::OK
+}
+
+fn scan(rule: &Rule, retries: u32, seen: &mut HashMap<PathBuf, Seen>) -> crate::Result {
+    let glob = rule.dir.join(&rule.glob);
+    for file in glob::glob(&glob.to_string_lossy()).into_diagnostic()? {
+        let file = file.into_diagnostic()?;
+        if transcode::interrupted() {
+            break;
+        }
+        if !file.is_file() || file.extension().is_some_and(|e| e == "part") {
+            continue;
+        }
+
+        let size = fs::metadata(&file).into_diagnostic()?.len();
+        let attempts = match seen.get(&file) {
+            None => {
+                seen.insert(file, Seen::Settling(size));
+                continue;
+            }
+            Some(Seen::Settling(s)) if *s != size => {
+                seen.insert(file, Seen::Settling(size));
+                continue;
+            }
+            Some(Seen::Settling(_)) => 0,
+            Some(Seen::Failed(n)) => *n,
+            Some(Seen::Done) => continue,
+        };
+
+        let state = match process(rule, &file) {
+            Ok(outfiles) => {
+                let o: Vec<_> = outfiles
+                    .iter()
+                    .map(|o| format!("`{}`", o.display()))
+                    .collect();
+                status!("`{}` -> {}", file.display(), o.join(", "));
+                // Outputs written to a watched directory aren't inputs
+                for outfile in outfiles {
+                    seen.insert(outfile, Seen::Done);
+                }
+                Seen::Done
+            }
+            Err(e) if attempts < retries => {
+                eprintln!(
+                    "{}: {:#}",
+                    tr!("Failed to transcode `{}`, will retry", file.display()),
+                    e
+                );
+                Seen::Failed(attempts + 1)
+            }
+            Err(e) => {
+                eprintln!("{}: {:#}", tr!("Giving up on `{}`", file.display()), e);
+                Seen::Done
+            }
+        };
+        seen.insert(file, state);
+    }
+
+    // Forget files that were removed so that they are picked up again if they come back
+    seen.retain(|f, _| f.exists());
+    crate::OK
+}
+
+fn process(rule: &Rule, file: &Path) -> miette::Result<Vec<PathBuf>> {
+    let mut opt = Opt::for_file(
+        file.to_owned(),
+        rule.preset.clone(),
+        rule.pattern.clone(),
+        rule.out.clone(),
+        rule.keep,
+    );
+    opt.prepare()?;
+    transcode::transcode_file(&opt)
+}
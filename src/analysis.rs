@@ -0,0 +1,178 @@
+use std::f32::consts::{PI, TAU};
+
+use serde::Serialize;
+
+use crate::Lilac;
+
+/// Frames analysed at once
+const WINDOW: usize = 4096;
+/// Most windows analysed, spread evenly over the track
+const WINDOWS: usize = 256;
+/// Lowest frequency looked at for a cutoff, lossy encoders never cut lower
+const LOWEST_CUTOFF: f32 = 10_000.0;
+/// Width of the bands compared on each side of a possible cutoff, in hertz
+const BAND: f32 = 300.0;
+/// Smallest difference between both bands for a cutoff to count, in decibels
+const SHARP_DROP: f32 = 20.0;
+/// Encoders keep everything below this frequency only at their highest bitrates,
+/// which can't be told apart from lossless
+const LOSSY_CUTOFF: f32 = 20_500.0;
+
+/// Result of [`Lilac::authenticity`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Authenticity {
+    /// Frequency in hertz above which the spectrum falls off sharply, if it does
+    pub cutoff: Option<f32>,
+    /// How much quieter the spectrum gets across the cutoff, in decibels
+    pub drop: f32,
+    pub verdict: Verdict,
+}
+
+/// Most likely origin of a track, judging by its spectrum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+    /// Content goes up to the top of the spectrum or fades out gradually
+    Clean,
+    /// Cut off below 20 kHz, like MP3 and AAC encoders do
+    Lossy,
+    /// Cut off at the Nyquist frequency of the given lower sample rate
+    Upsampled(u32),
+    /// Too short or too quiet to tell
+    Inconclusive,
+}
+
+impl Lilac {
+    /// Looks for signs that a track was decoded from a lossy format or upsampled
+    /// before being stored losslessly
+    ///
+    /// Lossy encoders discard everything above a cutoff frequency for the whole track,
+    /// which shows up as a cliff in the average spectrum where lossless recordings
+    /// roll off gradually.
+    pub fn authenticity(&self) -> Authenticity {
+        let inconclusive = Authenticity {
+            cutoff: None,
+            drop: 0.0,
+            verdict: Verdict::Inconclusive,
+        };
+        let Some(db) = self.average_spectrum() else {
+            return inconclusive;
+        };
+
+        let resolution = self.sample_rate as f32 / WINDOW as f32;
+        let band = ((BAND / resolution).ceil() as usize).max(2);
+        let lowest = ((LOWEST_CUTOFF / resolution) as usize).max(band);
+        let mean = |bins: &[f32]| bins.iter().sum::<f32>() / bins.len() as f32;
+
+        let Some((bin, drop)) = (lowest..db.len().saturating_sub(band))
+            .map(|k| (k, mean(&db[k - band..k]) - mean(&db[k..k + band])))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+        else {
+            return inconclusive;
+        };
+        if drop < SHARP_DROP {
+            return Authenticity {
+                cutoff: None,
+                drop,
+                verdict: Verdict::Clean,
+            };
+        }
+
+        let cutoff = bin as f32 * resolution;
+        let upsampled = [44_100, 48_000]
+            .into_iter()
+            .filter(|&rate| rate < self.sample_rate)
+            .find(|&rate| (rate as f32 / 2.0 * 0.9..=rate as f32 / 2.0).contains(&cutoff));
+        let verdict = match upsampled {
+            Some(rate) => Verdict::Upsampled(rate),
+            None if cutoff < LOSSY_CUTOFF => Verdict::Lossy,
+            None => Verdict::Clean,
+        };
+        Authenticity {
+            cutoff: Some(cutoff),
+            drop,
+            verdict,
+        }
+    }
+
+    /// Power of each frequency bin averaged over the track in decibels,
+    /// leaving out silent windows
+    fn average_spectrum(&self) -> Option<Vec<f32>> {
+        let channels = self.channels as usize;
+        let frames = self.frame_count();
+        if frames < WINDOW {
+            return None;
+        }
+        let normalize = self.normalizer();
+        let count = WINDOWS.min(frames / WINDOW);
+
+        let mut power = vec![0f64; WINDOW / 2];
+        let mut used = 0;
+        let (mut re, mut im) = (vec![0f32; WINDOW], vec![0f32; WINDOW]);
+        for w in 0..count {
+            let start = (frames - WINDOW) * w / (count - 1).max(1);
+            let samples = &self.samples[start * channels..(start + WINDOW) * channels];
+            for (i, frame) in samples.chunks_exact(channels).enumerate() {
+                let mix = frame.iter().map(|&s| normalize(s)).sum::<f32>() / channels as f32;
+                // Hann window, to avoid leakage from the cut at both ends
+                re[i] = mix * (PI * i as f32 / WINDOW as f32).sin().powi(2);
+            }
+            if re.iter().all(|&s| s.abs() < 1e-4) {
+                continue;
+            }
+            im.fill(0.0);
+            fft(&mut re, &mut im);
+            for (p, (&r, &i)) in power.iter_mut().zip(re.iter().zip(&im)) {
+                *p += (r * r + i * i) as f64;
+            }
+            used += 1;
+        }
+        if used == 0 {
+            return None;
+        }
+
+        Some(
+            power
+                .into_iter()
+                .map(|p| (10.0 * (p / used as f64 + 1e-20).log10()) as f32)
+                .collect(),
+        )
+    }
+}
+
+/// In-place radix-2 fast Fourier transform, for lengths that are powers of two
+fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -TAU / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let (r, i) = (re[b] * cos - im[b] * sin, re[b] * sin + im[b] * cos);
+                re[b] = re[a] - r;
+                im[b] = im[a] - i;
+                re[a] += r;
+                im[a] += i;
+            }
+        }
+        len <<= 1;
+    }
+}
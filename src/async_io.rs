@@ -0,0 +1,73 @@
+use std::io;
+#[cfg(any(feature = "mp3", feature = "flac", feature = "ogg", feature = "wav"))]
+use std::io::Cursor;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task;
+
+use crate::{Error, Lilac};
+
+/// Asynchronous variants of the IO methods
+///
+/// Since the underlying decoders are synchronous,
+/// inputs are read into memory first and decoded from there on the blocking thread pool,
+/// and outputs are encoded into memory before being written.
+impl Lilac {
+    pub async fn read_async<R: AsyncRead + Unpin>(reader: R) -> Result<Self, Error> {
+        decode(reader, |buf| Self::read(buf.as_slice())).await
+    }
+    pub async fn write_async<W: AsyncWrite + Unpin>(&self, writer: W) -> Result<(), Error> {
+        let mut buf = Vec::new();
+        self.write(&mut buf)?;
+        write_all(writer, &buf).await.map_err(Into::into)
+    }
+
+    #[cfg(feature = "mp3")]
+    pub async fn from_mp3_async<R: AsyncRead + Unpin>(reader: R) -> Result<Self, Error> {
+        decode(reader, |buf| Self::from_mp3(Cursor::new(buf))).await
+    }
+
+    #[cfg(feature = "flac")]
+    pub async fn from_flac_async<R: AsyncRead + Unpin>(reader: R) -> Result<Self, Error> {
+        decode(reader, |buf| Self::from_flac(Cursor::new(buf))).await
+    }
+
+    #[cfg(feature = "ogg")]
+    pub async fn from_ogg_async<R: AsyncRead + Unpin>(reader: R) -> Result<Self, Error> {
+        decode(reader, |buf| Self::from_ogg(Cursor::new(buf))).await
+    }
+
+    #[cfg(feature = "wav")]
+    pub async fn from_wav_async<R: AsyncRead + Unpin>(reader: R) -> Result<Self, Error> {
+        decode(reader, |buf| Self::from_wav(Cursor::new(buf))).await
+    }
+    #[cfg(feature = "wav")]
+    pub async fn to_wav_async<W: AsyncWrite + Unpin>(&self, writer: W) -> Result<(), Error> {
+        let mut buf = Cursor::new(Vec::new());
+        self.to_wav(&mut buf)?;
+        write_all(writer, buf.get_ref()).await.map_err(Into::into)
+    }
+}
+
+/// Reads a whole input and decodes it without blocking the runtime
+async fn decode<R, F>(reader: R, decode: F) -> Result<Lilac, Error>
+where
+    R: AsyncRead + Unpin,
+    F: FnOnce(Vec<u8>) -> Result<Lilac, Error> + Send + 'static,
+{
+    let buf = read_all(reader).await?;
+    task::spawn_blocking(move || decode(buf))
+        .await
+        .map_err(io::Error::from)?
+}
+
+async fn read_all<R: AsyncRead + Unpin>(mut reader: R) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf).await?;
+    Ok(buf)
+}
+
+async fn write_all<W: AsyncWrite + Unpin>(mut writer: W, buf: &[u8]) -> io::Result<()> {
+    writer.write_all(buf).await?;
+    writer.flush().await
+}
@@ -0,0 +1,91 @@
+use serde::Serialize;
+
+/// Formats and optional features the library was compiled with
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize)]
+pub struct Capabilities {
+    /// MP3 decoding, from the `mp3` feature
+    pub mp3: bool,
+    /// FLAC decoding, from the `flac` feature
+    pub flac: bool,
+    /// Ogg Vorbis decoding, from the `ogg` feature
+    pub ogg: bool,
+    /// WAV decoding and encoding, from the `wav` feature
+    pub wav: bool,
+    /// Reading and writing files by path, from the `fs` feature
+    pub fs: bool,
+    /// Playback through rodio, from the `playback` feature
+    pub playback: bool,
+    /// Spilling large tracks to disk, from the `spill` feature
+    pub spill: bool,
+    /// Async reading and writing, from the `tokio` feature
+    pub tokio: bool,
+    /// Fixtures for tests, from the `testing` feature
+    pub testing: bool,
+}
+
+pub const fn capabilities() -> Capabilities {
+    Capabilities {
+        mp3: cfg!(feature = "mp3"),
+        flac: cfg!(feature = "flac"),
+        ogg: cfg!(feature = "ogg"),
+        wav: cfg!(feature = "wav"),
+        fs: cfg!(feature = "fs"),
+        playback: cfg!(feature = "playback"),
+        spill: cfg!(feature = "spill"),
+        tokio: cfg!(feature = "tokio"),
+        testing: cfg!(feature = "testing"),
+    }
+}
+
+impl Capabilities {
+    /// Formats that can be read, by file extension
+    pub fn decoders(&self) -> Vec<&'static str> {
+        let optional = [
+            ("mp3", self.mp3),
+            ("flac", self.flac),
+            ("ogg", self.ogg),
+            ("wav", self.wav),
+        ];
+        Self::available("lilac", &optional)
+    }
+    /// Formats that can be written, by file extension
+    pub fn encoders(&self) -> Vec<&'static str> {
+        Self::available("lilac", &[("wav", self.wav)])
+    }
+    /// Optional features that were enabled, formats aside
+    pub fn features(&self) -> Vec<&'static str> {
+        [
+            ("fs", self.fs),
+            ("playback", self.playback),
+            ("spill", self.spill),
+            ("tokio", self.tokio),
+            ("testing", self.testing),
+        ]
+        .into_iter()
+        .filter_map(|(name, enabled)| enabled.then_some(name))
+        .collect()
+    }
+
+    /// Feature to enable to read files with the given extension, if it is missing
+    ///
+    /// Returns `None` for supported and unknown extensions.
+    pub fn missing_decoder(&self, extension: &str) -> Option<&'static str> {
+        match extension.to_lowercase().as_str() {
+            "mp3" if !self.mp3 => Some("mp3"),
+            "flac" if !self.flac => Some("flac"),
+            "ogg" if !self.ogg => Some("ogg"),
+            "wav" if !self.wav => Some("wav"),
+            _ => None,
+        }
+    }
+
+    fn available(always: &'static str, optional: &[(&'static str, bool)]) -> Vec<&'static str> {
+        std::iter::once(always)
+            .chain(
+                optional
+                    .iter()
+                    .filter_map(|&(name, enabled)| enabled.then_some(name)),
+            )
+            .collect()
+    }
+}
@@ -0,0 +1,223 @@
+use std::time::Duration;
+
+use crate::{Error, Lilac};
+
+/// CUE sheet describing the tracks of a single-file rip
+///
+/// Sheets referencing more than one file are rejected.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+pub struct Cue {
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub year: Option<i32>,
+    pub file: Option<String>,
+
+    pub tracks: Vec<CueTrack>,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct CueTrack {
+    pub number: u32,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    /// Position of `INDEX 01` in the file
+    pub start: Duration,
+}
+
+impl Cue {
+    pub fn parse(sheet: &str) -> Result<Self, Error> {
+        let mut cue = Cue::default();
+        let mut track: Option<CueTrack> = None;
+        let mut indexed = false;
+
+        for (i, line) in sheet.trim_start_matches('\u{feff}').lines().enumerate() {
+            let err = |message: &str| Error::Cue(i + 1, message.to_owned());
+
+            let args = split(line);
+            let Some((command, args)) = args.split_first() else {
+                continue;
+            };
+            let arg = |n: usize| args.get(n).cloned();
+
+            match (command.to_ascii_uppercase().as_str(), &mut track) {
+                ("REM", _) => {
+                    if arg(0).is_some_and(|a| a.eq_ignore_ascii_case("DATE")) {
+                        cue.year = arg(1).and_then(|d| d.get(..4)?.parse().ok());
+                    }
+                }
+                ("FILE", _) => {
+                    // Offsets are relative to each file, which can't be split as a single track
+                    if cue.file.is_some() {
+                        return Err(err("sheets with more than one FILE aren't supported"));
+                    }
+                    cue.file = arg(0);
+                }
+                ("TITLE", None) => cue.title = arg(0),
+                ("TITLE", Some(t)) => t.title = arg(0),
+                ("PERFORMER", None) => cue.performer = arg(0),
+                ("PERFORMER", Some(t)) => t.performer = arg(0),
+                ("TRACK", _) => {
+                    let number = arg(0)
+                        .and_then(|n| n.parse().ok())
+                        .ok_or_else(|| err("invalid track number"))?;
+                    let next = CueTrack {
+                        number,
+                        title: None,
+                        performer: None,
+                        start: Duration::ZERO,
+                    };
+                    if let Some(t) = track.replace(next) {
+                        if !indexed {
+                            return Err(err("previous track has no INDEX 01"));
+                        }
+                        cue.tracks.push(t);
+                    }
+                    indexed = false;
+                }
+                ("INDEX", None) => return Err(err("INDEX outside of a track")),
+                ("INDEX", Some(t)) => {
+                    if arg(0).and_then(|n| n.parse::<u32>().ok()) == Some(1) {
+                        t.start = arg(1)
+                            .as_deref()
+                            .and_then(parse_time)
+                            .ok_or_else(|| err("invalid INDEX time"))?;
+                        indexed = true;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        if let Some(t) = track {
+            if !indexed {
+                let line = sheet.lines().count();
+                return Err(Error::Cue(line, "last track has no INDEX 01".to_owned()));
+            }
+            cue.tracks.push(t);
+        }
+        Ok(cue)
+    }
+}
+
+impl Lilac {
+    /// Splits a single-file rip into one track per entry of the CUE sheet
+    ///
+    /// Each track runs from its `INDEX 01` to the next track's,
+    /// and gets its metadata from the sheet, falling back to the file's own.
+    pub fn split_by_cue(&self, cue: &Cue) -> Vec<Lilac> {
+        cue.tracks
+            .iter()
+            .enumerate()
+            .map(|(i, t)| {
+                let end = cue.tracks.get(i + 1).map_or(Duration::MAX, |n| n.start);
+                let mut lilac = self.slice(t.start..end);
+
+                lilac.title = t.title.clone().or(lilac.title);
+                lilac.artist = t
+                    .performer
+                    .clone()
+                    .or_else(|| cue.performer.clone())
+                    .or(lilac.artist);
+                lilac.album = cue.title.clone().or(lilac.album);
+                lilac.year = cue.year.or(lilac.year);
+                lilac.track = Some(t.number);
+                lilac
+            })
+            .collect()
+    }
+}
+
+/// Splits a line into whitespace separated arguments, honouring double quotes
+fn split(line: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut chars = line.trim().chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '"' {
+            chars.next();
+            args.push(chars.by_ref().take_while(|&c| c != '"').collect());
+        } else {
+            let mut arg = String::new();
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+            args.push(arg);
+        }
+    }
+    args
+}
+
+/// Parses a `MM:SS:FF` timestamp, where frames are 1/75th of a second
+fn parse_time(time: &str) -> Option<Duration> {
+    let mut parts = time.split(':').map(|p| p.parse::<u64>().ok());
+    let (m, s, f) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || s >= 60 || f >= 75 {
+        return None;
+    }
+
+    let frames = (m * 60 + s) * 75 + f;
+    // Round up so that converting back to samples lands exactly on the frame boundary
+    Some(Duration::from_nanos((frames * 1_000_000_000).div_ceil(75)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sheet() {
+        let cue = Cue::parse(
+            "PERFORMER \"Lilac\"\n\
+             TITLE \"Tests\"\n\
+             FILE \"rip.wav\" WAVE\n\
+             \x20 TRACK 01 AUDIO\n\
+             \x20   TITLE \"One\"\n\
+             \x20   INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\n\
+             \x20   INDEX 00 03:59:60\n\
+             \x20   INDEX 01 04:01:30\n",
+        )
+        .unwrap();
+
+        assert_eq!(cue.file.as_deref(), Some("rip.wav"));
+        assert_eq!(cue.tracks.len(), 2);
+        assert_eq!(cue.tracks[0].title.as_deref(), Some("One"));
+        assert_eq!(cue.tracks[0].start, Duration::ZERO);
+        assert_eq!(cue.tracks[1].start, Duration::from_millis(241_400));
+    }
+
+    #[test]
+    fn parse_time_frames() {
+        assert_eq!(parse_time("00:00:75"), None);
+        assert_eq!(parse_time("00:60:00"), None);
+        assert_eq!(parse_time("01:02"), None);
+        assert_eq!(parse_time("00:01:00"), Some(Duration::from_secs(1)));
+        // A single frame doesn't divide evenly into nanoseconds, so it's rounded up
+        assert_eq!(
+            parse_time("00:00:01"),
+            Some(Duration::from_nanos(13_333_334))
+        );
+    }
+
+    #[test]
+    fn missing_index() {
+        let err =
+            Cue::parse("FILE \"rip.wav\" WAVE\nTRACK 01 AUDIO\nTRACK 02 AUDIO\nINDEX 01 00:00:00")
+                .unwrap_err();
+        assert!(matches!(err, Error::Cue(3, _)));
+
+        let err = Cue::parse("FILE \"rip.wav\" WAVE\nTRACK 01 AUDIO").unwrap_err();
+        assert!(matches!(err, Error::Cue(2, _)));
+    }
+
+    #[test]
+    fn multiple_files() {
+        let err = Cue::parse(
+            "FILE \"1.wav\" WAVE\nTRACK 01 AUDIO\nINDEX 01 00:00:00\n\
+             FILE \"2.wav\" WAVE\nTRACK 02 AUDIO\nINDEX 01 00:00:00",
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::Cue(4, _)));
+    }
+}
@@ -0,0 +1,564 @@
+use std::cmp::Ordering;
+use std::f64::consts::TAU;
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::{Bookmark, Error, Lilac, SampleFormat};
+
+impl Lilac {
+    pub fn to_mono(self) -> Self {
+        self.remix(1)
+    }
+    pub fn to_stereo(self) -> Self {
+        self.remix(2)
+    }
+
+    /// Changes the channel count of the track
+    ///
+    /// When downmixing, every output channel is the average of the input channels
+    /// that map to it (`input % channels == output`), so mono gets the average of all channels.
+    /// When upmixing, input channels are duplicated cyclically across the output channels.
+    pub fn remix(mut self, channels: u16) -> Self {
+        assert_ne!(channels, 0, "cannot remix to zero channels");
+        if channels == self.channels {
+            return self;
+        }
+
+        let from = self.channels as usize;
+        let to = channels as usize;
+
+        let mut samples = Vec::with_capacity(self.samples.len() / from * to);
+        for frame in self.samples.chunks_exact(from) {
+            if to < from {
+                for c in 0..to {
+                    let mixed = frame.iter().skip(c).step_by(to);
+                    samples.push(match self.sample_format {
+                        SampleFormat::Int => {
+                            let (sum, n) =
+                                mixed.fold((0i64, 0i64), |(sum, n), &s| (sum + s as i64, n + 1));
+                            (sum / n) as i32
+                        }
+                        SampleFormat::Float => {
+                            let (sum, n) = mixed.fold((0f64, 0f64), |(sum, n), &s| {
+                                (sum + f32::from_bits(s as u32) as f64, n + 1.0)
+                            });
+                            ((sum / n) as f32).to_bits() as i32
+                        }
+                    });
+                }
+            } else {
+                samples.extend((0..to).map(|c| frame[c % from]));
+            }
+        }
+
+        self.channels = channels;
+        self.samples = samples.into();
+        self.source_checksum = None;
+        self
+    }
+
+    /// Copies the given time range, moving bookmarks along and dropping those outside of it
+    pub fn slice(&self, range: Range<Duration>) -> Self {
+        let bookmarks = self.bookmarks_within(range.clone());
+        let range = self.sample_range(range);
+        let mut slice = self.with_samples(self.samples[range].to_vec());
+        slice.bookmarks = bookmarks;
+        slice
+    }
+    /// Keeps the given time range, moving bookmarks along and dropping those outside of it
+    pub fn trim(&mut self, range: Range<Duration>) {
+        self.bookmarks = self.bookmarks_within(range.clone());
+        let range = self.sample_range(range);
+        self.samples.truncate(range.end);
+        self.samples.remove_start(range.start);
+        self.source_checksum = None;
+    }
+
+    /// Appends the samples of all tracks, keeping the metadata of the first one
+    ///
+    /// All tracks must share the same channel count, sample rate and sample format.
+    /// Tracks with a lower bit depth are scaled up to the highest one.
+    pub fn concat(tracks: &[Lilac]) -> Result<Self, Error> {
+        let (first, rest) = tracks.split_first().ok_or(Error::Empty)?;
+        for t in rest {
+            if t.channels != first.channels {
+                return Err(Error::ChannelMismatch(first.channels, t.channels));
+            }
+            if t.sample_rate != first.sample_rate {
+                return Err(Error::SampleRateMismatch(first.sample_rate, t.sample_rate));
+            }
+            if t.sample_format != first.sample_format {
+                return Err(Error::SampleFormatMismatch(
+                    first.sample_format,
+                    t.sample_format,
+                ));
+            }
+        }
+
+        let bit_depth = tracks
+            .iter()
+            .map(|t| t.bit_depth)
+            .max()
+            .unwrap_or(first.bit_depth);
+        let mut samples = Vec::with_capacity(tracks.iter().map(|t| t.samples.len()).sum());
+        for t in tracks {
+            let shift = bit_depth - t.bit_depth;
+            samples.extend(t.samples.iter().map(|s| s << shift));
+        }
+
+        let mut lilac = first.with_samples(samples);
+        lilac.bit_depth = bit_depth;
+        Ok(lilac)
+    }
+
+    /// Converts samples to another bit depth, rounding them when it decreases
+    ///
+    /// Float samples are quantized to integers of the given bit depth.
+    pub fn set_bit_depth(&mut self, bit_depth: u32) {
+        assert!(
+            (1..=32).contains(&bit_depth),
+            "bit depth must be between 1 and 32"
+        );
+        if self.sample_format == SampleFormat::Float {
+            self.bit_depth = bit_depth;
+            self.sample_format = SampleFormat::Int;
+
+            let (min, max) = self.sample_bounds();
+            for s in self.samples.iter_mut() {
+                *s = (f32::from_bits(*s as u32) as f64 * -min)
+                    .round()
+                    .clamp(min, max) as i32;
+            }
+        } else if bit_depth > self.bit_depth {
+            let shift = bit_depth - self.bit_depth;
+            for s in self.samples.iter_mut() {
+                *s <<= shift;
+            }
+        } else if bit_depth < self.bit_depth {
+            let shift = self.bit_depth - bit_depth;
+            let max = (1i64 << (bit_depth - 1)) - 1;
+            for s in self.samples.iter_mut() {
+                *s = ((*s as i64 + (1 << (shift - 1))) >> shift).min(max) as i32;
+            }
+        }
+        self.bit_depth = bit_depth;
+        self.source_checksum = None;
+    }
+
+    /// Same as [`Lilac::set_bit_depth`], adding triangular dither when precision is lost
+    ///
+    /// Instead of the distortion caused by rounding, quiet passages get a little noise,
+    /// which is what should be used when going down to 16 bits for playback.
+    pub fn dither(&mut self, bit_depth: u32) {
+        assert!(
+            (1..=32).contains(&bit_depth),
+            "bit depth must be between 1 and 32"
+        );
+        let scale = match self.sample_format {
+            SampleFormat::Int if bit_depth >= self.bit_depth => {
+                self.set_bit_depth(bit_depth);
+                return;
+            }
+            SampleFormat::Int => 2f64.powi(bit_depth as i32 - self.bit_depth as i32),
+            SampleFormat::Float => 2f64.powi(bit_depth as i32 - 1),
+        };
+        let float = self.sample_format == SampleFormat::Float;
+        self.bit_depth = bit_depth;
+        self.sample_format = SampleFormat::Int;
+        self.source_checksum = None;
+
+        let (min, max) = self.sample_bounds();
+        let mut noise = Noise::new(0);
+        for s in self.samples.iter_mut() {
+            let sample = if float {
+                f32::from_bits(*s as u32) as f64
+            } else {
+                *s as f64
+            };
+            *s = (sample * scale + noise.triangular())
+                .round()
+                .clamp(min, max) as i32;
+        }
+    }
+
+    /// Feeds the low frequencies of each channel of a stereo track into the other,
+    /// the way speakers reach both ears, making hard-panned mixes easier on headphones
+    ///
+    /// Tracks that aren't stereo are left as they are.
+    pub fn crossfeed(&mut self) {
+        if self.channels != 2 {
+            return;
+        }
+        let format = self.sample_format;
+        let (min, max) = self.sample_bounds();
+        let mut filter = Crossfeed::new(self.sample_rate);
+        self.source_checksum = None;
+
+        for frame in self.samples.chunks_exact_mut(2) {
+            let [left, right] = [frame[0], frame[1]].map(|s| match format {
+                SampleFormat::Int => s as f64,
+                SampleFormat::Float => f32::from_bits(s as u32) as f64,
+            });
+            let (left, right) = filter.process(left, right);
+            for (s, value) in frame.iter_mut().zip([left, right]) {
+                *s = match format {
+                    SampleFormat::Int => value.round().clamp(min, max) as i32,
+                    SampleFormat::Float => (value as f32).to_bits() as i32,
+                };
+            }
+        }
+    }
+
+    /// Changes the volume by the given amount of decibels, clipping samples that overflow
+    pub fn amplify(&mut self, db: f64) {
+        let gain = db_to_gain(db);
+        self.apply_gain(0..self.frame_count(), |_| gain);
+    }
+    /// Scales the track so that its loudest sample peaks at the given amount of decibels
+    /// relative to full scale
+    pub fn normalize(&mut self, db: f64) {
+        let peak = self.peak();
+        if peak == 0.0 {
+            return;
+        }
+
+        let gain = db_to_gain(db) / peak;
+        self.apply_gain(0..self.frame_count(), |_| gain);
+    }
+    /// Largest absolute sample value relative to full scale
+    pub fn peak(&self) -> f64 {
+        match self.sample_format {
+            SampleFormat::Int => {
+                let peak = self
+                    .samples
+                    .iter()
+                    .map(|s| s.unsigned_abs())
+                    .max()
+                    .unwrap_or(0);
+                peak as f64 / self.sample_bounds().1
+            }
+            SampleFormat::Float => self
+                .samples
+                .iter()
+                .map(|&s| f32::from_bits(s as u32).abs() as f64)
+                .fold(0.0, f64::max),
+        }
+    }
+
+    /// Linearly fades in from silence over the given duration
+    pub fn fade_in(&mut self, duration: Duration) {
+        let len = self.frame_at(duration);
+        self.apply_gain(0..len, |i| i as f64 / len as f64);
+    }
+    /// Linearly fades out to silence over the given duration
+    pub fn fade_out(&mut self, duration: Duration) {
+        let frames = self.frame_count();
+        let len = self.frame_at(duration);
+        self.apply_gain(frames - len..frames, |i| (len - i - 1) as f64 / len as f64);
+    }
+
+    /// Summarizes each channel over the given number of equal parts of the track,
+    /// for rendering waveforms
+    ///
+    /// The result is indexed by channel then by bucket,
+    /// and buckets left empty by tracks shorter than them are all zero.
+    pub fn peaks(&self, buckets: usize) -> Vec<Vec<Peak>> {
+        let channels = self.channels as usize;
+        let frames = self.frame_count();
+        let normalize = self.normalizer();
+
+        let mut peaks: Vec<_> = (0..channels).map(|_| Vec::with_capacity(buckets)).collect();
+        let mut squares = vec![0f64; channels];
+        for b in 0..buckets {
+            let range = frames * b / buckets..frames * (b + 1) / buckets;
+            let len = range.len();
+
+            let mut bucket = vec![
+                Peak {
+                    min: f32::INFINITY,
+                    max: f32::NEG_INFINITY,
+                    rms: 0.0,
+                };
+                channels
+            ];
+            squares.fill(0.0);
+            for frame in
+                self.samples[range.start * channels..range.end * channels].chunks_exact(channels)
+            {
+                for (c, &s) in frame.iter().enumerate() {
+                    let s = normalize(s);
+                    bucket[c].min = bucket[c].min.min(s);
+                    bucket[c].max = bucket[c].max.max(s);
+                    squares[c] += s as f64 * s as f64;
+                }
+            }
+
+            for (c, mut peak) in bucket.into_iter().enumerate() {
+                if len > 0 {
+                    peak.rms = (squares[c] / len as f64).sqrt() as f32;
+                } else {
+                    peak = Peak::default();
+                }
+                peaks[c].push(peak);
+            }
+        }
+        peaks
+    }
+
+    /// Amplitude of the track around the given time, in logarithmically spaced bands
+    /// from 50 Hz up to 16 kHz or the Nyquist frequency, for rendering spectrums
+    ///
+    /// Channels are mixed together and amplitudes are relative to full scale.
+    pub fn spectrum(&self, at: Duration, bands: usize) -> Vec<f32> {
+        const WINDOW: usize = 2048;
+
+        let channels = self.channels as usize;
+        let normalize = self.normalizer();
+        let start = self.frame_at(at);
+        let end = (start + WINDOW).min(self.frame_count());
+        let len = end - start;
+        // Hann window, to avoid leakage from the cut at both ends
+        let frames: Vec<f32> = self.samples[start * channels..end * channels]
+            .chunks_exact(channels)
+            .enumerate()
+            .map(|(i, frame)| {
+                let mix = frame.iter().map(|&s| normalize(s)).sum::<f32>() / channels as f32;
+                let w = (std::f32::consts::PI * i as f32 / len as f32).sin().powi(2);
+                mix * w
+            })
+            .collect();
+
+        let low = 50f32;
+        let high = (self.sample_rate as f32 / 2.0).min(16_000.0);
+        (0..bands)
+            .map(|b| {
+                if len == 0 {
+                    return 0.0;
+                }
+                // Goertzel filter at the band's center frequency
+                let frequency = low * (high / low).powf((b as f32 + 0.5) / bands as f32);
+                let coeff =
+                    2.0 * (std::f32::consts::TAU * frequency / self.sample_rate as f32).cos();
+                let (mut s1, mut s2) = (0f32, 0f32);
+                for &x in &frames {
+                    (s1, s2) = (x + coeff * s1 - s2, s1);
+                }
+                let power = s1 * s1 + s2 * s2 - coeff * s1 * s2;
+                // The Hann window halves the amplitude on average
+                power.max(0.0).sqrt() * 4.0 / len as f32
+            })
+            .collect()
+    }
+
+    /// Maps samples to the `[-1.0, 1.0]` range
+    pub(crate) fn normalizer(&self) -> impl Fn(i32) -> f32 {
+        let format = self.sample_format;
+        let min = (2u32.pow(self.bit_depth - 1)) as f32;
+        let max = (2u32.pow(self.bit_depth - 1) - 1) as f32;
+
+        move |s| match format {
+            SampleFormat::Float => f32::from_bits(s as u32),
+            SampleFormat::Int => match s.cmp(&0) {
+                Ordering::Less => s as f32 / min,
+                Ordering::Equal => 0.0,
+                Ordering::Greater => s as f32 / max,
+            },
+        }
+    }
+
+    /// Time at which the given frame starts
+    fn time_at(&self, frame: usize) -> Duration {
+        Duration::from_nanos((frame as u128 * 1_000_000_000 / self.sample_rate as u128) as u64)
+    }
+    /// Bookmarks within a time range, relative to its start
+    fn bookmarks_within(&self, range: Range<Duration>) -> Vec<Bookmark> {
+        let start = self.frame_at(range.start);
+        let end = self.frame_at(range.end).max(start);
+        let (from, to) = (self.time_at(start), self.time_at(end));
+        self.bookmarks
+            .iter()
+            .filter(|b| (from..=to).contains(&b.position))
+            .map(|b| Bookmark {
+                name: b.name.clone(),
+                position: b.position - from,
+            })
+            .collect()
+    }
+    /// Index of the frame at the given time, clamped to the length of the track
+    fn frame_at(&self, time: Duration) -> usize {
+        (time.as_nanos() * self.sample_rate as u128 / 1_000_000_000).min(self.frame_count() as u128)
+            as usize
+    }
+
+    /// Converts a time range to a range of sample indices aligned on frame boundaries
+    /// and clamped to the length of the track
+    fn sample_range(&self, range: Range<Duration>) -> Range<usize> {
+        let channels = self.channels as usize;
+        let start = self.frame_at(range.start);
+        let end = self.frame_at(range.end).max(start);
+        start * channels..end * channels
+    }
+
+    /// Smallest and largest representable sample values at the track's bit depth
+    fn sample_bounds(&self) -> (f64, f64) {
+        let max = 2f64.powi(self.bit_depth as i32 - 1);
+        (-max, max - 1.0)
+    }
+    /// Multiplies every frame in the range by a gain computed from its index
+    /// relative to the start of the range, clipping integer samples that overflow
+    fn apply_gain(&mut self, frames: Range<usize>, gain: impl Fn(usize) -> f64) {
+        self.source_checksum = None;
+        let (min, max) = self.sample_bounds();
+        let format = self.sample_format;
+        let channels = self.channels as usize;
+
+        let samples = &mut self.samples[frames.start * channels..frames.end * channels];
+        for (i, frame) in samples.chunks_exact_mut(channels).enumerate() {
+            let gain = gain(i);
+            for s in frame {
+                *s = match format {
+                    SampleFormat::Int => (*s as f64 * gain).round().clamp(min, max) as i32,
+                    SampleFormat::Float => {
+                        ((f32::from_bits(*s as u32) as f64 * gain) as f32).to_bits() as i32
+                    }
+                };
+            }
+        }
+    }
+
+    /// Creates a track with the same metadata and format but different samples
+    pub(crate) fn with_samples(&self, samples: Vec<i32>) -> Self {
+        Self {
+            title: self.title.clone(),
+            artist: self.artist.clone(),
+            year: self.year,
+            album: self.album.clone(),
+            track: self.track,
+            genres: self.genres.clone(),
+            disc: self.disc,
+            album_artist: self.album_artist.clone(),
+            compilation: self.compilation,
+            composer: self.composer.clone(),
+            comment: self.comment.clone(),
+            original_year: self.original_year,
+            label: self.label.clone(),
+            catalog_number: self.catalog_number.clone(),
+            rating: self.rating,
+            favorite: self.favorite,
+            bookmarks: self.bookmarks.clone(),
+            tags: self.tags.clone(),
+            // The samples are different, so they no longer match the source
+            source_checksum: None,
+
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bit_depth: self.bit_depth,
+            sample_format: self.sample_format,
+
+            samples: samples.into(),
+            stored_checksum: None,
+        }
+    }
+}
+
+/// Summary of the normalized samples of a channel over part of a track
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Peak {
+    pub min: f32,
+    pub max: f32,
+    /// Root mean square, a measure of loudness
+    pub rms: f32,
+}
+
+/// Bauer-style crossfeed, with the default cutoff and level of bs2b
+pub(crate) struct Crossfeed {
+    /// Smoothing factor of the low-pass filters
+    alpha: f64,
+    /// Low-passed left and right channels
+    low: (f64, f64),
+}
+
+impl Crossfeed {
+    const CUTOFF: f64 = 700.0;
+    const LEVEL: f64 = -4.5;
+
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        Self {
+            alpha: 1.0 - (-TAU * Self::CUTOFF / sample_rate as f64).exp(),
+            low: (0.0, 0.0),
+        }
+    }
+
+    /// Filters a frame, which never gets louder than its loudest channel
+    pub(crate) fn process(&mut self, left: f64, right: f64) -> (f64, f64) {
+        // The low-pass filters also delay the fed channel by about a quarter millisecond,
+        // close to the time sound takes to reach the far ear
+        self.low.0 += self.alpha * (left - self.low.0);
+        self.low.1 += self.alpha * (right - self.low.1);
+
+        let gain = db_to_gain(Self::LEVEL);
+        (
+            (left + gain * self.low.1) / (1.0 + gain),
+            (right + gain * self.low.0) / (1.0 + gain),
+        )
+    }
+
+    /// Forgets past samples, after seeking
+    #[cfg(feature = "playback")]
+    pub(crate) fn reset(&mut self) {
+        self.low = (0.0, 0.0);
+    }
+}
+
+/// Xorshift generator for dither, with a fixed seed so that outputs are reproducible
+pub(crate) struct Noise(u64);
+
+impl Noise {
+    pub(crate) fn new(seed: u64) -> Self {
+        // Xorshift would only ever produce zeros from zero
+        Self((seed ^ 0x2545_f491_4f6c_dd1d).max(1))
+    }
+
+    /// From 0 inclusive to 1 exclusive
+    pub(crate) fn uniform(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+    /// From -1 to 1 exclusive, most likely around 0
+    fn triangular(&mut self) -> f64 {
+        self.uniform() - self.uniform()
+    }
+}
+
+fn db_to_gain(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peaks_of_one_sided_buckets() {
+        let lilac = Lilac {
+            channels: 1,
+            sample_rate: 4,
+            bit_depth: 16,
+            samples: vec![100, 200, -300, -100].into(),
+            ..Default::default()
+        };
+        let peaks = &lilac.peaks(2)[0];
+
+        let normalize = lilac.normalizer();
+        assert_eq!(
+            (peaks[0].min, peaks[0].max),
+            (normalize(100), normalize(200))
+        );
+        assert_eq!(
+            (peaks[1].min, peaks[1].max),
+            (normalize(-300), normalize(-100))
+        );
+    }
+}
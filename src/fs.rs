@@ -0,0 +1,48 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+use crate::{Cue, Error, Lilac};
+
+impl Lilac {
+    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::read(BufReader::new(File::open(path)?))
+    }
+    pub fn read_header_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::read_header(BufReader::new(File::open(path)?))
+    }
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
+
+    #[cfg(feature = "mp3")]
+    pub fn from_mp3_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_mp3(BufReader::new(File::open(path)?))
+    }
+
+    #[cfg(feature = "flac")]
+    pub fn from_flac_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_flac(BufReader::new(File::open(path)?))
+    }
+
+    #[cfg(feature = "ogg")]
+    pub fn from_ogg_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_ogg(BufReader::new(File::open(path)?))
+    }
+
+    #[cfg(feature = "wav")]
+    pub fn from_wav_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::from_wav(BufReader::new(File::open(path)?))
+    }
+    #[cfg(feature = "wav")]
+    pub fn to_wav_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.to_wav(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl Cue {
+    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        // CUE sheets are often written in legacy encodings, don't fail on those
+        Self::parse(&String::from_utf8_lossy(&fs::read(path)?))
+    }
+}
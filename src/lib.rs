@@ -1,19 +1,65 @@
-use std::cmp::Ordering;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
 use std::time::Duration;
 
 use miette::Diagnostic;
-use rodio::Source;
+use serde::de::IgnoredAny;
 use serde::{Deserialize, Serialize};
 
+mod analysis;
+#[cfg(feature = "tokio")]
+mod async_io;
+mod capabilities;
+mod cue;
+mod dsp;
+#[cfg(feature = "fs")]
+mod fs;
+#[cfg(feature = "playback")]
+mod playback;
+mod samples;
+mod schema;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use analysis::{Authenticity, Verdict};
+pub use capabilities::{capabilities, Capabilities};
+pub use cue::{Cue, CueTrack};
+pub use dsp::Peak;
+#[cfg(feature = "playback")]
+pub use playback::{CrossfeedSource, Interpolation, ResampledSource};
+#[cfg(feature = "spill")]
+pub use samples::set_spill_threshold;
+pub use schema::{json_schema, layout, ChecksumLayout, Layout, SampleLayout, SCHEMA_VERSION};
+
+use crate::samples::Samples;
+
+/// Placeholder used in place of missing metadata
+pub const UNKNOWN: &str = "Unknown";
+/// Album artist of compilations without one
+pub const VARIOUS_ARTISTS: &str = "Various Artists";
+
 #[derive(Debug, thiserror::Error, Diagnostic)]
 pub enum Error {
     #[error("io error: {0}")]
     IO(#[from] std::io::Error),
     #[error("json error: {0}")]
     Json(#[from] serde_json::Error),
+    #[error("checksum mismatch: expected {expected:08x}, found {actual:08x}")]
+    Checksum { expected: u32, actual: u32 },
+    #[error("invalid file: {0}")]
+    Invalid(&'static str),
+
+    #[error("cue sheet error on line {0}: {1}")]
+    Cue(usize, String),
+
+    #[error("nothing to concatenate")]
+    Empty,
+    #[error("mismatched channel count: {0} and {1}")]
+    ChannelMismatch(u16, u16),
+    #[error("mismatched sample rate: {0} Hz and {1} Hz")]
+    SampleRateMismatch(u32, u32),
+    #[error("mismatched sample format: {0:?} and {1:?}")]
+    SampleFormatMismatch(SampleFormat, SampleFormat),
 
     #[cfg(feature = "mp3")]
     #[error("mp3 error: {0}")]
@@ -35,7 +81,7 @@ pub enum Error {
     Wav(#[from] hound::Error),
 }
 
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Lilac {
     pub title: Option<String>,
@@ -43,136 +89,355 @@ pub struct Lilac {
     pub year: Option<i32>,
     pub album: Option<String>,
     pub track: Option<u32>,
+    /// Stored as a single string when there is only one, for compatibility
+    #[serde(
+        default,
+        rename = "genre",
+        with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub genres: Vec<String>,
+    pub disc: Option<u32>,
+    pub album_artist: Option<String>,
+    /// Whether the album gathers tracks of various artists
+    #[serde(default)]
+    pub compilation: bool,
+    pub composer: Option<String>,
+    pub comment: Option<String>,
+    /// Year of the first release, when this is a reissue
+    pub original_year: Option<i32>,
+    /// Record label that published the release
+    pub label: Option<String>,
+    /// Catalog number of the release, as printed by the label
+    pub catalog_number: Option<String>,
+    /// From 1 to 5
+    pub rating: Option<u8>,
+    #[serde(default)]
+    pub favorite: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bookmarks: Vec<Bookmark>,
+    /// Tags from the source file that don't map to any of the fields above
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// Checksum of the samples of the file this one was losslessly transcoded from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_checksum: Option<u32>,
 
     pub channels: u16,
     pub sample_rate: u32,
     pub bit_depth: u32,
+    #[serde(default)]
+    pub sample_format: SampleFormat,
+
+    samples: Samples,
+
+    /// Checksum read from a file, only set while it is being verified
+    #[serde(default, rename = "checksum", skip_serializing)]
+    stored_checksum: Option<u32>,
+}
 
-    samples: Vec<i32>,
+/// Named position within a track, e.g. a chapter of a lecture
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Deserialize, Serialize)]
+pub struct Bookmark {
+    pub name: String,
+    pub position: Duration,
+}
+
+/// Representation of the samples of a track
+#[derive(
+    Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Default, Deserialize, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleFormat {
+    /// Signed integers of the track's bit depth
+    #[default]
+    Int,
+    /// 32-bit floats in the `[-1.0, 1.0]` range, stored as their bit pattern
+    Float,
 }
+
 impl Lilac {
     pub fn read<R: Read>(reader: R) -> Result<Self, Error> {
-        serde_json::from_reader(reader).map_err(Into::into)
+        let mut lilac: Self = serde_json::from_reader(reader)?;
+
+        // Files written before checksums were introduced don't have one
+        if let Some(expected) = lilac.stored_checksum.take() {
+            let actual = lilac.checksum();
+            if expected != actual {
+                return Err(Error::Checksum { expected, actual });
+            }
+        }
+        lilac.validate()?;
+
+        Ok(lilac)
     }
-    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        Self::read(BufReader::new(File::open(path)?))
+
+    /// Reads the metadata of a track without its samples
+    ///
+    /// The samples are skipped over rather than stored or verified,
+    /// so the returned track is empty, which makes scanning large collections cheap.
+    pub fn read_header<R: Read>(reader: R) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Header {
+            title: Option<String>,
+            artist: Option<String>,
+            year: Option<i32>,
+            album: Option<String>,
+            track: Option<u32>,
+            #[serde(default, rename = "genre", with = "one_or_many")]
+            genres: Vec<String>,
+            disc: Option<u32>,
+            album_artist: Option<String>,
+            #[serde(default)]
+            compilation: bool,
+            composer: Option<String>,
+            comment: Option<String>,
+            original_year: Option<i32>,
+            label: Option<String>,
+            catalog_number: Option<String>,
+            rating: Option<u8>,
+            #[serde(default)]
+            favorite: bool,
+            #[serde(default)]
+            bookmarks: Vec<Bookmark>,
+            #[serde(default)]
+            tags: BTreeMap<String, String>,
+            source_checksum: Option<u32>,
+
+            channels: u16,
+            sample_rate: u32,
+            bit_depth: u32,
+            #[serde(default)]
+            sample_format: SampleFormat,
+
+            #[allow(dead_code)]
+            samples: IgnoredAny,
+        }
+
+        let h: Header = serde_json::from_reader(reader)?;
+        // The rest of the checks of `validate` need the samples
+        if h.channels == 0 {
+            return Err(Error::Invalid("zero channels"));
+        }
+        if h.sample_rate == 0 {
+            return Err(Error::Invalid("zero sample rate"));
+        }
+        Ok(Self {
+            title: h.title,
+            artist: h.artist,
+            year: h.year,
+            album: h.album,
+            track: h.track,
+            genres: h.genres,
+            disc: h.disc,
+            album_artist: h.album_artist,
+            compilation: h.compilation,
+            composer: h.composer,
+            comment: h.comment,
+            original_year: h.original_year,
+            label: h.label,
+            catalog_number: h.catalog_number,
+            rating: h.rating,
+            favorite: h.favorite,
+            bookmarks: h.bookmarks,
+            tags: h.tags,
+            source_checksum: h.source_checksum,
+
+            channels: h.channels,
+            sample_rate: h.sample_rate,
+            bit_depth: h.bit_depth,
+            sample_format: h.sample_format,
+
+            ..Default::default()
+        })
     }
 
     pub fn write<W: Write>(&self, writer: W) -> Result<(), Error> {
-        serde_json::to_writer_pretty(writer, self).map_err(Into::into)
-    }
-    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
-        self.write(BufWriter::new(File::create(path)?))
-    }
+        #[derive(Serialize)]
+        struct Checked<'a> {
+            #[serde(flatten)]
+            lilac: &'a Lilac,
+            checksum: u32,
+        }
 
-    pub fn title(&self) -> &str {
-        self.title.as_ref().map(AsRef::as_ref).unwrap_or("Unknown")
-    }
-    pub fn artist(&self) -> &str {
-        self.artist.as_ref().map(AsRef::as_ref).unwrap_or("Unknown")
-    }
-    pub fn album(&self) -> &str {
-        self.album.as_ref().map(AsRef::as_ref).unwrap_or("Unknown")
+        let checked = Checked {
+            lilac: self,
+            checksum: self.checksum(),
+        };
+        serde_json::to_writer_pretty(writer, &checked).map_err(Into::into)
     }
 
-    pub fn source(self) -> impl Source<Item = f32> {
-        let min = (2u32.pow(self.bit_depth - 1)) as f32;
-        let max = (2u32.pow(self.bit_depth - 1) - 1) as f32;
+    /// CRC-32 of the samples, as stored in written files
+    pub fn checksum(&self) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buf = Vec::with_capacity(4096 * 4);
+        for chunk in self.samples.chunks(4096) {
+            buf.clear();
+            buf.extend(chunk.iter().flat_map(|s| s.to_le_bytes()));
+            hasher.update(&buf);
+        }
+        hasher.finalize()
+    }
 
-        let samples_len = self.samples.len();
+    /// Checks that the format fields are consistent with each other and with the samples
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.channels == 0 {
+            return Err(Error::Invalid("zero channels"));
+        }
+        if self.sample_rate == 0 {
+            return Err(Error::Invalid("zero sample rate"));
+        }
+        if !(1..=32).contains(&self.bit_depth) {
+            return Err(Error::Invalid("bit depth out of range"));
+        }
+        if self.rating.is_some_and(|r| !(1..=5).contains(&r)) {
+            return Err(Error::Invalid("rating out of range"));
+        }
+        if self.samples.len() % self.channels as usize != 0 {
+            return Err(Error::Invalid(
+                "sample count is not a multiple of the channel count",
+            ));
+        }
+        if self.sample_format == SampleFormat::Float {
+            if self.bit_depth != 32 {
+                return Err(Error::Invalid("float samples must be 32 bits"));
+            }
+            return Ok(());
+        }
 
-        LilacSource {
-            channels: self.channels,
-            sample_rate: self.sample_rate,
+        let max = (1i64 << (self.bit_depth - 1)) - 1;
+        if self
+            .samples
+            .iter()
+            .any(|&s| s as i64 > max || (s as i64) < -max - 1)
+        {
+            return Err(Error::Invalid("sample out of range for the bit depth"));
+        }
 
-            samples: self.samples.into_iter().map(move |s| match s.cmp(&0) {
-                Ordering::Less => s as f32 / min,
-                Ordering::Equal => 0.0,
-                Ordering::Greater => s as f32 / max,
-            }),
+        Ok(())
+    }
 
-            duration: Duration::from_millis(
-                samples_len as u64 / self.channels as u64 / (self.sample_rate / 1000) as u64,
-            ),
+    /// Total number of samples across all channels
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+    /// Number of samples per channel, zero for tracks without channels
+    pub fn frame_count(&self) -> usize {
+        self.samples
+            .len()
+            .checked_div(self.channels as usize)
+            .unwrap_or(0)
+    }
+    /// Zero for tracks without a sample rate, such as default ones
+    pub fn duration(&self) -> Duration {
+        let frames = self.frame_count() as u64;
+        let rate = self.sample_rate as u64;
+        if rate == 0 {
+            return Duration::ZERO;
         }
+        Duration::new(
+            frames / rate,
+            ((frames % rate) * 1_000_000_000 / rate) as u32,
+        )
     }
-}
-
-struct LilacSource<T: Iterator<Item = f32>> {
-    channels: u16,
-    sample_rate: u32,
-
-    samples: T,
 
-    duration: Duration,
-}
-impl<T: Iterator<Item = f32>> Iterator for LilacSource<T> {
-    type Item = f32;
+    /// Title of the track, or [`UNKNOWN`] if it is missing
+    pub fn title(&self) -> &str {
+        self.title_or(UNKNOWN)
+    }
+    /// Artist of the track, or [`UNKNOWN`] if it is missing
+    pub fn artist(&self) -> &str {
+        self.artist_or(UNKNOWN)
+    }
+    /// Artist the album is filed under
+    ///
+    /// Falls back to [`VARIOUS_ARTISTS`] for compilations, then to the artist of the track.
+    pub fn album_artist(&self) -> &str {
+        match &self.album_artist {
+            Some(artist) => artist,
+            None if self.compilation => VARIOUS_ARTISTS,
+            None => self.artist(),
+        }
+    }
+    /// Every genre of the track separated by `; `, if it has any
+    pub fn genre(&self) -> Option<String> {
+        (!self.genres.is_empty()).then(|| self.genres.join("; "))
+    }
+    /// Album of the track, or [`UNKNOWN`] if it is missing
+    pub fn album(&self) -> &str {
+        self.album_or(UNKNOWN)
+    }
 
-    #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        self.samples.next()
+    pub fn title_or<'a>(&'a self, fallback: &'a str) -> &'a str {
+        self.title.as_deref().unwrap_or(fallback)
     }
-}
-impl<T: Iterator<Item = f32>> Source for LilacSource<T> {
-    #[inline]
-    fn current_frame_len(&self) -> Option<usize> {
-        None
+    pub fn artist_or<'a>(&'a self, fallback: &'a str) -> &'a str {
+        self.artist.as_deref().unwrap_or(fallback)
     }
-    #[inline]
-    fn channels(&self) -> u16 {
-        self.channels
+    pub fn album_or<'a>(&'a self, fallback: &'a str) -> &'a str {
+        self.album.as_deref().unwrap_or(fallback)
     }
-    #[inline]
-    fn sample_rate(&self) -> u32 {
-        self.sample_rate
+}
+
+/// (De)serializes a list as a single value when it has one element
+///
+/// `null`, which files written before lists were supported can hold, is an empty list.
+mod one_or_many {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
     }
-    #[inline]
-    fn total_duration(&self) -> Option<Duration> {
-        Some(self.duration)
+
+    pub fn serialize<S: Serializer>(values: &[String], serializer: S) -> Result<S::Ok, S::Error> {
+        match values {
+            [value] => value.serialize(serializer),
+            values => values.serialize(serializer),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        Ok(match Option::<OneOrMany>::deserialize(deserializer)? {
+            Some(OneOrMany::One(value)) => vec![value],
+            Some(OneOrMany::Many(values)) => values,
+            None => Vec::new(),
+        })
     }
 }
 
 #[cfg(feature = "mp3")]
 mod mp3 {
-    use std::fs::File;
-    use std::io::{BufReader, Read, Seek, SeekFrom};
-    use std::path::Path;
+    use std::collections::BTreeMap;
+    use std::io::{Read, Seek, SeekFrom};
 
     use id3::{ErrorKind, Tag, TagLike};
     use minimp3::Decoder;
 
+    use crate::samples::SamplesBuilder;
     use crate::{Error, Lilac};
 
     impl Lilac {
         pub fn from_mp3<R: Read + Seek>(mut reader: R) -> Result<Self, Error> {
-            let (title, artist, year, album, track) = match Tag::read_from2(&mut reader) {
-                Ok(tag) => {
-                    let title = tag.title().map(ToOwned::to_owned);
-                    let artist = tag.artist().map(ToOwned::to_owned);
-                    let year = tag.year();
-                    let album = tag.album().map(ToOwned::to_owned);
-                    let track = tag.track();
-                    (title, artist, year, album, track)
-                }
-                Err(e) => match e.kind {
-                    ErrorKind::NoTag => (None, None, None, None, None),
-                    _ => return Err(e.into()),
-                },
-            };
+            let metadata = Lilac::read_id3(&mut reader)?;
 
             reader.seek(SeekFrom::Start(0))?;
             let mut reader = Decoder::new(reader);
-            let mut samples = Vec::new();
+            let mut samples = SamplesBuilder::new();
 
             let first_frame = reader.next_frame()?;
             let channels = first_frame.channels as u16;
             let sample_rate = first_frame.sample_rate as u32;
-            samples.extend(first_frame.data.into_iter().map(|s| s as i32));
+            samples.extend(first_frame.data.into_iter().map(|s| s as i32))?;
 
             loop {
                 match reader.next_frame() {
-                    Ok(f) => samples.extend(f.data.into_iter().map(|s| s as i32)),
+                    Ok(f) => samples.extend(f.data.into_iter().map(|s| s as i32))?,
                     Err(e) => match e {
                         minimp3::Error::Eof => break,
                         _ => return Err(e.into()),
@@ -181,175 +446,311 @@ mod mp3 {
             }
 
             Ok(Lilac {
-                title,
-                artist,
-                year,
-                album,
-                track,
                 channels,
                 sample_rate,
                 bit_depth: 16,
-                samples,
+                samples: samples.finish()?,
+                ..metadata
+            })
+        }
+
+        /// Reads the metadata of a track and decodes its first frame only, for its format
+        ///
+        /// The returned track is empty, as with [`Lilac::read_header`].
+        pub fn from_mp3_header<R: Read + Seek>(mut reader: R) -> Result<Self, Error> {
+            let metadata = Lilac::read_id3(&mut reader)?;
+
+            reader.seek(SeekFrom::Start(0))?;
+            let frame = Decoder::new(reader).next_frame()?;
+            Ok(Lilac {
+                channels: frame.channels as u16,
+                sample_rate: frame.sample_rate as u32,
+                bit_depth: 16,
+                ..metadata
             })
         }
 
-        pub fn from_mp3_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-            Self::from_mp3(BufReader::new(File::open(path)?))
+        fn read_id3<R: Read + Seek>(reader: &mut R) -> Result<Self, Error> {
+            match Tag::read_from2(reader) {
+                Ok(tag) => Ok(Lilac::from_id3(&tag)),
+                Err(e) => match e.kind {
+                    ErrorKind::NoTag => Ok(Lilac::default()),
+                    _ => Err(e.into()),
+                },
+            }
+        }
+
+        fn from_id3(tag: &Tag) -> Self {
+            let text = |id: &str| {
+                tag.get(id)
+                    .and_then(|f| f.content().text())
+                    .map(ToOwned::to_owned)
+            };
+
+            let mut tags = BTreeMap::new();
+            for frame in tag.frames() {
+                if KNOWN_FRAMES.contains(&frame.id()) {
+                    continue;
+                }
+                if let Some(text) = frame.content().text() {
+                    tags.insert(frame.id().to_owned(), text.to_owned());
+                }
+            }
+            let mut catalog_number = None;
+            for text in tag.extended_texts() {
+                // Written as a user-defined frame by Picard and foobar2000
+                if text.description.eq_ignore_ascii_case("CATALOGNUMBER") {
+                    catalog_number = Some(text.value.clone());
+                } else {
+                    tags.insert(text.description.clone(), text.value.clone());
+                }
+            }
+
+            Lilac {
+                title: tag.title().map(ToOwned::to_owned),
+                artist: tag.artist().map(ToOwned::to_owned),
+                year: tag.year().or_else(|| tag.date_recorded().map(|d| d.year)),
+                album: tag.album().map(ToOwned::to_owned),
+                track: tag.track(),
+                // TCON frames can hold a list of genres
+                genres: tag
+                    .genres()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(ToOwned::to_owned)
+                    .collect(),
+                disc: tag.disc(),
+                album_artist: tag.album_artist().map(ToOwned::to_owned),
+                // iTunes extension
+                compilation: text("TCMP").is_some_and(|c| c == "1"),
+                composer: text("TCOM"),
+                comment: tag.comments().next().map(|c| c.text.clone()),
+                original_year: tag.original_date_released().map(|d| d.year),
+                label: text("TPUB"),
+                catalog_number,
+                tags,
+                ..Default::default()
+            }
         }
     }
+
+    /// Frames that are either mapped to a field or not textual
+    static KNOWN_FRAMES: &[&str] = &[
+        "TIT2", "TPE1", "TYER", "TDRC", "TALB", "TRCK", "TCON", "TPOS", "TPE2", "TCMP", "TCOM",
+        "COMM", "TDOR", "TPUB", "TXXX",
+    ];
 }
 
 #[cfg(feature = "flac")]
 mod flac {
-    use std::fs::File;
-    use std::io::{BufReader, Read};
-    use std::path::Path;
+    use std::io::Read;
 
     use claxon::FlacReader;
 
+    use crate::samples::SamplesBuilder;
     use crate::{Error, Lilac};
 
     impl Lilac {
         pub fn from_flac<R: Read>(reader: R) -> Result<Self, Error> {
             let mut reader = FlacReader::new(reader)?;
+            let mut lilac = Lilac::flac_header(&reader);
 
-            let info = reader.streaminfo();
-
-            let title = reader.get_tag("TITLE").next().map(ToOwned::to_owned);
-            let artist = {
-                let artists: Vec<&str> = reader.get_tag("ARTIST").collect();
-                if !artists.is_empty() {
-                    Some(artists.join(", "))
-                } else {
-                    None
-                }
-            };
-            let album = reader.get_tag("ALBUM").next().map(ToOwned::to_owned);
-            let track = match reader.get_tag("TRACKNUMBER").next() {
-                Some(tn) => match tn.parse() {
-                    Ok(tn) => Some(tn),
-                    Err(_) => None,
-                },
-                None => None,
-            };
+            let mut samples = SamplesBuilder::new();
+            for s in reader.samples() {
+                samples.push(s?)?;
+            }
+            lilac.samples = samples.finish()?;
+            Ok(lilac)
+        }
 
-            Ok(Lilac {
-                title,
-                artist,
-                year: None,
-                album,
-                track,
+        /// Reads the metadata of a track without decoding its samples
+        ///
+        /// The returned track is empty, as with [`Lilac::read_header`].
+        pub fn from_flac_header<R: Read>(reader: R) -> Result<Self, Error> {
+            Ok(Lilac::flac_header(&FlacReader::new(reader)?))
+        }
 
+        fn flac_header<R: Read>(reader: &FlacReader<R>) -> Self {
+            let info = reader.streaminfo();
+            let mut lilac = Lilac {
                 channels: info.channels as u16,
                 sample_rate: info.sample_rate,
                 bit_depth: info.bits_per_sample,
-
-                samples: reader.samples().collect::<Result<_, _>>()?,
-            })
-        }
-
-        pub fn from_flac_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-            Self::from_flac(BufReader::new(File::open(path)?))
+                ..Default::default()
+            };
+            lilac.read_vorbis_comments(reader.tags());
+            lilac
         }
     }
 }
 
 #[cfg(feature = "ogg")]
 mod ogg {
-    use std::fs::File;
-    use std::io::{BufReader, Read, Seek};
-    use std::path::Path;
+    use std::io::{Read, Seek};
 
     use lewton::inside_ogg::OggStreamReader;
 
+    use crate::samples::SamplesBuilder;
     use crate::{Error, Lilac};
 
     impl Lilac {
         pub fn from_ogg<R: Read + Seek>(reader: R) -> Result<Self, Error> {
             let mut reader = OggStreamReader::new(reader)?;
+            let mut lilac = Lilac::ogg_header(&reader);
 
-            let mut title = None;
-            let mut artists = Vec::new();
-            let mut album = None;
-            let mut track = None;
-            for (k, v) in &reader.comment_hdr.comment_list {
-                let uk = k.to_ascii_uppercase();
-                if uk == "TITLE" && title.is_none() {
-                    title = Some(v.clone());
-                } else if uk == "ARTIST" {
-                    artists.push(v.as_ref());
-                } else if uk == "ALBUM" && album.is_none() {
-                    album = Some(v.clone());
-                } else if uk == "TRACKNUMBER" && track.is_none() {
-                    if let Ok(tn) = v.parse() {
-                        track = Some(tn);
-                    }
-                }
-            }
-            let artist = if !artists.is_empty() {
-                Some(artists.join(", "))
-            } else {
-                None
-            };
-
-            let mut samples = Vec::new();
+            let mut samples = SamplesBuilder::new();
             while let Some(packet) = reader.read_dec_packet_itl()? {
-                samples.extend(packet.into_iter().map(|s| s as i32));
+                samples.extend(packet.into_iter().map(|s| s as i32))?;
             }
+            lilac.samples = samples.finish()?;
+            Ok(lilac)
+        }
 
-            Ok(Lilac {
-                title,
-                artist,
-                year: None,
-                album,
-                track,
+        /// Reads the metadata of a track without decoding its samples
+        ///
+        /// The returned track is empty, as with [`Lilac::read_header`].
+        pub fn from_ogg_header<R: Read + Seek>(reader: R) -> Result<Self, Error> {
+            Ok(Lilac::ogg_header(&OggStreamReader::new(reader)?))
+        }
 
+        fn ogg_header<R: Read + Seek>(reader: &OggStreamReader<R>) -> Self {
+            let mut lilac = Lilac {
                 channels: reader.ident_hdr.audio_channels as u16,
                 sample_rate: reader.ident_hdr.audio_sample_rate,
                 bit_depth: 16,
+                ..Default::default()
+            };
+            lilac.read_vorbis_comments(
+                reader
+                    .comment_hdr
+                    .comment_list
+                    .iter()
+                    .map(|(k, v)| (k.as_str(), v.as_str())),
+            );
+            lilac
+        }
+    }
+}
 
-                samples,
-            })
+#[cfg(any(feature = "flac", feature = "ogg"))]
+mod vorbis {
+    use crate::Lilac;
+
+    impl Lilac {
+        /// Fills the metadata from Vorbis comments, as found in FLAC and OGG files
+        pub(crate) fn read_vorbis_comments<'a, I>(&mut self, comments: I)
+        where
+            I: IntoIterator<Item = (&'a str, &'a str)>,
+        {
+            let mut artists = Vec::new();
+            for (k, v) in comments {
+                let k = k.to_ascii_uppercase();
+                match k.as_str() {
+                    "TITLE" => set(&mut self.title, v),
+                    "ARTIST" => artists.push(v),
+                    "ALBUM" => set(&mut self.album, v),
+                    "TRACKNUMBER" => self.track = self.track.or_else(|| number(v)),
+                    "DATE" => self.year = self.year.or_else(|| v.get(..4)?.parse().ok()),
+                    "GENRE" => {
+                        if !self.genres.iter().any(|g| g == v) {
+                            self.genres.push(v.to_owned());
+                        }
+                    }
+                    "DISCNUMBER" => self.disc = self.disc.or_else(|| number(v)),
+                    "ALBUMARTIST" | "ALBUM ARTIST" => set(&mut self.album_artist, v),
+                    "COMPILATION" => self.compilation = v.trim() == "1",
+                    "COMPOSER" => set(&mut self.composer, v),
+                    "COMMENT" | "DESCRIPTION" => set(&mut self.comment, v),
+                    "ORIGINALDATE" | "ORIGINALYEAR" => {
+                        self.original_year = self.original_year.or_else(|| v.get(..4)?.parse().ok())
+                    }
+                    "LABEL" => set(&mut self.label, v),
+                    "CATALOGNUMBER" => set(&mut self.catalog_number, v),
+                    _ => {
+                        self.tags
+                            .entry(k)
+                            .and_modify(|e| {
+                                e.push_str("; ");
+                                e.push_str(v);
+                            })
+                            .or_insert_with(|| v.to_owned());
+                    }
+                }
+            }
+
+            if !artists.is_empty() {
+                self.artist = Some(artists.join(", "));
+            }
         }
+    }
 
-        pub fn from_ogg_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-            Self::from_ogg(BufReader::new(File::open(path)?))
+    fn set(field: &mut Option<String>, value: &str) {
+        if field.is_none() {
+            *field = Some(value.to_owned());
         }
     }
+
+    /// Parses numbers like track numbers which may be followed by a total, as in `3/12`
+    fn number(value: &str) -> Option<u32> {
+        value.split('/').next()?.trim().parse().ok()
+    }
 }
 
 #[cfg(feature = "wav")]
 mod wav {
-    use std::fs::File;
-    use std::io::{BufReader, BufWriter, Read, Seek, Write};
-    use std::path::Path;
+    use std::io::{Read, Seek, Write};
 
-    use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+    use hound::{WavReader, WavSpec, WavWriter};
 
-    use crate::{Error, Lilac};
+    use crate::samples::SamplesBuilder;
+    use crate::{Error, Lilac, SampleFormat};
 
     impl Lilac {
         pub fn from_wav<R: Read>(reader: R) -> Result<Self, Error> {
             let mut reader = WavReader::new(reader)?;
 
             let spec = reader.spec();
-            let samples = reader.samples().collect::<Result<_, _>>()?;
+            let mut samples = SamplesBuilder::new();
+            let sample_format = match spec.sample_format {
+                hound::SampleFormat::Int => {
+                    for s in reader.samples() {
+                        samples.push(s?)?;
+                    }
+                    SampleFormat::Int
+                }
+                hound::SampleFormat::Float => {
+                    for s in reader.samples::<f32>() {
+                        samples.push(s?.to_bits() as i32)?;
+                    }
+                    SampleFormat::Float
+                }
+            };
 
             Ok(Lilac {
-                title: None,
-                artist: None,
-                year: None,
-                album: None,
-                track: None,
                 channels: spec.channels,
                 sample_rate: spec.sample_rate,
                 bit_depth: spec.bits_per_sample as u32,
-                samples,
+                sample_format,
+                samples: samples.finish()?,
+                ..Default::default()
             })
         }
 
-        pub fn from_wav_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-            Self::from_wav(BufReader::new(File::open(path)?))
+        /// Reads the format of a track without decoding its samples
+        ///
+        /// The returned track is empty, as with [`Lilac::read_header`].
+        pub fn from_wav_header<R: Read>(reader: R) -> Result<Self, Error> {
+            let spec = WavReader::new(reader)?.spec();
+            Ok(Lilac {
+                channels: spec.channels,
+                sample_rate: spec.sample_rate,
+                bit_depth: spec.bits_per_sample as u32,
+                sample_format: match spec.sample_format {
+                    hound::SampleFormat::Int => SampleFormat::Int,
+                    hound::SampleFormat::Float => SampleFormat::Float,
+                },
+                ..Default::default()
+            })
         }
 
         pub fn to_wav<W: Write + Seek>(&self, writer: W) -> Result<(), Error> {
@@ -357,19 +758,33 @@ mod wav {
                 channels: self.channels,
                 sample_rate: self.sample_rate,
                 bits_per_sample: self.bit_depth as u16,
-                sample_format: SampleFormat::Int,
+                sample_format: match self.sample_format {
+                    SampleFormat::Int => hound::SampleFormat::Int,
+                    SampleFormat::Float => hound::SampleFormat::Float,
+                },
             };
 
             let mut writer = WavWriter::new(writer, spec)?;
             for sample in self.samples.iter().copied() {
-                writer.write_sample(sample)?;
+                match self.sample_format {
+                    SampleFormat::Int => writer.write_sample(sample)?,
+                    SampleFormat::Float => writer.write_sample(f32::from_bits(sample as u32))?,
+                }
             }
 
             writer.finalize().map_err(Into::into)
         }
+    }
+}
 
-        pub fn to_wav_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
-            self.to_wav(BufWriter::new(File::create(path)?))
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_genre() {
+        let file = br#"{"genre":null,"channels":2,"sampleRate":44100,"bitDepth":16,"samples":[]}"#;
+        assert!(Lilac::read(&file[..]).unwrap().genres.is_empty());
+        assert!(Lilac::read_header(&file[..]).unwrap().genres.is_empty());
     }
 }
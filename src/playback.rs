@@ -0,0 +1,404 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+use std::iter::Map;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rodio::source::SeekError;
+use rodio::Source;
+
+use crate::dsp::Crossfeed;
+use crate::samples::IntoIter;
+use crate::Lilac;
+
+impl Lilac {
+    pub fn source(self) -> impl Source<Item = f32> {
+        let normalize = self.normalizer();
+        LilacSource {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            duration: self.duration(),
+
+            samples: self.samples.into_iter().map(normalize),
+        }
+    }
+    /// Plays a shared track without copying its samples,
+    /// so that it can be played again or restarted cheaply
+    ///
+    /// Unlike [`Lilac::source`], the returned source supports seeking.
+    pub fn source_shared(self: Arc<Self>) -> impl Source<Item = f32> {
+        let normalize = self.normalizer();
+        LilacSource {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            duration: self.duration(),
+
+            samples: SharedSamples {
+                lilac: self,
+                position: 0,
+                normalize,
+            },
+        }
+    }
+}
+
+/// Sample iterator backing a [`LilacSource`]
+trait Samples: Iterator<Item = f32> {
+    /// Moves to the given sample index, returning false if unsupported
+    fn seek(&mut self, position: usize) -> bool;
+}
+
+impl<F: FnMut(i32) -> f32> Samples for Map<IntoIter, F> {
+    fn seek(&mut self, _: usize) -> bool {
+        false
+    }
+}
+
+struct SharedSamples<F: Fn(i32) -> f32> {
+    lilac: Arc<Lilac>,
+    position: usize,
+    normalize: F,
+}
+impl<F: Fn(i32) -> f32> Iterator for SharedSamples<F> {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        let sample = self.lilac.samples.get(self.position).copied();
+        self.position += 1;
+        sample.map(&self.normalize)
+    }
+}
+impl<F: Fn(i32) -> f32> Samples for SharedSamples<F> {
+    fn seek(&mut self, position: usize) -> bool {
+        self.position = position.min(self.lilac.samples.len());
+        true
+    }
+}
+
+struct LilacSource<T: Samples> {
+    channels: u16,
+    sample_rate: u32,
+
+    samples: T,
+
+    duration: Duration,
+}
+impl<T: Samples> Iterator for LilacSource<T> {
+    type Item = f32;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.samples.next()
+    }
+}
+impl<T: Samples> Source for LilacSource<T> {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        Some(self.duration)
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        // Round down to a whole frame so channels stay aligned
+        let frame = (pos.as_secs_f64() * self.sample_rate as f64) as usize;
+        if self.samples.seek(frame * self.channels as usize) {
+            Ok(())
+        } else {
+            Err(SeekError::NotSupported {
+                underlying_source: std::any::type_name::<Self>(),
+            })
+        }
+    }
+}
+
+/// Source applying [`Lilac::crossfeed`] to a stereo source while the flag is set,
+/// so that it can be toggled during playback
+pub struct CrossfeedSource<S> {
+    source: S,
+    enabled: Arc<AtomicBool>,
+    filter: Crossfeed,
+    /// Right channel of the last frame, returned after its left channel
+    right: Option<f32>,
+}
+impl<S: Source<Item = f32>> CrossfeedSource<S> {
+    pub fn new(source: S, enabled: Arc<AtomicBool>) -> Self {
+        let filter = Crossfeed::new(source.sample_rate());
+        Self {
+            source,
+            enabled,
+            filter,
+            right: None,
+        }
+    }
+}
+impl<S: Source<Item = f32>> Iterator for CrossfeedSource<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(right) = self.right.take() {
+            return Some(right);
+        }
+        let left = self.source.next()?;
+        if self.source.channels() != 2 {
+            return Some(left);
+        }
+        let Some(right) = self.source.next() else {
+            return Some(left);
+        };
+
+        // The filter keeps running while disabled so that enabling it doesn't click
+        let (l, r) = self.filter.process(left as f64, right as f64);
+        if self.enabled.load(Ordering::Relaxed) {
+            self.right = Some(r as f32);
+            Some(l as f32)
+        } else {
+            self.right = Some(right);
+            Some(left)
+        }
+    }
+}
+impl<S: Source<Item = f32>> Source for CrossfeedSource<S> {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        let pending = self.right.is_some() as usize;
+        self.source.current_frame_len().map(|len| len + pending)
+    }
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.right = None;
+        self.filter.reset();
+        Ok(())
+    }
+}
+
+/// How samples between two input frames are computed by a [`ResampledSource`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Interpolation {
+    /// Straight lines between frames, cheapest but dulls the highs and lets aliasing through
+    Linear,
+    /// Catmull-Rom splines over four frames
+    #[default]
+    Cubic,
+    /// Windowed sinc over 32 frames, the most accurate
+    Sinc,
+}
+
+impl Interpolation {
+    /// Number of frames used on each side of the interpolated position
+    fn reach(self) -> usize {
+        match self {
+            Interpolation::Linear => 1,
+            Interpolation::Cubic => 2,
+            Interpolation::Sinc => 16,
+        }
+    }
+
+    /// Weight of a frame at the given distance from the interpolated position,
+    /// with frequencies above the cutoff (relative to the input Nyquist) filtered out
+    fn weight(self, x: f64, cutoff: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            Interpolation::Linear => (1.0 - x).max(0.0),
+            Interpolation::Cubic if x < 1.0 => 1.5 * x.powi(3) - 2.5 * x.powi(2) + 1.0,
+            Interpolation::Cubic if x < 2.0 => -0.5 * x.powi(3) + 2.5 * x.powi(2) - 4.0 * x + 2.0,
+            Interpolation::Cubic => 0.0,
+            Interpolation::Sinc => {
+                let reach = self.reach() as f64;
+                if x >= reach {
+                    return 0.0;
+                }
+                let t = PI * x * cutoff;
+                let sinc = if t == 0.0 { 1.0 } else { t.sin() / t };
+                // Blackman window
+                let w = PI * (x / reach + 1.0);
+                let window = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                cutoff * sinc * window
+            }
+        }
+    }
+}
+
+/// Source converting another one to the given sample rate,
+/// so that the output device doesn't have to
+///
+/// Sources already at that rate are passed through untouched.
+pub struct ResampledSource<S> {
+    source: S,
+    sample_rate: u32,
+    interpolation: Interpolation,
+
+    /// Frames around the current position, in order
+    frames: VecDeque<Vec<f32>>,
+    /// Index of the first buffered frame in the input, negative while priming
+    first: i64,
+    /// Number of frames read from the input, once it ran out
+    end: Option<i64>,
+    /// Index of the next output frame, since the start or the last seek
+    position: u64,
+    /// Output frame being returned, one channel at a time
+    output: Vec<f32>,
+    channel: usize,
+}
+impl<S: Source<Item = f32>> ResampledSource<S> {
+    pub fn new(source: S, sample_rate: u32, interpolation: Interpolation) -> Self {
+        let mut resampled = Self {
+            source,
+            sample_rate,
+            interpolation,
+            frames: VecDeque::new(),
+            first: 0,
+            end: None,
+            position: 0,
+            output: Vec::new(),
+            channel: 0,
+        };
+        resampled.reset();
+        resampled
+    }
+
+    fn passthrough(&self) -> bool {
+        self.source.sample_rate() == self.sample_rate
+    }
+
+    fn reset(&mut self) {
+        let channels = self.source.channels() as usize;
+        // Frames before the start are silent
+        let priming = self.interpolation.reach() - 1;
+        self.frames = VecDeque::from(vec![vec![0.0; channels]; priming]);
+        self.first = -(priming as i64);
+        self.end = None;
+        self.position = 0;
+        self.output.clear();
+        self.channel = 0;
+    }
+
+    /// Reads the next input frame, or silence past the end
+    fn read_frame(&mut self) {
+        let channels = self.source.channels() as usize;
+        let mut frame = Vec::with_capacity(channels);
+        if self.end.is_none() {
+            frame.extend(self.source.by_ref().take(channels));
+            if frame.len() < channels {
+                self.end = Some(self.first + self.frames.len() as i64);
+            }
+        }
+        frame.resize(channels, 0.0);
+        self.frames.push_back(frame);
+    }
+
+    /// Computes the next output frame, returning false past the end of the input
+    fn next_frame(&mut self) -> bool {
+        let ratio = self.source.sample_rate() as f64 / self.sample_rate as f64;
+        let time = self.position as f64 * ratio;
+        let index = time.floor() as i64;
+        if self.end.is_some_and(|end| index >= end) {
+            return false;
+        }
+
+        let reach = self.interpolation.reach() as i64;
+        while self.first + self.frames.len() as i64 <= index + reach {
+            self.read_frame();
+        }
+        while self.first < index - reach + 1 {
+            self.frames.pop_front();
+            self.first += 1;
+        }
+        if self.end.is_some_and(|end| index >= end) {
+            return false;
+        }
+
+        // Frequencies the output can't represent are filtered out when downsampling
+        let cutoff = (1.0 / ratio).min(1.0);
+        self.output.clear();
+        self.output.resize(self.source.channels() as usize, 0.0);
+        let mut total = 0.0;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let weight = self
+                .interpolation
+                .weight(time - (self.first + i as i64) as f64, cutoff);
+            total += weight;
+            for (out, &sample) in self.output.iter_mut().zip(frame) {
+                *out += (sample as f64 * weight) as f32;
+            }
+        }
+        // Sinc weights don't quite add up to one, which would change the level
+        if self.interpolation == Interpolation::Sinc && total > 0.0 {
+            for out in &mut self.output {
+                *out = (*out as f64 / total) as f32;
+            }
+        }
+
+        self.position += 1;
+        self.channel = 0;
+        true
+    }
+}
+impl<S: Source<Item = f32>> Iterator for ResampledSource<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.passthrough() {
+            return self.source.next();
+        }
+        if self.channel == self.output.len() && !self.next_frame() {
+            return None;
+        }
+        let sample = self.output[self.channel];
+        self.channel += 1;
+        Some(sample)
+    }
+}
+impl<S: Source<Item = f32>> Source for ResampledSource<S> {
+    #[inline]
+    fn current_frame_len(&self) -> Option<usize> {
+        if self.passthrough() {
+            self.source.current_frame_len()
+        } else {
+            None
+        }
+    }
+    #[inline]
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+    #[inline]
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    #[inline]
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, pos: Duration) -> Result<(), SeekError> {
+        self.source.try_seek(pos)?;
+        self.reset();
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use std::fmt::{self, Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::ops::{Deref, DerefMut};
+#[cfg(feature = "spill")]
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    slice,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[cfg(feature = "spill")]
+use memmap2::MmapMut;
+use serde::de::{SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[cfg(feature = "spill")]
+static SPILL_THRESHOLD: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Sets the number of samples above which decoded audio is spilled
+/// to a memory-mapped temporary file instead of being kept in memory
+///
+/// Spilling is disabled by default.
+#[cfg(feature = "spill")]
+pub fn set_spill_threshold(samples: Option<usize>) {
+    SPILL_THRESHOLD.store(samples.unwrap_or(usize::MAX), Ordering::Relaxed);
+}
+
+/// Interleaved samples, either in memory or spilled to disk
+pub(crate) enum Samples {
+    Memory(Vec<i32>),
+    #[cfg(feature = "spill")]
+    Mapped {
+        map: MmapMut,
+        len: usize,
+    },
+}
+
+impl Samples {
+    pub fn truncate(&mut self, len: usize) {
+        match self {
+            Samples::Memory(v) => v.truncate(len),
+            #[cfg(feature = "spill")]
+            Samples::Mapped { len: l, .. } => *l = (*l).min(len),
+        }
+    }
+    /// Removes the given number of samples from the start of the buffer
+    pub fn remove_start(&mut self, count: usize) {
+        match self {
+            Samples::Memory(v) => {
+                v.drain(..count);
+            }
+            #[cfg(feature = "spill")]
+            Samples::Mapped { .. } => {
+                let len = self.len();
+                self.copy_within(count.., 0);
+                self.truncate(len - count);
+            }
+        }
+    }
+}
+
+impl Deref for Samples {
+    type Target = [i32];
+
+    fn deref(&self) -> &[i32] {
+        match self {
+            Samples::Memory(v) => v,
+            #[cfg(feature = "spill")]
+            // SAFETY: the map is page aligned and holds at least `len` native endian samples
+            Samples::Mapped { map, len } => unsafe {
+                slice::from_raw_parts(map.as_ptr().cast(), *len)
+            },
+        }
+    }
+}
+impl DerefMut for Samples {
+    fn deref_mut(&mut self) -> &mut [i32] {
+        match self {
+            Samples::Memory(v) => v,
+            #[cfg(feature = "spill")]
+            // SAFETY: see `deref`
+            Samples::Mapped { map, len } => unsafe {
+                slice::from_raw_parts_mut(map.as_mut_ptr().cast(), *len)
+            },
+        }
+    }
+}
+
+impl From<Vec<i32>> for Samples {
+    fn from(v: Vec<i32>) -> Self {
+        Samples::Memory(v)
+    }
+}
+impl Default for Samples {
+    fn default() -> Self {
+        Samples::Memory(Vec::new())
+    }
+}
+impl Clone for Samples {
+    fn clone(&self) -> Self {
+        Samples::Memory(self.to_vec())
+    }
+}
+impl Debug for Samples {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+impl PartialEq for Samples {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+impl Eq for Samples {}
+impl Hash for Samples {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl IntoIterator for Samples {
+    type Item = i32;
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> IntoIter {
+        IntoIter {
+            samples: self,
+            position: 0,
+        }
+    }
+}
+pub(crate) struct IntoIter {
+    samples: Samples,
+    position: usize,
+}
+impl Iterator for IntoIter {
+    type Item = i32;
+
+    #[inline]
+    fn next(&mut self) -> Option<i32> {
+        let sample = self.samples.get(self.position).copied();
+        self.position += 1;
+        sample
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.samples.len().saturating_sub(self.position);
+        (remaining, Some(remaining))
+    }
+}
+
+impl Serialize for Samples {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+impl<'de> Deserialize<'de> for Samples {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SamplesVisitor;
+        impl<'de> Visitor<'de> for SamplesVisitor {
+            type Value = Samples;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str("a sequence of samples")
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Samples, A::Error> {
+                let mut builder = SamplesBuilder::new();
+                while let Some(s) = seq.next_element()? {
+                    builder.push(s).map_err(serde::de::Error::custom)?;
+                }
+                builder.finish().map_err(serde::de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_seq(SamplesVisitor)
+    }
+}
+
+/// Accumulates decoded samples, spilling them to a temporary file past the threshold
+pub(crate) struct SamplesBuilder {
+    samples: Vec<i32>,
+    #[cfg(feature = "spill")]
+    spilled: Option<(BufWriter<File>, usize)>,
+}
+
+impl SamplesBuilder {
+    pub fn new() -> Self {
+        Self {
+            samples: Vec::new(),
+            #[cfg(feature = "spill")]
+            spilled: None,
+        }
+    }
+
+    #[inline]
+    pub fn push(&mut self, sample: i32) -> io::Result<()> {
+        self.extend([sample])
+    }
+    pub fn extend<I: IntoIterator<Item = i32>>(&mut self, samples: I) -> io::Result<()> {
+        #[cfg(feature = "spill")]
+        if let Some((file, len)) = &mut self.spilled {
+            for s in samples {
+                file.write_all(&s.to_ne_bytes())?;
+                *len += 1;
+            }
+            return Ok(());
+        }
+
+        self.samples.extend(samples);
+
+        #[cfg(feature = "spill")]
+        if self.samples.len() > SPILL_THRESHOLD.load(Ordering::Relaxed) {
+            let mut file = BufWriter::new(tempfile::tempfile()?);
+            for s in &self.samples {
+                file.write_all(&s.to_ne_bytes())?;
+            }
+            self.spilled = Some((file, self.samples.len()));
+            self.samples = Vec::new();
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<Samples> {
+        #[cfg(feature = "spill")]
+        if let Some((file, len)) = self.spilled {
+            let file = file.into_inner().map_err(|e| e.into_error())?;
+            // SAFETY: the file is an unnamed temporary file that nothing else can access
+            let map = unsafe { MmapMut::map_mut(&file)? };
+            return Ok(Samples::Mapped { map, len });
+        }
+
+        Ok(Samples::Memory(self.samples))
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::BTreeMap;
+use std::mem;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::SampleFormat;
+
+/// Version of the descriptions below, incremented whenever the format changes
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema of LILAC files, which are a single JSON object
+///
+/// Metadata fields are missing or `null` when unknown,
+/// except for flags, which are `false`, and lists, which are missing when empty.
+/// Constraints that JSON Schema can't express, such as the sample count being a multiple
+/// of the channel count, are described by [`layout`].
+pub fn json_schema() -> Value {
+    let string = json!({ "type": ["string", "null"] });
+    let year = json!({ "type": ["integer", "null"], "minimum": i32::MIN, "maximum": i32::MAX });
+    let number = json!({ "type": ["integer", "null"], "minimum": 0, "maximum": u32::MAX });
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": format!("LILAC file, version {}", SCHEMA_VERSION),
+        "type": "object",
+        "required": ["channels", "sampleRate", "bitDepth", "samples"],
+        "properties": {
+            "title": string,
+            "artist": string,
+            "year": year,
+            "album": string,
+            "track": number,
+            "genre": {
+                "description": "A single string when there is only one genre",
+                "oneOf": [
+                    { "type": "null" },
+                    { "type": "string" },
+                    { "type": "array", "items": { "type": "string" } },
+                ],
+            },
+            "disc": number,
+            "albumArtist": string,
+            "compilation": { "type": "boolean", "default": false },
+            "composer": string,
+            "comment": string,
+            "originalYear": year,
+            "label": string,
+            "catalogNumber": string,
+            "rating": { "type": ["integer", "null"], "minimum": 1, "maximum": 5 },
+            "favorite": { "type": "boolean", "default": false },
+            "bookmarks": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "required": ["name", "position"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "position": {
+                            "type": "object",
+                            "required": ["secs", "nanos"],
+                            "properties": {
+                                "secs": { "type": "integer", "minimum": 0, "maximum": u64::MAX },
+                                "nanos": {
+                                    "type": "integer",
+                                    "minimum": 0,
+                                    "maximum": 999_999_999,
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "tags": {
+                "description": "Tags of the source file that don't map to any other field",
+                "type": "object",
+                "additionalProperties": { "type": "string" },
+            },
+            "sourceChecksum": { "type": ["integer", "null"], "minimum": 0, "maximum": u32::MAX },
+            "channels": { "type": "integer", "minimum": 1, "maximum": u16::MAX },
+            "sampleRate": { "type": "integer", "minimum": 1, "maximum": u32::MAX },
+            "bitDepth": { "type": "integer", "minimum": 1, "maximum": 32 },
+            "sampleFormat": {
+                "enum": [SampleFormat::Int, SampleFormat::Float],
+                "default": SampleFormat::default(),
+            },
+            "samples": {
+                "description": "Samples of every channel, interleaved frame by frame",
+                "type": "array",
+                "items": { "type": "integer", "minimum": i32::MIN, "maximum": i32::MAX },
+            },
+            "checksum": {
+                "description": "CRC-32 of the samples, missing in files written by older versions",
+                "type": "integer",
+                "minimum": 0,
+                "maximum": u32::MAX,
+            },
+        },
+    })
+}
+
+/// How the samples of a LILAC file are laid out, beyond what [`json_schema`] describes
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Layout {
+    pub version: u32,
+    /// Encoding of the whole file
+    pub container: &'static str,
+    pub samples: SampleLayout,
+    pub checksum: ChecksumLayout,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleLayout {
+    /// Order of the samples of different channels
+    pub interleaving: &'static str,
+    /// Requirement on the number of samples
+    pub count: &'static str,
+    /// Size of a sample, in the checksum and in memory
+    pub bits: usize,
+    /// How values are stored, by sample format
+    pub formats: BTreeMap<SampleFormat, &'static str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChecksumLayout {
+    /// Name of the checksum in the CRC catalogue
+    pub algorithm: &'static str,
+    /// Bytes the checksum is computed over
+    pub input: &'static str,
+    /// What readers do when it doesn't match
+    pub mismatch: &'static str,
+}
+
+/// Describes the layout of the samples and their checksum, as read and written by this crate
+pub fn layout() -> Layout {
+    Layout {
+        version: SCHEMA_VERSION,
+        container: "UTF-8 JSON object",
+        samples: SampleLayout {
+            interleaving: "frame by frame, each frame holding one sample per channel in order",
+            count: "a multiple of the channel count",
+            bits: mem::size_of::<i32>() * 8,
+            formats: [
+                (
+                    SampleFormat::Int,
+                    "signed integers within the range of the bit depth",
+                ),
+                (
+                    SampleFormat::Float,
+                    "bit patterns of 32-bit IEEE 754 floats from -1.0 to 1.0, \
+                     read as signed 32-bit integers, with a bit depth of 32",
+                ),
+            ]
+            .into(),
+        },
+        checksum: ChecksumLayout {
+            algorithm: "CRC-32/ISO-HDLC",
+            input: "every sample as 4 little-endian two's complement bytes, in order",
+            mismatch: "the file is rejected",
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{Fixture, Signal};
+
+    /// Checks a value against the subset of JSON Schema used by [`json_schema`]
+    fn check(schema: &Value, value: &Value, path: &str) -> Result<(), String> {
+        if let Some(types) = schema.get("type") {
+            let actual = match value {
+                Value::Null => "null",
+                Value::Bool(_) => "boolean",
+                Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+                Value::Number(_) => "number",
+                Value::String(_) => "string",
+                Value::Array(_) => "array",
+                Value::Object(_) => "object",
+            };
+            let allowed = match types {
+                Value::Array(types) => types.iter().any(|t| t == actual),
+                t => t == actual,
+            };
+            if !allowed {
+                return Err(format!("{} is {}, expected {}", path, actual, types));
+            }
+        }
+        if let (Some(n), Some(min)) = (value.as_f64(), schema.get("minimum")) {
+            if n < min.as_f64().unwrap() {
+                return Err(format!("{} is below {}", path, min));
+            }
+        }
+        if let (Some(n), Some(max)) = (value.as_f64(), schema.get("maximum")) {
+            if n > max.as_f64().unwrap() {
+                return Err(format!("{} is above {}", path, max));
+            }
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            if !values.contains(value) {
+                return Err(format!("{} isn't one of {:?}", path, values));
+            }
+        }
+        if let Some(schemas) = schema.get("oneOf").and_then(Value::as_array) {
+            let matching = schemas.iter().filter(|s| check(s, value, path).is_ok());
+            if matching.count() != 1 {
+                return Err(format!("{} doesn't match exactly one schema", path));
+            }
+        }
+
+        if let Value::Array(items) = value {
+            if let Some(schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(schema, item, &format!("{}[{}]", path, i))?;
+                }
+            }
+        }
+        if let Value::Object(fields) = value {
+            for field in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+            {
+                if !fields.contains_key(field.as_str().unwrap()) {
+                    return Err(format!("{} is missing {}", path, field));
+                }
+            }
+            for (name, field) in fields {
+                let schema = schema
+                    .get("properties")
+                    .and_then(|p| p.get(name))
+                    .or_else(|| schema.get("additionalProperties"))
+                    .ok_or_else(|| format!("{}.{} isn't in the schema", path, name))?;
+                check(schema, field, &format!("{}.{}", path, name))?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn tagged_fixture_matches_schema() {
+        let schema = json_schema();
+        for mut lilac in [
+            Fixture::new(Signal::Tone(440.0)).tagged().build(),
+            Fixture::new(Signal::Noise(1)).float().build(),
+        ] {
+            lilac.source_checksum = Some(lilac.checksum());
+            let mut file = Vec::new();
+            lilac.write(&mut file).unwrap();
+            let value: Value = serde_json::from_slice(&file).unwrap();
+
+            check(&schema, &value, "$").unwrap();
+        }
+    }
+
+    #[test]
+    fn null_metadata_matches_schema() {
+        let schema = json_schema();
+        let properties = schema["properties"].as_object().unwrap();
+        let defaults = serde_json::to_value(crate::Lilac::default()).unwrap();
+        for (name, value) in defaults.as_object().unwrap() {
+            if value.is_null() {
+                check(&properties[name], value, name).unwrap();
+            }
+        }
+        for name in ["genre", "sourceChecksum"] {
+            check(&properties[name], &Value::Null, name).unwrap();
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Helpers for tests of code reading and writing tracks
+//!
+//! Generated tracks are deterministic, so they can be compared against golden files
+//! and round-tripped through other formats with consistent tolerances.
+
+use std::f64::consts::TAU;
+use std::fmt;
+use std::time::Duration;
+
+use crate::dsp::Noise;
+use crate::{Bookmark, Lilac, SampleFormat};
+
+/// Signal generated by a [`Fixture`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Signal {
+    Silence,
+    /// Sine wave at the given frequency in hertz
+    Tone(f64),
+    /// White noise from the given seed
+    Noise(u64),
+    /// Sine wave going from one frequency to the other over the whole track
+    Sweep(f64, f64),
+}
+
+/// Builder of generated tracks
+///
+/// Defaults to one second of stereo 16-bit audio at 44.1 kHz, at half of full scale.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fixture {
+    signal: Signal,
+    duration: Duration,
+    channels: u16,
+    sample_rate: u32,
+    bit_depth: u32,
+    sample_format: SampleFormat,
+    amplitude: f64,
+    tagged: bool,
+}
+
+impl Fixture {
+    pub fn new(signal: Signal) -> Self {
+        Self {
+            signal,
+            duration: Duration::from_secs(1),
+            channels: 2,
+            sample_rate: 44_100,
+            bit_depth: 16,
+            sample_format: SampleFormat::Int,
+            amplitude: 0.5,
+            tagged: false,
+        }
+    }
+
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+    pub fn channels(mut self, channels: u16) -> Self {
+        self.channels = channels;
+        self
+    }
+    pub fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+    /// Integer samples of the given bit depth
+    pub fn bit_depth(mut self, bit_depth: u32) -> Self {
+        self.bit_depth = bit_depth;
+        self.sample_format = SampleFormat::Int;
+        self
+    }
+    /// 32-bit float samples
+    pub fn float(mut self) -> Self {
+        self.bit_depth = 32;
+        self.sample_format = SampleFormat::Float;
+        self
+    }
+    /// Peak level, relative to full scale
+    pub fn amplitude(mut self, amplitude: f64) -> Self {
+        self.amplitude = amplitude;
+        self
+    }
+    /// Fills every metadata field with a fixed value
+    ///
+    /// `source_checksum` is left unset, as it describes another file.
+    pub fn tagged(mut self) -> Self {
+        self.tagged = true;
+        self
+    }
+
+    pub fn build(&self) -> Lilac {
+        let frames = (self.duration.as_secs_f64() * self.sample_rate as f64) as usize;
+        let rate = self.sample_rate as f64;
+        let mut noise = match self.signal {
+            Signal::Noise(seed) => Noise::new(seed),
+            _ => Noise::new(0),
+        };
+
+        let mut phase = 0f64;
+        let mut samples = Vec::with_capacity(frames * self.channels as usize);
+        for i in 0..frames {
+            let value = match self.signal {
+                Signal::Silence => 0.0,
+                Signal::Tone(frequency) => (TAU * frequency * i as f64 / rate).sin(),
+                Signal::Noise(_) => noise.uniform() * 2.0 - 1.0,
+                Signal::Sweep(from, to) => {
+                    let frequency = from + (to - from) * i as f64 / frames.max(1) as f64;
+                    phase += TAU * frequency / rate;
+                    phase.sin()
+                }
+            } * self.amplitude;
+
+            let sample = match self.sample_format {
+                SampleFormat::Int => {
+                    let max = 2f64.powi(self.bit_depth as i32 - 1) - 1.0;
+                    (value * max).round() as i32
+                }
+                SampleFormat::Float => (value as f32).to_bits() as i32,
+            };
+            samples.extend((0..self.channels).map(|_| sample));
+        }
+
+        let mut lilac = Lilac {
+            channels: self.channels,
+            sample_rate: self.sample_rate,
+            bit_depth: self.bit_depth,
+            sample_format: self.sample_format,
+            samples: samples.into(),
+            ..Default::default()
+        };
+        if self.tagged {
+            lilac.title = Some("Fixture".to_owned());
+            lilac.artist = Some("Lilac".to_owned());
+            lilac.year = Some(2000);
+            lilac.album = Some("Tests".to_owned());
+            lilac.track = Some(1);
+            lilac.genres = vec!["Noise".to_owned()];
+            lilac.disc = Some(1);
+            lilac.album_artist = Some("Lilac".to_owned());
+            lilac.composer = Some("Lilac".to_owned());
+            lilac.comment = Some("Generated".to_owned());
+            lilac.compilation = true;
+            lilac.original_year = Some(1999);
+            lilac.label = Some("Lilac Records".to_owned());
+            lilac.catalog_number = Some("LILAC-001".to_owned());
+            lilac.rating = Some(5);
+            lilac.favorite = true;
+            lilac.bookmarks = vec![Bookmark {
+                name: "Middle".to_owned(),
+                position: self.duration / 2,
+            }];
+            lilac.tags = [("ENCODER".to_owned(), "lilac".to_owned())].into();
+        }
+        lilac
+    }
+}
+
+/// One second of a stereo 16-bit sine wave at the given frequency
+pub fn tone(frequency: f64) -> Lilac {
+    Fixture::new(Signal::Tone(frequency)).build()
+}
+/// One second of stereo 16-bit white noise from the given seed
+pub fn noise(seed: u64) -> Lilac {
+    Fixture::new(Signal::Noise(seed)).build()
+}
+
+/// How far the samples of a track are from the expected ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Difference {
+    /// Largest difference between two samples, relative to full scale
+    pub max: f32,
+    /// Number of samples that differ at all
+    pub differing: usize,
+}
+
+/// Reason two tracks can't be compared sample by sample
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Channels(u16, u16),
+    SampleRate(u32, u32),
+    Length(usize, usize),
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (name, expected, actual) = match *self {
+            Mismatch::Channels(e, a) => ("channel count", e as usize, a as usize),
+            Mismatch::SampleRate(e, a) => ("sample rate", e as usize, a as usize),
+            Mismatch::Length(e, a) => ("sample count", e, a),
+        };
+        write!(
+            f,
+            "{} differs, expected {} but got {}",
+            name, expected, actual
+        )
+    }
+}
+
+/// Compares the samples of two tracks, normalized so that bit depths and formats can differ
+pub fn compare(expected: &Lilac, actual: &Lilac) -> Result<Difference, Mismatch> {
+    if expected.channels != actual.channels {
+        return Err(Mismatch::Channels(expected.channels, actual.channels));
+    }
+    if expected.sample_rate != actual.sample_rate {
+        return Err(Mismatch::SampleRate(
+            expected.sample_rate,
+            actual.sample_rate,
+        ));
+    }
+    if expected.sample_count() != actual.sample_count() {
+        return Err(Mismatch::Length(
+            expected.sample_count(),
+            actual.sample_count(),
+        ));
+    }
+
+    let (e, a) = (expected.normalizer(), actual.normalizer());
+    let mut difference = Difference {
+        max: 0.0,
+        differing: 0,
+    };
+    for (&x, &y) in expected.samples.iter().zip(actual.samples.iter()) {
+        let d = (e(x) - a(y)).abs();
+        if d > 0.0 {
+            difference.max = difference.max.max(d);
+            difference.differing += 1;
+        }
+    }
+    Ok(difference)
+}
+
+/// Panics unless both tracks have the same layout and samples within the tolerance,
+/// relative to full scale
+///
+/// Use 0 for lossless round-trips, and about `2^(1 - bits)` when the bit depth is reduced.
+#[track_caller]
+pub fn assert_similar(expected: &Lilac, actual: &Lilac, tolerance: f32) {
+    match compare(expected, actual) {
+        Ok(d) if d.max <= tolerance => {}
+        Ok(d) => panic!(
+            "{} samples differ, by up to {} when the tolerance is {}",
+            d.differing, d.max, tolerance
+        ),
+        Err(mismatch) => panic!("{}", mismatch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lilac_round_trip() {
+        for fixture in [
+            Fixture::new(Signal::Sweep(20.0, 20_000.0)).tagged(),
+            Fixture::new(Signal::Noise(1)).bit_depth(24),
+            Fixture::new(Signal::Tone(440.0)).float(),
+        ] {
+            let expected = fixture.build();
+            let mut file = Vec::new();
+            expected.write(&mut file).unwrap();
+            let actual = Lilac::read(file.as_slice()).unwrap();
+
+            assert_similar(&expected, &actual, 0.0);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[cfg(feature = "wav")]
+    #[test]
+    fn wav_round_trip() {
+        use std::io::Cursor;
+
+        for fixture in [
+            Fixture::new(Signal::Sweep(20.0, 20_000.0)),
+            Fixture::new(Signal::Noise(1)).bit_depth(24),
+            Fixture::new(Signal::Tone(440.0)).float(),
+        ] {
+            let expected = fixture.build();
+            let mut file = Cursor::new(Vec::new());
+            expected.to_wav(&mut file).unwrap();
+            file.set_position(0);
+            let actual = Lilac::from_wav(file).unwrap();
+
+            assert_similar(&expected, &actual, 0.0);
+        }
+    }
+}